@@ -1,12 +1,58 @@
-use regex::Regex;
-use validator::ValidateEmail;
+use pubsub::validation::{
+    validate_email_addr, validate_password, validate_room_name, validate_username,
+};
 
 use crate::common::{
-    print_password_rule, print_user_name_rule, print_warning_error_msg, CHAT_ROOM_CMD,
-    CHECK_USER_STATUS_CMD, EXIT_CMD, HELP_CMD, JOIN_CHAT_ROOM_CMD, LIST_CHAT_ROOMS_CMD,
-    LIST_RECIPIENTS_CMD, LIST_USERS_CMD, LOGIN_CMD, LOGOUT_CMD, PRIVATE_CHAT_CMD, RESUME_CHAT_CMD,
-    SIGNUP_CMD,
+    print_password_rule, print_user_name_rule, print_warning_error_msg, ACCEPT_INVITE_CMD, BAN_CMD,
+    BLOCK_CMD, CHAT_ROOM_CMD, CHECK_USER_STATUS_CMD, DECLINE_INVITE_CMD, DELETE_ROOM_CMD,
+    DEMOTE_CMD, EXIT_CMD, EXPORT_CMD, FAILED_LOGINS_CMD, GROUP_ADD_CMD, GROUP_REMOVE_CMD, HELP_CMD,
+    IMPORT_CMD, INVITE_CMD, JOIN_CHAT_ROOM_CMD, JUMP_CMD, KICK_CMD, LIST_BLOCKED_CMD,
+    LIST_CHAT_ROOMS_CMD, LIST_GROUPS_CMD, LIST_INVITES_CMD, LIST_MEMBERS_CMD, LIST_RECIPIENTS_CMD,
+    LIST_USERS_CMD, LOGIN_CMD, LOGOUT_CMD, MENTIONS_CMD, MUTE_CMD, PASSWD_CMD, PRIVATE_CHAT_CMD,
+    PROMOTE_CMD, RENAME_ROOM_CMD, RESET_PASSWORD_CMD, RESUME_CHAT_CMD, ROOM_INFO_CMD, SEARCH_CMD,
+    SET_DESCRIPTION_CMD, SET_TOPIC_CMD, SIGNUP_CMD, UNBAN_CMD, UNBLOCK_CMD, UNMUTE_CMD, VERIFY_CMD,
 };
+use crate::user::{ConversationExport, ConversationImport, MessageSearch};
+
+#[derive(Debug, Clone, Copy)]
+pub enum RoomAction {
+    Promote,
+    Demote,
+    Kick,
+    Ban,
+    Unban,
+    Invite,
+    Mute,
+    Unmute,
+}
+
+impl RoomAction {
+    pub fn endpoint(&self) -> &'static str {
+        match self {
+            RoomAction::Promote => "promote",
+            RoomAction::Demote => "demote",
+            RoomAction::Kick => "kick",
+            RoomAction::Ban => "ban",
+            RoomAction::Unban => "unban",
+            RoomAction::Invite => "invite",
+            RoomAction::Mute => "mute",
+            RoomAction::Unmute => "unmute",
+        }
+    }
+
+    pub fn past_tense(&self) -> &'static str {
+        match self {
+            RoomAction::Promote => "promoted",
+            RoomAction::Demote => "demoted",
+            RoomAction::Kick => "kicked",
+            RoomAction::Ban => "banned",
+            RoomAction::Unban => "unbanned",
+            RoomAction::Invite => "invited",
+            RoomAction::Mute => "muted",
+            RoomAction::Unmute => "unmuted",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum Command {
@@ -15,11 +61,30 @@ pub enum Command {
         email: String,
         password: String,
     },
+    Verify {
+        username: String,
+        code: String,
+    },
+    RequestVerificationCode {
+        username: String,
+    },
     Login {
         username: String,
         password: String,
     },
     Logout,
+    ChangePassword {
+        old_password: String,
+        new_password: String,
+    },
+    RequestPasswordReset {
+        username: String,
+    },
+    ResetPassword {
+        username: String,
+        token: String,
+        new_password: String,
+    },
     ListUsers,
     CheckUserStatus {
         username: String, // the user that we want to check
@@ -27,34 +92,108 @@ pub enum Command {
     CreatePrivateChat {
         with_user: String,
     },
+    CreateGroupChat {
+        with_users: Vec<String>,
+    },
+    UpdateGroupChat {
+        chat_id: String,
+        target: String,
+        add: bool,
+    },
+    ListGroupChats,
     ResumeChat {
         with_user: String,
     },
+    JumpToUnread {
+        number: usize,
+    },
     CreateChatRoom {
-        name: String
+        name: String,
+        visibility: Option<String>,
+        password: Option<String>,
     },
     JoinChatRoom {
-        chat_id: String,
+        room: String, // room name, name prefix or id
+        password: Option<String>,
     },
     ListAllChatRooms,
+    ListRoomMembers {
+        room_id: String,
+    },
+    ModerateRoom {
+        action: RoomAction,
+        room_id: String,
+        target: String,
+    },
+    ShowRoomInfo {
+        room_id: String,
+    },
+    RenameRoom {
+        room_id: String,
+        name: String,
+    },
+    UpdateRoomDetails {
+        room_id: String,
+        topic: Option<String>,
+        description: Option<String>,
+    },
+    DeleteRoom {
+        room_id: String,
+    },
+    ListInvites,
+    RespondToInvite {
+        room_id: String,
+        accept: bool,
+    },
+    BlockUser {
+        target: String,
+        block: bool,
+    },
+    ListBlockedUsers,
+    ListMentions,
+    Search(MessageSearch),
+    Export(ConversationExport),
+    Import(ConversationImport),
     ListAllRecipients,
+    ListFailedLogins {
+        username: Option<String>,
+    },
     Help,
     Quit,
 }
 
 pub fn parse_command(input: &str) -> Option<Command> {
-    let input_list: Vec<&str> = input.trim().split_whitespace().collect();
+    let input_list: Vec<&str> = input.split_whitespace().collect();
     match input_list.as_slice() {
         [SIGNUP_CMD, username, email, password] => Some(Command::Signup {
             username: (username.to_string()),
             email: (email.to_string()),
             password: (password.to_string()),
         }),
+        [VERIFY_CMD, username, code] => Some(Command::Verify {
+            username: (username.to_string()),
+            code: (code.to_string()),
+        }),
+        [VERIFY_CMD, username] => Some(Command::RequestVerificationCode {
+            username: (username.to_string()),
+        }),
         [LOGIN_CMD, username, password] => Some(Command::Login {
             username: (username.to_string()),
             password: (password.to_string()),
         }),
         [LOGOUT_CMD] => Some(Command::Logout),
+        [PASSWD_CMD, old_password, new_password] => Some(Command::ChangePassword {
+            old_password: (old_password.to_string()),
+            new_password: (new_password.to_string()),
+        }),
+        [RESET_PASSWORD_CMD, username] => Some(Command::RequestPasswordReset {
+            username: (username.to_string()),
+        }),
+        [RESET_PASSWORD_CMD, username, token, new_password] => Some(Command::ResetPassword {
+            username: (username.to_string()),
+            token: (token.to_string()),
+            new_password: (new_password.to_string()),
+        }),
         [LIST_USERS_CMD] => Some(Command::ListUsers),
         [CHECK_USER_STATUS_CMD, username] => Some(Command::CheckUserStatus {
             username: (username.to_string()),
@@ -62,75 +201,231 @@ pub fn parse_command(input: &str) -> Option<Command> {
         [PRIVATE_CHAT_CMD, with_user] => Some(Command::CreatePrivateChat {
             with_user: (with_user.to_string()),
         }),
+        [PRIVATE_CHAT_CMD, with_users @ ..] if with_users.len() > 1 => {
+            Some(Command::CreateGroupChat {
+                with_users: with_users.iter().map(|u| u.to_string()).collect(),
+            })
+        }
+        [GROUP_ADD_CMD, chat_id, target] => Some(Command::UpdateGroupChat {
+            chat_id: chat_id.to_string(),
+            target: target.to_string(),
+            add: true,
+        }),
+        [GROUP_REMOVE_CMD, chat_id, target] => Some(Command::UpdateGroupChat {
+            chat_id: chat_id.to_string(),
+            target: target.to_string(),
+            add: false,
+        }),
+        [LIST_GROUPS_CMD] => Some(Command::ListGroupChats),
         [RESUME_CHAT_CMD, with_user] => Some(Command::ResumeChat {
             with_user: (with_user.to_string()),
         }),
-        [CHAT_ROOM_CMD, name] => {
-            Some(Command::CreateChatRoom {
-                name: (name.to_string())
+        [JUMP_CMD, number] => number
+            .parse()
+            .ok()
+            .map(|number| Command::JumpToUnread { number }),
+        [CHAT_ROOM_CMD, name] => Some(Command::CreateChatRoom {
+            name: (name.to_string()),
+            visibility: None,
+            password: None,
+        }),
+        [CHAT_ROOM_CMD, name, visibility] => Some(Command::CreateChatRoom {
+            name: (name.to_string()),
+            visibility: Some(visibility.to_string()),
+            password: None,
+        }),
+        [CHAT_ROOM_CMD, name, visibility, password] => Some(Command::CreateChatRoom {
+            name: (name.to_string()),
+            visibility: Some(visibility.to_string()),
+            password: Some(password.to_string()),
+        }),
+        [JOIN_CHAT_ROOM_CMD, room] => Some(Command::JoinChatRoom {
+            room: room.to_string(),
+            password: None,
+        }),
+        [JOIN_CHAT_ROOM_CMD, room, password] => Some(Command::JoinChatRoom {
+            room: room.to_string(),
+            password: Some(password.to_string()),
+        }),
+        [LIST_CHAT_ROOMS_CMD] => Some(Command::ListAllChatRooms),
+        [LIST_MEMBERS_CMD, room_id] => Some(Command::ListRoomMembers {
+            room_id: room_id.to_string(),
+        }),
+        [action @ (PROMOTE_CMD | DEMOTE_CMD | KICK_CMD | BAN_CMD | UNBAN_CMD | INVITE_CMD
+        | MUTE_CMD | UNMUTE_CMD), room_id, target] => {
+            let action = match *action {
+                PROMOTE_CMD => RoomAction::Promote,
+                DEMOTE_CMD => RoomAction::Demote,
+                KICK_CMD => RoomAction::Kick,
+                BAN_CMD => RoomAction::Ban,
+                UNBAN_CMD => RoomAction::Unban,
+                MUTE_CMD => RoomAction::Mute,
+                UNMUTE_CMD => RoomAction::Unmute,
+                _ => RoomAction::Invite,
+            };
+            Some(Command::ModerateRoom {
+                action,
+                room_id: room_id.to_string(),
+                target: target.to_string(),
             })
         }
-        [JOIN_CHAT_ROOM_CMD, chat_id] => Some(Command::JoinChatRoom {
-            chat_id: chat_id.to_string(),
+        [ROOM_INFO_CMD, room_id] => Some(Command::ShowRoomInfo {
+            room_id: room_id.to_string(),
         }),
-        [LIST_CHAT_ROOMS_CMD] => Some(Command::ListAllChatRooms),
+        [RENAME_ROOM_CMD, room_id, name] => Some(Command::RenameRoom {
+            room_id: room_id.to_string(),
+            name: name.to_string(),
+        }),
+        // The topic and description are free text; leaving them out clears them.
+        [SET_TOPIC_CMD, room_id, topic @ ..] => Some(Command::UpdateRoomDetails {
+            room_id: room_id.to_string(),
+            topic: Some(topic.join(" ")),
+            description: None,
+        }),
+        [SET_DESCRIPTION_CMD, room_id, description @ ..] => Some(Command::UpdateRoomDetails {
+            room_id: room_id.to_string(),
+            topic: None,
+            description: Some(description.join(" ")),
+        }),
+        [DELETE_ROOM_CMD, room_id] => Some(Command::DeleteRoom {
+            room_id: room_id.to_string(),
+        }),
+        [LIST_INVITES_CMD] => Some(Command::ListInvites),
+        [ACCEPT_INVITE_CMD, room_id] => Some(Command::RespondToInvite {
+            room_id: room_id.to_string(),
+            accept: true,
+        }),
+        [DECLINE_INVITE_CMD, room_id] => Some(Command::RespondToInvite {
+            room_id: room_id.to_string(),
+            accept: false,
+        }),
+        [BLOCK_CMD, target] => Some(Command::BlockUser {
+            target: target.to_string(),
+            block: true,
+        }),
+        [UNBLOCK_CMD, target] => Some(Command::BlockUser {
+            target: target.to_string(),
+            block: false,
+        }),
+        [LIST_BLOCKED_CMD] => Some(Command::ListBlockedUsers),
+        [MENTIONS_CMD] => Some(Command::ListMentions),
+        [SEARCH_CMD, args @ ..] if !args.is_empty() => parse_search(args).map(Command::Search),
+        [EXPORT_CMD, target, args @ ..] => parse_export(target, args).map(Command::Export),
+        [IMPORT_CMD, path, args @ ..] => parse_import(path, args).map(Command::Import),
         [LIST_RECIPIENTS_CMD] => Some(Command::ListAllRecipients),
+        [FAILED_LOGINS_CMD] => Some(Command::ListFailedLogins { username: None }),
+        [FAILED_LOGINS_CMD, username] => Some(Command::ListFailedLogins {
+            username: Some(username.to_string()),
+        }),
         [HELP_CMD] => Some(Command::Help),
         [EXIT_CMD] => Some(Command::Quit),
         _ => None,
     }
 }
 
-pub fn is_valid_email_addr(email: &str) -> bool {
-    let res = email.validate_email();
-    if !res {
-        println!("Error: Invalid email address.");
+// Words are searched for; `--from`, `--since` and `--until` each take a value.
+fn parse_search(args: &[&str]) -> Option<MessageSearch> {
+    let mut search = MessageSearch {
+        text: None,
+        sender: None,
+        since: None,
+        until: None,
+    };
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = match *arg {
+            "--from" => &mut search.sender,
+            "--since" => &mut search.since,
+            "--until" => &mut search.until,
+            word => {
+                words.push(word);
+                continue;
+            }
+        };
+        *value = Some(args.next()?.to_string());
     }
-    res
+    if !words.is_empty() {
+        search.text = Some(words.join(" "));
+    }
+    Some(search)
 }
 
-pub fn is_valid_username(username: &str) -> bool {
-    let re = Regex::new(r"^[a-zA-Z][a-zA-Z0-9._]{4,19}[a-zA-Z0-9]$").unwrap();
-    let res = re.is_match(username);
-
-    if !res {
-        print_warning_error_msg("Error: Invalid username.");
-        print_user_name_rule();
+// `--format`, `--since` and `--out` each take a value.
+fn parse_export(target: &str, args: &[&str]) -> Option<ConversationExport> {
+    let mut export = ConversationExport {
+        target: target.to_string(),
+        format: String::from("txt"),
+        since: None,
+        output: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next()?.to_string();
+        match *arg {
+            "--format" => export.format = value,
+            "--since" => export.since = Some(value),
+            "--out" => export.output = Some(value),
+            _ => return None,
+        }
     }
-    res
+    Some(export)
 }
 
-pub fn is_valid_password(password: &str) -> bool {
-    // 6 - 16
-    if password.len() < 6 || password.len() > 16 {
-        print_warning_error_msg("Error: Invalid password.");
-        print_password_rule();
-        return false;
+fn parse_import(path: &str, args: &[&str]) -> Option<ConversationImport> {
+    let mut import = ConversationImport {
+        path: path.to_string(),
+        create_users: false,
+        create_conversation: false,
+    };
+    for arg in args {
+        match *arg {
+            "--create-users" => import.create_users = true,
+            "--create-conversation" => import.create_conversation = true,
+            _ => return None,
+        }
     }
+    Some(import)
+}
 
-    // at least one digit
-    let has_digit = Regex::new(r"[0-9]").unwrap();
-    if !has_digit.is_match(password) {
-        print_warning_error_msg("Error: Invalid password.");
-        print_password_rule();
-        return false;
+pub fn is_valid_email_addr(email: &str) -> bool {
+    match validate_email_addr(email) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("Error: {}", e);
+            false
+        }
     }
+}
 
-    // at least one special character
-    let has_special = Regex::new(r"[!@#$%^&*]").unwrap();
-    if !has_special.is_match(password) {
-        print_warning_error_msg("Error: Invalid password.");
-        print_password_rule();
-        return false;
+pub fn is_valid_username(username: &str) -> bool {
+    match validate_username(username) {
+        Ok(()) => true,
+        Err(_) => {
+            print_warning_error_msg("Error: Invalid username.");
+            print_user_name_rule();
+            false
+        }
     }
+}
 
-    // all characters are valid
-    let valid_chars = Regex::new(r"^[a-zA-Z0-9!@#$%^&*]+$").unwrap();
-    if !valid_chars.is_match(password) {
-        print_warning_error_msg("Error: Invalid password.");
-        print_password_rule();
-        return false;
+pub fn is_valid_password(password: &str) -> bool {
+    match validate_password(password) {
+        Ok(()) => true,
+        Err(_) => {
+            print_warning_error_msg("Error: Invalid password.");
+            print_password_rule();
+            false
+        }
     }
+}
 
-    true
+pub fn is_valid_room_name(name: &str) -> bool {
+    match validate_room_name(name) {
+        Ok(()) => true,
+        Err(e) => {
+            print_warning_error_msg(&format!("Error: {}", e));
+            false
+        }
+    }
 }
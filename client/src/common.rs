@@ -1,16 +1,54 @@
 use colored::Colorize;
+use reqwest::{Certificate, Client};
+use std::env;
+use std::error::Error;
+use std::fs;
+
+pub const DEFAULT_SERVER_URL: &str = "http://localhost:8000";
 
 pub const SIGNUP_CMD: &str = "signup";
+pub const VERIFY_CMD: &str = "verify";
 pub const LOGIN_CMD: &str = "login";
 pub const LOGOUT_CMD: &str = "logout";
+pub const PASSWD_CMD: &str = "passwd";
+pub const RESET_PASSWORD_CMD: &str = "reset-password";
 pub const LIST_USERS_CMD: &str = "list-users";
 pub const CHECK_USER_STATUS_CMD: &str = "check";
 pub const PRIVATE_CHAT_CMD: &str = "private-chat";
+pub const GROUP_ADD_CMD: &str = "group-add";
+pub const GROUP_REMOVE_CMD: &str = "group-remove";
+pub const LIST_GROUPS_CMD: &str = "list-groups";
 pub const RESUME_CHAT_CMD: &str = "resume-chat";
 pub const CHAT_ROOM_CMD: &str = "chat-room";
 pub const LIST_CHAT_ROOMS_CMD: &str = "list-chat-rooms";
 pub const JOIN_CHAT_ROOM_CMD: &str = "join-chat-room";
 pub const LIST_RECIPIENTS_CMD: &str = "list-recipients";
+pub const LIST_MEMBERS_CMD: &str = "list-members";
+pub const PROMOTE_CMD: &str = "promote";
+pub const DEMOTE_CMD: &str = "demote";
+pub const KICK_CMD: &str = "kick";
+pub const BAN_CMD: &str = "ban";
+pub const UNBAN_CMD: &str = "unban";
+pub const ROOM_INFO_CMD: &str = "room-info";
+pub const RENAME_ROOM_CMD: &str = "rename-room";
+pub const SET_TOPIC_CMD: &str = "set-topic";
+pub const SET_DESCRIPTION_CMD: &str = "set-description";
+pub const DELETE_ROOM_CMD: &str = "delete-room";
+pub const INVITE_CMD: &str = "invite";
+pub const LIST_INVITES_CMD: &str = "list-invites";
+pub const ACCEPT_INVITE_CMD: &str = "accept-invite";
+pub const DECLINE_INVITE_CMD: &str = "decline-invite";
+pub const MUTE_CMD: &str = "mute";
+pub const UNMUTE_CMD: &str = "unmute";
+pub const BLOCK_CMD: &str = "block";
+pub const UNBLOCK_CMD: &str = "unblock";
+pub const LIST_BLOCKED_CMD: &str = "list-blocked";
+pub const FAILED_LOGINS_CMD: &str = "failed-logins";
+pub const JUMP_CMD: &str = "jump";
+pub const MENTIONS_CMD: &str = "mentions";
+pub const SEARCH_CMD: &str = "search";
+pub const EXPORT_CMD: &str = "export";
+pub const IMPORT_CMD: &str = "import";
 pub const HELP_CMD: &str = "help";
 pub const EXIT_CMD: &str = "exit";
 
@@ -49,11 +87,31 @@ pub fn print_help_msg_by_default() {
         SIGNUP_CMD.cyan(),
         "[username] [email] [password]".cyan()
     );
+    println!(
+        "Verify email: {} {}",
+        VERIFY_CMD.cyan(),
+        "[username] [code]".cyan()
+    );
+    println!(
+        "Request a new verification code: {} {}",
+        VERIFY_CMD.cyan(),
+        "[username]".cyan()
+    );
     println!(
         "Login: {} {}",
         LOGIN_CMD.cyan(),
         "[username] [password]".cyan()
     );
+    println!(
+        "Request a password reset token: {} {}",
+        RESET_PASSWORD_CMD.cyan(),
+        "[username]".cyan()
+    );
+    println!(
+        "Reset password with a token: {} {}",
+        RESET_PASSWORD_CMD.cyan(),
+        "[username] [token] [new_password]".cyan()
+    );
     println!("Exit: {}", EXIT_CMD.cyan());
 
     print_user_name_rule();
@@ -64,6 +122,11 @@ pub fn print_help_msg_after_login() {
     println!("{}", "Commands:".cyan());
     println!("Show help message: {}", HELP_CMD.cyan());
     println!("Logout: {}", LOGOUT_CMD.cyan());
+    println!(
+        "Change password: {} {}",
+        PASSWD_CMD.cyan(),
+        "[old_password] [new_password]".cyan()
+    );
     println!("List all the users: {}", LIST_USERS_CMD.cyan());
     println!(
         "Check the status based on username: {} {}",
@@ -71,10 +134,21 @@ pub fn print_help_msg_after_login() {
         "[username]".cyan()
     );
     println!(
-        "Create private chat with a user: {} {}",
+        "Create private chat with a user, or a group chat with several: {} {}",
         PRIVATE_CHAT_CMD.cyan(),
-        "[with_user_name]".cyan()
+        "[with_user_name] [more users (optional)...]".cyan()
     );
+    println!(
+        "Add someone to a group chat: {} {}",
+        GROUP_ADD_CMD.cyan(),
+        "[chat_id] [username]".cyan()
+    );
+    println!(
+        "Remove someone from a group chat: {} {}",
+        GROUP_REMOVE_CMD.cyan(),
+        "[chat_id] [username]".cyan()
+    );
+    println!("List your group chats: {}", LIST_GROUPS_CMD.cyan());
     println!(
         "List all the private chat recipients: {}",
         LIST_RECIPIENTS_CMD.cyan()
@@ -84,17 +158,138 @@ pub fn print_help_msg_after_login() {
         RESUME_CHAT_CMD.cyan(),
         "[recipient]".cyan()
     );
+    println!(
+        "Open a conversation from the unread summary shown at login: {} {}",
+        JUMP_CMD.cyan(),
+        "[number]".cyan()
+    );
     println!(
         "Create chat room with a list of users: {} {}",
         CHAT_ROOM_CMD.cyan(),
-        "[group_name]".cyan()
+        "[group_name] [public|private|password (optional)] [password (optional)]".cyan()
     );
     println!(
-        "Join an existing chat room: {} {}",
+        "Join an existing chat room by name, name prefix or id (press Tab to complete names): {} {}",
         JOIN_CHAT_ROOM_CMD.cyan(),
-        "[id]".cyan()
+        "[name|id] [password (optional)]".cyan()
     );
     println!("List existing chat rooms: {}", LIST_CHAT_ROOMS_CMD.cyan());
+    println!(
+        "List the members of a chat room and their roles: {} {}",
+        LIST_MEMBERS_CMD.cyan(),
+        "[room_id]".cyan()
+    );
+    println!(
+        "Make a member a moderator (owner only): {} {}",
+        PROMOTE_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!(
+        "Make a moderator a regular member (owner only): {} {}",
+        DEMOTE_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!(
+        "Remove a member from a chat room: {} {}",
+        KICK_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!(
+        "Ban a user from a chat room: {} {}",
+        BAN_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!(
+        "Lift a ban: {} {}",
+        UNBAN_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!(
+        "Show the name, topic and description of a chat room: {} {}",
+        ROOM_INFO_CMD.cyan(),
+        "[room_id]".cyan()
+    );
+    println!(
+        "Rename a chat room (owner only): {} {}",
+        RENAME_ROOM_CMD.cyan(),
+        "[room_id] [new_name]".cyan()
+    );
+    println!(
+        "Set or clear the topic of a chat room (owner or moderator): {} {}",
+        SET_TOPIC_CMD.cyan(),
+        "[room_id] [topic (optional)]".cyan()
+    );
+    println!(
+        "Set or clear the description of a chat room (owner or moderator): {} {}",
+        SET_DESCRIPTION_CMD.cyan(),
+        "[room_id] [description (optional)]".cyan()
+    );
+    println!(
+        "Delete a chat room and its messages (owner only): {} {}",
+        DELETE_ROOM_CMD.cyan(),
+        "[room_id]".cyan()
+    );
+    println!(
+        "Invite a user to a chat room: {} {}",
+        INVITE_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!("List your pending invites: {}", LIST_INVITES_CMD.cyan());
+    println!(
+        "Accept an invite: {} {}",
+        ACCEPT_INVITE_CMD.cyan(),
+        "[room_id]".cyan()
+    );
+    println!(
+        "Decline an invite: {} {}",
+        DECLINE_INVITE_CMD.cyan(),
+        "[room_id]".cyan()
+    );
+    println!(
+        "Hide a user's messages in a chat room from you: {} {}",
+        MUTE_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!(
+        "Show a muted user's messages again: {} {}",
+        UNMUTE_CMD.cyan(),
+        "[room_id] [username]".cyan()
+    );
+    println!(
+        "Block a user from starting private chats with you and hide their messages: {} {}",
+        BLOCK_CMD.cyan(),
+        "[username]".cyan()
+    );
+    println!(
+        "Unblock a user: {} {}",
+        UNBLOCK_CMD.cyan(),
+        "[username]".cyan()
+    );
+    println!("List the users you blocked: {}", LIST_BLOCKED_CMD.cyan());
+    println!(
+        "List recent messages mentioning you: {}",
+        MENTIONS_CMD.cyan()
+    );
+    println!(
+        "Search the messages of your conversations: {} {}",
+        SEARCH_CMD.cyan(),
+        "[words (optional)] [--from username] [--since YYYY-MM-DD] [--until YYYY-MM-DD]".cyan()
+    );
+    println!(
+        "Save the history of a conversation to a file: {} {}",
+        EXPORT_CMD.cyan(),
+        "[user|group_id|room] [--format json|md|txt] [--since YYYY-MM-DD] [--out file]".cyan()
+    );
+    println!(
+        "List failed login attempts (admin only): {} {}",
+        FAILED_LOGINS_CMD.cyan(),
+        "[username (optional)]".cyan()
+    );
+    println!(
+        "Load a JSON export into its conversation (admin only): {} {}",
+        IMPORT_CMD.cyan(),
+        "[file] [--create-users] [--create-conversation]".cyan()
+    );
     println!("Exit: {}", EXIT_CMD.cyan());
 }
 
@@ -114,3 +309,24 @@ pub fn print_session_exists_error_msg() {
 pub fn print_session_not_exist_error_msg() {
     print_warning_error_msg("Please login first!");
 }
+
+/// Full URL of a REST endpoint, on the server given by CHATAPP_SERVER_URL
+/// (e.g. https://localhost:8000).
+pub fn server_url(path: &str) -> String {
+    let base = env::var("CHATAPP_SERVER_URL").unwrap_or_else(|_| DEFAULT_SERVER_URL.to_string());
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
+
+/// HTTP client for the REST server. When CHATAPP_CA_PATH is set, the CA
+/// certificates in that PEM bundle are trusted as well, which is needed for
+/// self-signed server certificates.
+pub fn build_http_client() -> Result<Client, Box<dyn Error>> {
+    let mut builder = Client::builder();
+    if let Ok(ca_path) = env::var("CHATAPP_CA_PATH") {
+        let pem = fs::read(&ca_path)?;
+        for cert in Certificate::from_pem_bundle(&pem)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    Ok(builder.build()?)
+}
@@ -0,0 +1,57 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::{Context, Helper};
+use std::sync::{Arc, Mutex};
+
+use crate::common::JOIN_CHAT_ROOM_CMD;
+
+/// Completes chat room names after `join-chat-room`. The names come from a
+/// cache that is refreshed whenever the client fetches the room list.
+pub struct RoomNameCompleter {
+    room_names: Arc<Mutex<Vec<String>>>,
+}
+
+impl RoomNameCompleter {
+    pub fn new(room_names: Arc<Mutex<Vec<String>>>) -> RoomNameCompleter {
+        RoomNameCompleter { room_names }
+    }
+}
+
+impl Completer for RoomNameCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let prefix = format!("{} ", JOIN_CHAT_ROOM_CMD);
+        let Some(partial) = line.strip_prefix(&prefix) else {
+            return Ok((pos, vec![]));
+        };
+        // Only the room argument is completed, not the password after it.
+        if partial.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+
+        let partial = partial.to_lowercase();
+        let room_names = self.room_names.lock().unwrap();
+        let candidates = room_names
+            .iter()
+            // Names with spaces cannot be typed as a single argument.
+            .filter(|name| !name.contains(char::is_whitespace))
+            .filter(|name| name.to_lowercase().starts_with(&partial))
+            .cloned()
+            .collect();
+        Ok((prefix.len(), candidates))
+    }
+}
+
+impl Hinter for RoomNameCompleter {}
+
+impl Highlighter for RoomNameCompleter {}
+
+impl Helper for RoomNameCompleter {}
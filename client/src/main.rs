@@ -1,28 +1,38 @@
 mod commands;
 mod common;
+mod completion;
 mod user;
 
-use commands::{is_valid_email_addr, is_valid_password, is_valid_username, Command};
+use commands::{
+    is_valid_email_addr, is_valid_password, is_valid_room_name, is_valid_username, Command,
+};
 use common::{
-    print_help_msg_after_login, print_help_msg_by_default, print_msg,
+    build_http_client, print_help_msg_after_login, print_help_msg_by_default, print_msg,
     print_session_exists_error_msg, print_session_not_exist_error_msg, print_warning_error_msg,
 };
+use completion::RoomNameCompleter;
 use pubsub::client::PubSubClient;
-use reqwest::Client;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use tokio::sync::Mutex;
 use user::User;
 
+// Most unread messages shown when jumping into a conversation.
+const MAX_CATCH_UP_MESSAGES: i64 = 50;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     print_msg("Welcome to the real time chat app!");
     print_msg("Type 'help' to see available commands.");
     // client, input
     let history_file = "history.txt";
-    let mut rl = rustyline::Editor::<()>::new();
+    let mut rl = rustyline::Editor::<RoomNameCompleter>::new();
     let _ = rl.load_history(history_file);
+    // Room names offered by tab completion, refreshed whenever rooms are listed.
+    let room_names = Arc::new(std::sync::Mutex::new(Vec::new()));
+    rl.set_helper(Some(RoomNameCompleter::new(room_names.clone())));
 
     let mut current_mode = "main";
-    let client = Client::new();
+    let client = build_http_client()?;
     let mut user = User::new();
     let mut prompt = String::from(">> ");
     let mut pubsub_client: Option<Arc<Mutex<PubSubClient>>> = None;
@@ -31,6 +41,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     loop {
         match current_mode {
             "main" => {
+                if let Some(ps_client) = &pubsub_client {
+                    ps_client.lock().await.show_notifications();
+                }
                 let readline = rl.readline(&prompt);
                 match readline {
                     Err(_) => {
@@ -40,7 +53,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                     Ok(input) => {
                         rl.add_history_entry(input.clone());
-                        
+
                         match commands::parse_command(&input) {
                             Some(Command::Help) => {
                                 if user.session_exists() {
@@ -76,6 +89,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 )
                                 .await?;
                             }
+                            Some(Command::Verify { username, code }) => {
+                                // check whether session exists
+                                if user.session_exists() {
+                                    print_session_exists_error_msg();
+                                    continue;
+                                }
+
+                                user.verify(&client, username, code).await?;
+                            }
+                            Some(Command::RequestVerificationCode { username }) => {
+                                // check whether session exists
+                                if user.session_exists() {
+                                    print_session_exists_error_msg();
+                                    continue;
+                                }
+
+                                user.request_verification_code(&client, username).await?;
+                            }
                             Some(Command::Login { username, password }) => {
                                 // check whether session exists
                                 if user.session_exists() {
@@ -88,6 +119,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     .await?;
                                 if res {
                                     prompt = format!("{} >> ", user.get_user_name());
+                                    *room_names.lock().unwrap() =
+                                        user.fetch_room_names(&client).await;
 
                                     // Create PubSub client on login.
                                     match &pubsub_client {
@@ -117,6 +150,60 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     pubsub_client = None;
                                 }
                             }
+                            Some(Command::ChangePassword {
+                                old_password,
+                                new_password,
+                            }) => {
+                                // check whether session exists
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+
+                                if !is_valid_password(&new_password) {
+                                    continue;
+                                }
+
+                                let res = user
+                                    .change_password(&client, old_password, new_password)
+                                    .await?;
+                                if res {
+                                    if let Some(ps_client) = &pubsub_client {
+                                        let mut ps_client = ps_client.lock().await;
+                                        ps_client.set_session_id(user.get_session_id());
+                                        // The old session was revoked, and the broker
+                                        // refuses messages from connections opened with it.
+                                        let _ = ps_client.reconnect().await;
+                                    }
+                                }
+                            }
+                            Some(Command::RequestPasswordReset { username }) => {
+                                // check whether session exists
+                                if user.session_exists() {
+                                    print_session_exists_error_msg();
+                                    continue;
+                                }
+
+                                user.request_password_reset(&client, username).await?;
+                            }
+                            Some(Command::ResetPassword {
+                                username,
+                                token,
+                                new_password,
+                            }) => {
+                                // check whether session exists
+                                if user.session_exists() {
+                                    print_session_exists_error_msg();
+                                    continue;
+                                }
+
+                                if !is_valid_password(&new_password) {
+                                    continue;
+                                }
+
+                                user.reset_password(&client, username, token, new_password)
+                                    .await?;
+                            }
                             Some(Command::ListUsers) => {
                                 // check whether session exists
                                 if !user.session_exists() {
@@ -152,11 +239,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                         print_msg(&enter_msg);
                                         match &pubsub_client {
                                             Some(ps_client) => {
-                                                let _ = ps_client
-                                                    .lock()
-                                                    .unwrap()
-                                                    .subscribe(chat_id)
+                                                let ignored = user
+                                                    .fetch_ignored_users(&client, &chat_id)
                                                     .await;
+                                                let mut ps_client = ps_client.lock().await;
+                                                ps_client.set_ignored_users(ignored);
+                                                let _ = ps_client.subscribe(chat_id).await;
                                             }
                                             None => {
                                                 println!("Unable to join private chat. PubSub client is not initialized.");
@@ -168,6 +256,54 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     }
                                 }
                             }
+                            Some(Command::CreateGroupChat { with_users }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                let res =
+                                    user.create_group_chat(&client, with_users.clone()).await?;
+
+                                if let Some(chat_id) = res {
+                                    current_mode = "child";
+                                    let enter_msg = format!(
+                                        "Entering group chat with {}...",
+                                        with_users.join(", ")
+                                    );
+                                    print_msg(&enter_msg);
+                                    match &pubsub_client {
+                                        Some(ps_client) => {
+                                            let ignored =
+                                                user.fetch_ignored_users(&client, &chat_id).await;
+                                            let mut ps_client = ps_client.lock().await;
+                                            ps_client.set_ignored_users(ignored);
+                                            let _ = ps_client.subscribe(chat_id).await;
+                                        }
+                                        None => {
+                                            println!("Unable to join group chat. PubSub client is not initialized.");
+                                        }
+                                    }
+                                }
+                            }
+                            Some(Command::UpdateGroupChat {
+                                chat_id,
+                                target,
+                                add,
+                            }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.update_group_chat(&client, chat_id, target, add)
+                                    .await?;
+                            }
+                            Some(Command::ListGroupChats) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.list_group_chats(&client).await?;
+                            }
                             Some(Command::ResumeChat { with_user }) => {
                                 // check whether session exists
                                 if !user.session_exists() {
@@ -185,11 +321,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                                         match &pubsub_client {
                                             Some(ps_client) => {
-                                                let _ = ps_client
-                                                    .lock()
-                                                    .unwrap()
-                                                    .subscribe(chat_id)
+                                                let ignored = user
+                                                    .fetch_ignored_users(&client, &chat_id)
                                                     .await;
+                                                let mut ps_client = ps_client.lock().await;
+                                                ps_client.set_ignored_users(ignored);
+                                                let _ = ps_client.subscribe(chat_id).await;
                                             }
                                             None => {
                                                 println!("Unable to join chat room. PubSub client is not initialized.");
@@ -201,6 +338,84 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     }
                                 }
                             }
+                            Some(Command::JumpToUnread { number }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                let Some(conversation) = user.unread_conversation(number) else {
+                                    print_warning_error_msg(
+                                        "No such conversation in the unread summary.",
+                                    );
+                                    continue;
+                                };
+                                let chat_id = conversation.chat_id.clone();
+                                let num_messages =
+                                    conversation.unread.clamp(1, MAX_CATCH_UP_MESSAGES) as usize;
+                                current_mode = "child";
+                                let enter_msg = format!(
+                                    "Entering {}...",
+                                    conversation.describe(&user.get_user_name())
+                                );
+                                print_msg(&enter_msg);
+
+                                match &pubsub_client {
+                                    Some(ps_client) => {
+                                        let ignored =
+                                            user.fetch_ignored_users(&client, &chat_id).await;
+                                        let mut ps_client = ps_client.lock().await;
+                                        ps_client.set_ignored_users(ignored);
+                                        let _ = ps_client.subscribe(chat_id).await;
+                                        // Show the messages that were missed.
+                                        let _ = ps_client.fetch_recent_messages(num_messages).await;
+                                    }
+                                    None => {
+                                        println!("Unable to join the conversation. PubSub client is not initialized.");
+                                    }
+                                }
+                            }
+                            Some(Command::BlockUser { target, block }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.block_user(&client, target, block).await?;
+                            }
+                            Some(Command::ListBlockedUsers) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.list_blocked_users(&client).await?;
+                            }
+                            Some(Command::Search(search)) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.search_messages(&client, search).await?;
+                            }
+                            Some(Command::Export(export)) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.export_conversation(&client, export).await?;
+                            }
+                            Some(Command::Import(import)) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.import_conversation(&client, import).await?;
+                            }
+                            Some(Command::ListMentions) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.list_mentions(&client).await?;
+                            }
                             Some(Command::ListAllRecipients) => {
                                 // check whether session exists
                                 if !user.session_exists() {
@@ -209,26 +424,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 }
                                 user.list_all_recipients(&client).await?;
                             }
-                            Some(Command::CreateChatRoom { name }) => {
+                            Some(Command::CreateChatRoom {
+                                name,
+                                visibility,
+                                password,
+                            }) => {
                                 if !user.session_exists() {
                                     print_session_not_exist_error_msg();
                                     continue;
                                 }
-                                let res =
-                                    user.create_chat_room(&client, name.clone()).await?;
+                                if !is_valid_room_name(&name) {
+                                    continue;
+                                }
+                                let res = user
+                                    .create_chat_room(&client, name.clone(), visibility, password)
+                                    .await?;
 
                                 match res {
                                     Some(chat_room_id) => {
+                                        room_names.lock().unwrap().push(name.clone());
                                         current_mode = "child";
                                         let enter_msg = format!("Entering chat room {}...", name);
                                         print_msg(&enter_msg);
                                         match &pubsub_client {
                                             Some(ps_client) => {
-                                                let _ = ps_client
-                                                    .lock()
-                                                    .unwrap()
-                                                    .subscribe(chat_room_id)
+                                                let ignored = user
+                                                    .fetch_ignored_users(&client, &chat_room_id)
                                                     .await;
+                                                let mut ps_client = ps_client.lock().await;
+                                                ps_client.set_ignored_users(ignored);
+                                                let _ = ps_client.subscribe(chat_room_id).await;
                                             }
                                             None => {
                                                 println!("Unable to join chat room. PubSub client is not initialized.");
@@ -240,35 +465,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     }
                                 }
                             }
-                            Some(Command::JoinChatRoom { chat_id }) => {
+                            Some(Command::JoinChatRoom { room, password }) => {
                                 // check whether session exists
                                 if !user.session_exists() {
                                     print_session_not_exist_error_msg();
                                     continue;
                                 }
-                                let res = user.join_chat_room().await;
-                                match res {
-                                    Ok(()) => {
-                                        current_mode = "child";
-                                        let enter_msg =
-                                            format!("Entering chat room with id {}...", chat_id);
-                                        print_msg(&enter_msg);
+                                let Some((chat_id, name)) =
+                                    user.resolve_chat_room(&client, room).await?
+                                else {
+                                    continue;
+                                };
+                                let res = user
+                                    .join_chat_room(&client, chat_id.clone(), password)
+                                    .await?;
+                                if res {
+                                    current_mode = "child";
+                                    let enter_msg = format!("Entering chat room {}...", name);
+                                    print_msg(&enter_msg);
 
-                                        match &pubsub_client {
-                                            Some(ps_client) => {
-                                                let _ = ps_client
-                                                    .lock()
-                                                    .unwrap()
-                                                    .subscribe(chat_id)
-                                                    .await;
-                                            }
-                                            None => {
-                                                println!("Unable to join chat room. PubSub client is not initialized.");
-                                            }
+                                    match &pubsub_client {
+                                        Some(ps_client) => {
+                                            let ignored =
+                                                user.fetch_ignored_users(&client, &chat_id).await;
+                                            let mut ps_client = ps_client.lock().await;
+                                            ps_client.set_ignored_users(ignored);
+                                            let _ = ps_client.subscribe(chat_id).await;
+                                        }
+                                        None => {
+                                            println!("Unable to join chat room. PubSub client is not initialized.");
                                         }
-                                    }
-                                    Err(_) => {
-                                        continue;
                                     }
                                 }
                             }
@@ -277,7 +503,83 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     print_session_not_exist_error_msg();
                                     continue;
                                 }
-                                user.list_all_chat_rooms(&client).await?;
+                                *room_names.lock().unwrap() =
+                                    user.list_all_chat_rooms(&client).await?;
+                            }
+                            Some(Command::ListRoomMembers { room_id }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.list_room_members(&client, room_id).await?;
+                            }
+                            Some(Command::ModerateRoom {
+                                action,
+                                room_id,
+                                target,
+                            }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.moderate_room(&client, action, room_id, target).await?;
+                            }
+                            Some(Command::ShowRoomInfo { room_id }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.show_room_info(&client, room_id).await?;
+                            }
+                            Some(Command::RenameRoom { room_id, name }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                if !is_valid_room_name(&name) {
+                                    continue;
+                                }
+                                user.rename_room(&client, room_id, name).await?;
+                            }
+                            Some(Command::UpdateRoomDetails {
+                                room_id,
+                                topic,
+                                description,
+                            }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.update_room_details(&client, room_id, topic, description)
+                                    .await?;
+                            }
+                            Some(Command::DeleteRoom { room_id }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.delete_room(&client, room_id).await?;
+                            }
+                            Some(Command::ListInvites) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.list_invites(&client).await?;
+                            }
+                            Some(Command::RespondToInvite { room_id, accept }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.respond_to_invite(&client, room_id, accept).await?;
+                            }
+                            Some(Command::ListFailedLogins { username }) => {
+                                if !user.session_exists() {
+                                    print_session_not_exist_error_msg();
+                                    continue;
+                                }
+                                user.list_failed_logins(&client, username).await?;
                             }
                             Some(Command::Quit) => {
                                 // logout first
@@ -297,20 +599,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 }
             }
-            "child" => match &pubsub_client {
-                Some(ps_client) => {
-                    let _ = ps_client.lock().unwrap().start().await;
+            "child" => {
+                if let Some(ps_client) = &pubsub_client {
+                    let _ = ps_client.lock().await.start().await;
                     // This is kind of hacky but to exit the chat, the stream is closed so
                     // reconnect the stream so that the user can reuse the same pubsub client.
                     // Will improve this later if there is time.
-                    let _ = ps_client.lock().unwrap().reconnect().await;
+                    let _ = ps_client.lock().await.reconnect().await;
                     println!("Exited the chat");
                     current_mode = "main";
                     prompt = format!("{} >> ", user.get_user_name());
                     continue;
                 }
-                None => (),
-            },
+            }
             _ => {}
         }
     }
@@ -1,9 +1,18 @@
-use reqwest::{header, Client, Url};
-use rocket::serde::json::Value;
+use reqwest::{header, Client, Response, StatusCode, Url};
+use rocket::serde::json::{from_value, Value};
 use rocket::serde::ser::StdError;
 use rocket::serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
 
-use crate::common::{print_msg, print_warning_error_msg};
+use crate::commands::RoomAction;
+use crate::common::{
+    print_msg, print_warning_error_msg, server_url, JUMP_CMD, RESET_PASSWORD_CMD, VERIFY_CMD,
+};
+
+// Last line of an export the server could not finish.
+const EXPORT_FAILED_MARKER: &str = "\n*** Export failed: this transcript is incomplete. ***\n";
 
 #[derive(Serialize, Deserialize)]
 struct SignupInfo {
@@ -12,6 +21,17 @@ struct SignupInfo {
     password: String,
 }
 
+#[derive(Deserialize, Serialize)]
+struct SignupResponse {
+    verification_required: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct VerifyInfo {
+    username: String,
+    code: String,
+}
+
 #[derive(Deserialize, Serialize)]
 struct UserLogin {
     username: String,
@@ -24,6 +44,38 @@ struct UserRequest {
     session_id: String,
 }
 
+#[derive(Deserialize, Serialize)]
+struct ChangePasswordInfo {
+    username: String,
+    session_id: String,
+    old_password: String,
+    new_password: String,
+}
+
+// A notification sent while the user was offline, returned at login.
+#[derive(Deserialize, Serialize)]
+struct NotificationSummary {
+    content: String,
+    created_at: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct VerifyResendInfo {
+    username: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ResetRequestInfo {
+    username: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ResetPasswordInfo {
+    username: String,
+    token: String,
+    new_password: String,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct UserStatus {
     username: String,
@@ -34,7 +86,45 @@ pub struct UserStatus {
 pub struct ChatRoomInfo {
     username: String,
     session_id: String,
-    room_name: String
+    room_name: String,
+    visibility: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChatRoomJoinRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomInviteResponse {
+    room_id: String,
+    name: String,
+    inviter: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomModerationRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    target: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BlockRequest {
+    username: String,
+    session_id: String,
+    target: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomMemberResponse {
+    username: String,
+    role: String,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -44,10 +134,220 @@ pub struct PrivateChatRequest {
     recipient: String,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct GroupChatRequest {
+    username: String,
+    session_id: String,
+    participants: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct GroupMemberRequest {
+    username: String,
+    session_id: String,
+    chat_id: String,
+    target: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct GroupChatResponse {
+    chat_id: String,
+    participants: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FailedLoginResponse {
+    username: String,
+    ip_address: Option<String>,
+    reason: String,
+    attempted_at: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MentionResponse {
+    chat_id: String,
+    room_name: Option<String>,
+    sender: String,
+    message: String,
+    created_at: String,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct ChatRoomResponse {
     room_id: String,
     name: String,
+    topic: Option<String>,
+    visibility: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChatRoomInfoResponse {
+    room_id: String,
+    name: String,
+    topic: Option<String>,
+    description: Option<String>,
+    visibility: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomRenameRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomDetailsRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    topic: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ErrorResponse {
+    message: String,
+    #[serde(default)]
+    details: Value,
+}
+
+// Prints the error message returned by the server, falling back to the
+// HTTP status when the body is not a structured error.
+async fn print_error_response(context: &str, response: Response) {
+    let status = response.status();
+    match response.json::<ErrorResponse>().await {
+        Ok(error) => {
+            print_warning_error_msg(&format!("Error: {}: {}", context, error.message));
+            if let Some(fields) = error.details.get("fields").and_then(|v| v.as_array()) {
+                for field in fields {
+                    print_warning_error_msg(&format!(
+                        "  {}: {}",
+                        field["field"].as_str().unwrap_or(""),
+                        field["message"].as_str().unwrap_or("")
+                    ));
+                }
+            }
+            if let Some(candidates) = error.details.get("candidates").and_then(|v| v.as_array()) {
+                print_warning_error_msg("Did you mean one of these?");
+                for candidate in candidates {
+                    print_warning_error_msg(&format!(
+                        "  {} (Room_id: {})",
+                        candidate["name"].as_str().unwrap_or(""),
+                        candidate["room_id"].as_str().unwrap_or("")
+                    ));
+                }
+            }
+        }
+        Err(_) => print_warning_error_msg(&format!("Error: {}: {}.", context, status)),
+    }
+}
+
+/// A conversation with messages the user has not read, as summarized at login.
+#[derive(Debug, Deserialize)]
+pub struct UnreadSummary {
+    pub chat_id: String,
+    kind: String,
+    name: String,
+    pub unread: i64,
+    senders: Vec<String>,
+}
+
+impl UnreadSummary {
+    /// Describes the conversation from the point of view of `username`.
+    pub fn describe(&self, username: &str) -> String {
+        describe_conversation(&self.kind, &self.name, username)
+    }
+
+    fn summary(&self, username: &str) -> String {
+        let messages = if self.unread == 1 {
+            String::from("1 new message")
+        } else {
+            format!("{} new messages", self.unread)
+        };
+        match self.kind.as_str() {
+            "private" => format!("{} from {}", messages, self.name),
+            _ => format!(
+                "{} in {} from {}",
+                messages,
+                self.describe(username),
+                self.senders.join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SearchHitResponse {
+    chat_id: String,
+    kind: String,
+    name: String,
+    sender: String,
+    message: String,
+    sent_at: String,
+}
+
+/// Search filters given to the `search` command.
+#[derive(Debug)]
+pub struct MessageSearch {
+    pub text: Option<String>,
+    pub sender: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+/// What the `export` command writes and where.
+#[derive(Debug)]
+pub struct ConversationExport {
+    /// The other user of a private chat, a group chat id, or a chat room id or name.
+    pub target: String,
+    /// `json`, `md` or `txt`.
+    pub format: String,
+    pub since: Option<String>,
+    /// File to write; defaults to the target and format, e.g. `general.md`.
+    pub output: Option<String>,
+}
+
+/// Options of the admin `import` command.
+#[derive(Debug)]
+pub struct ConversationImport {
+    /// JSON file written by `export --format json`.
+    pub path: String,
+    pub create_users: bool,
+    pub create_conversation: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ImportReport {
+    chat_id: String,
+    created_conversation: bool,
+    created_users: Vec<String>,
+    imported: u32,
+    conflicts: Vec<ImportConflict>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ImportConflict {
+    message_id: Option<String>,
+    sender: String,
+    reason: String,
+}
+
+// Names a conversation as the server describes it (`kind` is `private`, `group`
+// or `room`) from the point of view of `username`.
+fn describe_conversation(kind: &str, name: &str, username: &str) -> String {
+    match kind {
+        "private" => format!("private chat with {}", name),
+        "group" => {
+            let others: Vec<&str> = name
+                .split(',')
+                .filter(|participant| *participant != username)
+                .collect();
+            format!("group chat with {}", others.join(", "))
+        }
+        _ => format!("chat room {}", name),
+    }
 }
 
 #[derive(Debug)]
@@ -68,11 +368,16 @@ impl Session {
 #[derive(Debug)]
 pub struct User {
     session: Option<Session>,
+    // Unread conversations reported at login, numbered from 1 for `jump`.
+    unread: Vec<UnreadSummary>,
 }
 
 impl User {
     pub fn new() -> Self {
-        User { session: None }
+        User {
+            session: None,
+            unread: Vec::new(),
+        }
     }
 
     pub fn get_user_name(&self) -> String {
@@ -100,11 +405,11 @@ impl User {
         email: String,
         password: String,
     ) -> Result<(), Box<dyn StdError>> {
-        let url = "http://localhost:8000/chatapp/user/signup"; // signup endpoint
+        let url = server_url("/chatapp/user/signup"); // signup endpoint
 
         // Prepare the signup data
         let signup_info = SignupInfo {
-            username,
+            username: username.clone(),
             email,
             password,
         };
@@ -113,8 +418,60 @@ impl User {
         let response = client.post(url).json(&signup_info).send().await?;
         if response.status().is_success() {
             print_msg("Signup successfully!");
+            let signup_response: SignupResponse =
+                response.json().await.expect("Failed to parse JSON.");
+            if signup_response.verification_required {
+                print_msg(&format!(
+                    "A verification code was sent to your email. Run '{} {} [code]' before logging in.",
+                    VERIFY_CMD, username
+                ));
+            }
+        } else {
+            print_error_response("failed to signup", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn verify(
+        &self,
+        client: &Client,
+        username: String,
+        code: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/user/verify"); // endpoint
+        let verify_info = VerifyInfo { username, code };
+
+        // Send the POST request
+        let response = client.post(url).json(&verify_info).send().await?;
+        if response.status().is_success() {
+            print_msg("Email verified successfully! You can now log in.");
+        } else {
+            print_error_response("failed to verify email", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn request_verification_code(
+        &self,
+        client: &Client,
+        username: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/user/verify/resend"); // endpoint
+        let resend_info = VerifyResendInfo {
+            username: username.clone(),
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&resend_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!(
+                "If user '{}' is awaiting verification, a new code was sent to its email. Run '{} {} [code]'.",
+                username, VERIFY_CMD, username
+            ));
         } else {
-            print_warning_error_msg(&format!("Error: failed to signup: {}.", response.status()));
+            print_error_response("failed to request a verification code", response).await;
         }
 
         Ok(())
@@ -126,7 +483,7 @@ impl User {
         username: &str,
         password: String,
     ) -> Result<bool, Box<dyn StdError>> {
-        let url = "http://localhost:8000/chatapp/user/login";
+        let url = server_url("/chatapp/user/login");
         let login_info = UserLogin {
             username: username.to_string(),
             password,
@@ -141,18 +498,56 @@ impl User {
                 // Create the session
                 self.session = Some(Session::new(username, session_id));
                 print_msg("Login successfully!");
+                self.unread = json
+                    .get("unread")
+                    .and_then(|v| from_value(v.clone()).ok())
+                    .unwrap_or_default();
+                let notifications: Vec<NotificationSummary> = json
+                    .get("notifications")
+                    .and_then(|v| from_value(v.clone()).ok())
+                    .unwrap_or_default();
+                for notification in &notifications {
+                    print_msg(&format!(
+                        ">>> {} ({}) <<<",
+                        notification.content, notification.created_at
+                    ));
+                }
+                self.print_unread_summary();
                 Ok(true)
             } else {
                 print_warning_error_msg("Failed to retrieve session_id from JSON response.");
                 Ok(false)
             }
         } else {
-            self.error_response(&format!("Error: failed to login: {}.", response.status()))
+            self.error_response("failed to login", response).await
         }
     }
 
+    fn print_unread_summary(&self) {
+        if self.unread.is_empty() {
+            return;
+        }
+        let username = self.get_user_name();
+        for (idx, conversation) in self.unread.iter().enumerate() {
+            print_msg(&format!(
+                "[{}] {}",
+                idx + 1,
+                conversation.summary(&username)
+            ));
+        }
+        print_msg(&format!(
+            "Type '{} [number]' to open one of these conversations.",
+            JUMP_CMD
+        ));
+    }
+
+    /// The unread conversation with the given number from the login summary.
+    pub fn unread_conversation(&self, number: usize) -> Option<&UnreadSummary> {
+        number.checked_sub(1).and_then(|idx| self.unread.get(idx))
+    }
+
     pub async fn logout(&mut self, client: &Client) -> Result<bool, Box<dyn StdError>> {
-        let url = "http://localhost:8000/chatapp/user/logout"; // endpoint
+        let url = server_url("/chatapp/user/logout"); // endpoint
 
         // Prepare the data
         let session = self.session.as_ref().unwrap();
@@ -166,11 +561,97 @@ impl User {
         // Check if the response was successful
         if response.status().is_success() {
             self.session = None;
+            self.unread.clear();
             print_msg("Log out successfully!");
             Ok(true)
         } else {
-            self.error_response(&format!("Error: failed to logout: {}.", response.status()))
+            self.error_response("failed to logout", response).await
+        }
+    }
+
+    pub async fn change_password(
+        &mut self,
+        client: &Client,
+        old_password: String,
+        new_password: String,
+    ) -> Result<bool, Box<dyn StdError>> {
+        let url = server_url("/chatapp/user/password/change"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let change_info = ChangePasswordInfo {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            old_password,
+            new_password,
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&change_info).send().await?;
+        if response.status().is_success() {
+            let json: Value = response.json().await.expect("Failed to parse JSON.");
+            if let Some(session_id) = json.get("session_id").and_then(|v| v.as_str()) {
+                // The server renews the session when the password changes.
+                let username = session.username.clone();
+                self.session = Some(Session::new(&username, session_id));
+                print_msg("Password changed successfully!");
+                Ok(true)
+            } else {
+                print_warning_error_msg("Failed to retrieve session_id from JSON response.");
+                Ok(false)
+            }
+        } else {
+            self.error_response("failed to change password", response)
+                .await
+        }
+    }
+
+    pub async fn request_password_reset(
+        &self,
+        client: &Client,
+        username: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/user/password/reset-request"); // endpoint
+        let reset_info = ResetRequestInfo {
+            username: username.clone(),
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&reset_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!(
+                "If user '{}' exists, a reset token was sent to its email. Run '{} {} [token] [new_password]'.",
+                username, RESET_PASSWORD_CMD, username
+            ));
+        } else {
+            print_error_response("failed to request a password reset", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn reset_password(
+        &self,
+        client: &Client,
+        username: String,
+        token: String,
+        new_password: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/user/password/reset"); // endpoint
+        let reset_info = ResetPasswordInfo {
+            username,
+            token,
+            new_password,
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&reset_info).send().await?;
+        if response.status().is_success() {
+            print_msg("Password reset successfully! You can now log in.");
+        } else {
+            print_error_response("failed to reset password", response).await;
         }
+
+        Ok(())
     }
 
     pub async fn check_user_status(
@@ -179,8 +660,8 @@ impl User {
         user: String,
     ) -> Result<(), Box<dyn StdError>> {
         // endpoint
-        let url = "http://localhost:8000/chatapp/user/status";
-        let url = Url::parse_with_params(url, &[("username", &user)])?;
+        let url = server_url("/chatapp/user/status");
+        let url = Url::parse_with_params(&url, &[("username", &user)])?;
 
         // Get the current session
         let session = self.session.as_ref().ok_or("Session is not initialized")?;
@@ -205,17 +686,14 @@ impl User {
             let user_status = response.text().await?;
             print_msg(&format!("The status of user '{}' is {}", user, user_status));
         } else {
-            print_warning_error_msg(&format!(
-                "Error: failed to retrieve user's status: {}.",
-                response.status()
-            ));
+            print_error_response("failed to retrieve user's status", response).await;
         }
 
         Ok(())
     }
 
     pub async fn list_users(&mut self, client: &Client) -> Result<(), Box<dyn StdError>> {
-        let url = "http://localhost:8000/chatapp/user/allusers"; // endpoint
+        let url = server_url("/chatapp/user/allusers"); // endpoint
         let session = self.session.as_ref().unwrap();
 
         // Send the GET request with headers
@@ -235,32 +713,118 @@ impl User {
                 }
             }
         } else {
-            print_warning_error_msg(&format!(
-                "Error: failed to retrieve users: {}.",
-                response.status()
-            ));
+            print_error_response("failed to retrieve users", response).await;
         }
 
         Ok(())
     }
 
-    pub async fn create_private_chat(
-        &mut self,
+    pub async fn block_user(
+        &self,
         client: &Client,
-        user: String,
-    ) -> Result<Option<String>, Box<dyn StdError>> {
-        let session = self.session.as_ref().unwrap();
-        if user == session.username {
-            print_warning_error_msg("You are not allowed to create a private chat with yourself");
-            return Ok(None);
-        }
-
-        let url = "http://localhost:8000/chatapp/chat/private-chat/create"; // endpoint
+        target: String,
+        block: bool,
+    ) -> Result<(), Box<dyn StdError>> {
+        let (action, past_tense) = if block {
+            ("block", "blocked")
+        } else {
+            ("unblock", "unblocked")
+        };
+        let url = server_url(&format!("/chatapp/user/{}", action)); // endpoint
 
-        let chat_info = PrivateChatRequest {
+        let session = self.session.as_ref().unwrap();
+        let block_info = BlockRequest {
             username: session.username.clone(),
             session_id: session.session_id.clone(),
-            recipient: user.clone(),
+            target: target.clone(),
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&block_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!("User '{}' {} successfully!", target, past_tense));
+        } else {
+            print_error_response(&format!("failed to {} user '{}'", action, target), response)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_blocked_users(&self, client: &Client) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/user/blocked"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let users: Vec<String> = response.json().await.expect("Failed to parse JSON");
+            if users.is_empty() {
+                print_msg("You have not blocked anyone.");
+            } else {
+                for user in users {
+                    print_msg(&format!("blocked: {}", user));
+                }
+            }
+        } else {
+            print_error_response("failed to retrieve blocked users", response).await;
+        }
+
+        Ok(())
+    }
+
+    // Users whose messages are hidden in the given conversation: everyone the
+    // user blocked, plus the users they muted if it is a chat room. Failures
+    // leave the list short; the broker filters these messages as well.
+    pub async fn fetch_ignored_users(&self, client: &Client, chat_id: &str) -> Vec<String> {
+        let session = self.session.as_ref().unwrap();
+        let mut ignored = Vec::new();
+        let Ok(mutes_url) = Url::parse_with_params(
+            &server_url("/chatapp/chat/chat-room/mutes"),
+            &[("room_id", chat_id)],
+        ) else {
+            return ignored;
+        };
+        for url in [server_url("/chatapp/user/blocked"), mutes_url.to_string()] {
+            let response = client
+                .get(url)
+                .header("username", &session.username)
+                .header("session_id", &session.session_id)
+                .send()
+                .await;
+            if let Ok(response) = response {
+                if response.status().is_success() {
+                    if let Ok(users) = response.json::<Vec<String>>().await {
+                        ignored.extend(users);
+                    }
+                }
+            }
+        }
+        ignored
+    }
+
+    pub async fn create_private_chat(
+        &mut self,
+        client: &Client,
+        user: String,
+    ) -> Result<Option<String>, Box<dyn StdError>> {
+        let session = self.session.as_ref().unwrap();
+        if user == session.username {
+            print_warning_error_msg("You are not allowed to create a private chat with yourself");
+            return Ok(None);
+        }
+
+        let url = server_url("/chatapp/chat/private-chat/create"); // endpoint
+
+        let chat_info = PrivateChatRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            recipient: user.clone(),
         };
 
         // Send the POST request
@@ -279,18 +843,121 @@ impl User {
                     user
                 ));
                 print_msg(&format!("Chat id is {}", chat_id));
-                return Ok(Some(chat_id));
+                Ok(Some(chat_id))
             }
         } else {
-            print_warning_error_msg(&format!(
-                "Error: failed to create a private chat with user '{}': {}.",
-                user,
-                response.status()
+            print_error_response(
+                &format!("failed to create a private chat with user '{}'", user),
+                response,
+            )
+            .await;
+            Ok(None)
+        }
+    }
+
+    // Opens the group chat with `users`, creating it the first time.
+    pub async fn create_group_chat(
+        &self,
+        client: &Client,
+        users: Vec<String>,
+    ) -> Result<Option<String>, Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/group-chat/create"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let group_info = GroupChatRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            participants: users.clone(),
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&group_info).send().await?;
+        if response.status() == StatusCode::CREATED {
+            let chat_id: String = response.json().await.expect("Failed to parse JSON");
+            print_msg(&format!(
+                "You created a group chat with {} successfully!",
+                users.join(", ")
             ));
+            print_msg(&format!("Chat id is {}", chat_id));
+            Ok(Some(chat_id))
+        } else if response.status().is_success() {
+            let chat_id: String = response.json().await.expect("Failed to parse JSON");
+            Ok(Some(chat_id))
+        } else {
+            print_error_response("failed to open the group chat", response).await;
             Ok(None)
         }
     }
 
+    pub async fn update_group_chat(
+        &self,
+        client: &Client,
+        chat_id: String,
+        target: String,
+        add: bool,
+    ) -> Result<(), Box<dyn StdError>> {
+        let (action, past_tense) = if add {
+            ("add", "added to")
+        } else {
+            ("remove", "removed from")
+        };
+        let url = server_url(&format!("/chatapp/chat/group-chat/{}", action)); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let member_info = GroupMemberRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            chat_id,
+            target: target.clone(),
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&member_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!(
+                "User '{}' {} the group chat successfully!",
+                target, past_tense
+            ));
+        } else {
+            print_error_response(&format!("failed to {} user '{}'", action, target), response)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_group_chats(&self, client: &Client) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/group-chat/all"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let groups: Vec<GroupChatResponse> =
+                response.json().await.expect("Failed to parse JSON");
+            if groups.is_empty() {
+                print_msg("No group chats.");
+            } else {
+                for group in groups {
+                    print_msg(&format!(
+                        "Chat_id: {}, Participants: {}",
+                        group.chat_id,
+                        group.participants.join(", ")
+                    ));
+                }
+            }
+        } else {
+            print_error_response("failed to retrieve group chats", response).await;
+        }
+
+        Ok(())
+    }
+
     pub async fn resume_private_chat(
         &self,
         client: &Client,
@@ -302,7 +969,7 @@ impl User {
             return Ok(None);
         }
 
-        let url = "http://localhost:8000/chatapp/chat/private-chat/resume"; // endpoint
+        let url = server_url("/chatapp/chat/private-chat/resume"); // endpoint
 
         let chat_info = PrivateChatRequest {
             username: session.username.clone(),
@@ -325,20 +992,20 @@ impl User {
                     user
                 ));
                 print_msg(&format!("Chat id is {}", chat_id));
-                return Ok(Some(chat_id));
+                Ok(Some(chat_id))
             }
         } else {
-            print_warning_error_msg(&format!(
-                "Error: failed to resume the private chat with user '{}': {}.",
-                user,
-                response.status()
-            ));
+            print_error_response(
+                &format!("failed to resume the private chat with user '{}'", user),
+                response,
+            )
+            .await;
             Ok(None)
         }
     }
 
     pub async fn list_all_recipients(&self, client: &Client) -> Result<(), Box<dyn StdError>> {
-        let url = "http://localhost:8000/chatapp/chat/private-chat/recipients"; // endpoint
+        let url = server_url("/chatapp/chat/private-chat/recipients"); // endpoint
 
         let session = self.session.as_ref().unwrap();
         // Send the GET request with headers
@@ -357,10 +1024,7 @@ impl User {
                 print_msg(&formatted);
             }
         } else {
-            print_warning_error_msg(&format!(
-                "Error: failed to retrieve private chat recipients: {}.",
-                response.status()
-            ));
+            print_error_response("failed to retrieve private chat recipients", response).await;
         }
 
         Ok(())
@@ -369,7 +1033,9 @@ impl User {
     pub async fn create_chat_room(
         &mut self,
         client: &Client,
-        room_name: String
+        room_name: String,
+        visibility: Option<String>,
+        password: Option<String>,
     ) -> Result<Option<String>, Box<dyn StdError>> {
         let session = self.session.as_ref().unwrap();
 
@@ -377,9 +1043,11 @@ impl User {
             username: session.username.clone(),
             session_id: session.session_id.clone(),
             room_name: room_name.clone(),
+            visibility,
+            password,
         };
 
-        let url = "http://localhost:8000/chatapp/chat/chat-room/create"; // endpoint
+        let url = server_url("/chatapp/chat/chat-room/create"); // endpoint
 
         // Send the POST request
         match client.post(url).json(&chat_room_info).send().await {
@@ -389,17 +1057,17 @@ impl User {
                     print_msg(&msg);
                     let chat_room_id: String = response.json().await.expect("Failed to parse JSON");
                     if chat_room_id.is_empty() {
-                        return Ok(None);
+                        Ok(None)
                     } else {
                         print_msg(&format!("Chat room id is {}", chat_room_id));
-                        return Ok(Some(chat_room_id));
+                        Ok(Some(chat_room_id))
                     }
                 } else {
-                    print_warning_error_msg(&format!(
-                        "Failed to create chat room '{}': {}.",
-                        room_name,
-                        response.status()
-                    ));
+                    print_error_response(
+                        &format!("failed to create chat room '{}'", room_name),
+                        response,
+                    )
+                    .await;
                     Ok(None)
                 }
             }
@@ -414,13 +1082,102 @@ impl User {
     }
 
     pub async fn join_chat_room(
-        &self
+        &self,
+        client: &Client,
+        room_id: String,
+        password: Option<String>,
+    ) -> Result<bool, Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/join"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let join_info = ChatRoomJoinRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            room_id: room_id.clone(),
+            password,
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&join_info).send().await?;
+        if response.status().is_success() {
+            Ok(true)
+        } else {
+            self.error_response(&format!("failed to join chat room '{}'", room_id), response)
+                .await
+        }
+    }
+
+    pub async fn list_room_members(
+        &self,
+        client: &Client,
+        room_id: String,
     ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/members"); // endpoint
+        let url = Url::parse_with_params(&url, &[("room_id", &room_id)])?;
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let members: Vec<RoomMemberResponse> =
+                response.json().await.expect("Failed to parse JSON");
+            for member in members {
+                print_msg(&format!("user: {}, role: {}", member.username, member.role));
+            }
+        } else {
+            print_error_response("failed to retrieve chat room members", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn moderate_room(
+        &self,
+        client: &Client,
+        action: RoomAction,
+        room_id: String,
+        target: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url(&format!("/chatapp/chat/chat-room/{}", action.endpoint())); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let moderation_info = RoomModerationRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            room_id,
+            target: target.clone(),
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&moderation_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!(
+                "User '{}' {} successfully!",
+                target,
+                action.past_tense()
+            ));
+        } else {
+            print_error_response(
+                &format!("failed to {} user '{}'", action.endpoint(), target),
+                response,
+            )
+            .await;
+        }
+
         Ok(())
     }
 
-    pub async fn list_all_chat_rooms(&self, client: &Client) -> Result<(), Box<dyn StdError>> {
-        let url = "http://localhost:8000/chatapp/chat/chat-room/all"; // endpoint
+    // Returns the names of the listed rooms so the caller can refresh its cache.
+    pub async fn list_all_chat_rooms(
+        &self,
+        client: &Client,
+    ) -> Result<Vec<String>, Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/all"); // endpoint
 
         let session = self.session.as_ref().unwrap();
         // Send the GET request with headers
@@ -430,6 +1187,7 @@ impl User {
             .header("session_id", &session.session_id)
             .send()
             .await?;
+        let mut names = Vec::new();
         if response.status().is_success() {
             let chat_rooms: Vec<ChatRoomResponse> =
                 response.json().await.expect("Failed to parse JSON");
@@ -437,21 +1195,519 @@ impl User {
                 print_msg("No chat rooms.");
             } else {
                 for room in chat_rooms {
-                    print_msg(&format!("Name: {}, Room_id: {}", room.name, room.room_id));
+                    print_msg(&format!(
+                        "Name: {}, Room_id: {}, Visibility: {}, Topic: {}",
+                        room.name,
+                        room.room_id,
+                        room.visibility,
+                        room.topic.as_deref().unwrap_or("-")
+                    ));
+                    names.push(room.name);
+                }
+            }
+        } else {
+            print_error_response("failed to retrieve chat rooms", response).await;
+        }
+
+        Ok(names)
+    }
+
+    // Fetches the names of the visible chat rooms without printing them, for
+    // tab completion. Failures just leave the completion list empty.
+    pub async fn fetch_room_names(&self, client: &Client) -> Vec<String> {
+        let url = server_url("/chatapp/chat/chat-room/all"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await;
+        match response {
+            Ok(response) if response.status().is_success() => response
+                .json::<Vec<ChatRoomResponse>>()
+                .await
+                .map(|rooms| rooms.into_iter().map(|room| room.name).collect())
+                .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    // Turns a room name, name prefix or id into the room's (id, name).
+    pub async fn resolve_chat_room(
+        &self,
+        client: &Client,
+        room: String,
+    ) -> Result<Option<(String, String)>, Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/resolve"); // endpoint
+        let url = Url::parse_with_params(&url, &[("name", &room)])?;
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let room: ChatRoomResponse = response.json().await.expect("Failed to parse JSON");
+            Ok(Some((room.room_id, room.name)))
+        } else {
+            print_error_response(&format!("failed to find chat room '{}'", room), response).await;
+            Ok(None)
+        }
+    }
+
+    pub async fn show_room_info(
+        &self,
+        client: &Client,
+        room_id: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/info"); // endpoint
+        let url = Url::parse_with_params(&url, &[("room_id", &room_id)])?;
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let room: ChatRoomInfoResponse = response.json().await.expect("Failed to parse JSON");
+            print_msg(&format!("Name: {}, Room_id: {}", room.name, room.room_id));
+            print_msg(&format!("Visibility: {}", room.visibility));
+            print_msg(&format!("Topic: {}", room.topic.as_deref().unwrap_or("-")));
+            print_msg(&format!(
+                "Description: {}",
+                room.description.as_deref().unwrap_or("-")
+            ));
+        } else {
+            print_error_response("failed to retrieve the chat room", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn rename_room(
+        &self,
+        client: &Client,
+        room_id: String,
+        name: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/rename"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let rename_info = RoomRenameRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            room_id,
+            name: name.clone(),
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&rename_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!("Chat room renamed to '{}' successfully!", name));
+        } else {
+            print_error_response("failed to rename the chat room", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_room_details(
+        &self,
+        client: &Client,
+        room_id: String,
+        topic: Option<String>,
+        description: Option<String>,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/details"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let details_info = RoomDetailsRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            room_id,
+            topic,
+            description,
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&details_info).send().await?;
+        if response.status().is_success() {
+            print_msg("Chat room updated successfully!");
+        } else {
+            print_error_response("failed to update the chat room", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete_room(
+        &self,
+        client: &Client,
+        room_id: String,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/delete"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let delete_info = ChatRoomJoinRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            room_id: room_id.clone(),
+            password: None,
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&delete_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!("Chat room '{}' deleted successfully!", room_id));
+        } else {
+            print_error_response("failed to delete the chat room", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_invites(&self, client: &Client) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/chat-room/invites"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let invites: Vec<RoomInviteResponse> =
+                response.json().await.expect("Failed to parse JSON");
+            if invites.is_empty() {
+                print_msg("No pending invites.");
+            } else {
+                for invite in invites {
+                    print_msg(&format!(
+                        "Name: {}, Room_id: {}, Invited by: {}",
+                        invite.name, invite.room_id, invite.inviter
+                    ));
                 }
             }
         } else {
+            print_error_response("failed to retrieve invites", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn respond_to_invite(
+        &self,
+        client: &Client,
+        room_id: String,
+        accept: bool,
+    ) -> Result<(), Box<dyn StdError>> {
+        let (action, past_tense) = if accept {
+            ("accept", "accepted")
+        } else {
+            ("decline", "declined")
+        };
+        let url = server_url(&format!("/chatapp/chat/chat-room/invite/{}", action)); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        let invite_info = ChatRoomJoinRequest {
+            username: session.username.clone(),
+            session_id: session.session_id.clone(),
+            room_id: room_id.clone(),
+            password: None,
+        };
+
+        // Send the POST request
+        let response = client.post(url).json(&invite_info).send().await?;
+        if response.status().is_success() {
+            print_msg(&format!(
+                "Invite to chat room '{}' {} successfully!",
+                room_id, past_tense
+            ));
+        } else {
+            print_error_response(
+                &format!("failed to {} the invite to chat room '{}'", action, room_id),
+                response,
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_failed_logins(
+        &self,
+        client: &Client,
+        username: Option<String>,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/admin/failed-logins"); // endpoint
+        let url = match &username {
+            Some(username) => Url::parse_with_params(&url, &[("username", username)])?,
+            None => Url::parse(&url)?,
+        };
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let attempts: Vec<FailedLoginResponse> =
+                response.json().await.expect("Failed to parse JSON");
+            if attempts.is_empty() {
+                print_msg("No failed login attempts.");
+            } else {
+                for attempt in attempts {
+                    print_msg(&format!(
+                        "{} user: {}, ip: {}, reason: {}",
+                        attempt.attempted_at,
+                        attempt.username,
+                        attempt.ip_address.as_deref().unwrap_or("unknown"),
+                        attempt.reason
+                    ));
+                }
+            }
+        } else {
+            print_error_response("failed to retrieve failed login attempts", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn search_messages(
+        &self,
+        client: &Client,
+        search: MessageSearch,
+    ) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/chat/search"); // endpoint
+        let params: Vec<(&str, String)> = [
+            ("q", search.text),
+            ("sender", search.sender),
+            ("since", search.since),
+            ("until", search.until),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| (key, value)))
+        .collect();
+        let url = Url::parse_with_params(&url, &params)?;
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let hits: Vec<SearchHitResponse> = response.json().await.expect("Failed to parse JSON");
+            if hits.is_empty() {
+                print_msg("No messages found.");
+            } else {
+                for hit in hits {
+                    print_msg(&format!(
+                        "{} [{}] {}: {}",
+                        hit.sent_at,
+                        describe_conversation(&hit.kind, &hit.name, &session.username),
+                        hit.sender,
+                        hit.message
+                    ));
+                }
+            }
+        } else {
+            print_error_response("failed to search messages", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn export_conversation(
+        &self,
+        client: &Client,
+        export: ConversationExport,
+    ) -> Result<(), Box<dyn StdError>> {
+        let path = export.output.unwrap_or_else(|| {
+            let name: String = export
+                .target
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || c == '.' || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("{}.{}", name, export.format)
+        });
+        if Path::new(&path).exists() {
             print_warning_error_msg(&format!(
-                "Error: failed to retrieve chat rooms: {}.",
-                response.status()
+                "'{}' already exists. Choose another file with --out.",
+                path
+            ));
+            return Ok(());
+        }
+
+        let url = server_url("/chatapp/chat/export"); // endpoint
+        let mut params = vec![("target", export.target), ("format", export.format)];
+        if let Some(since) = export.since {
+            params.push(("since", since));
+        }
+        let url = Url::parse_with_params(&url, &params)?;
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let mut response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            // Written as it arrives, so long histories are not held in memory.
+            let mut file = File::create(&path).await?;
+            // Only the end is kept, to spot the server's failure marker.
+            let mut tail: Vec<u8> = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+                tail.extend_from_slice(&chunk);
+                let excess = tail.len().saturating_sub(EXPORT_FAILED_MARKER.len());
+                tail.drain(..excess);
+            }
+            file.flush().await?;
+            if tail == EXPORT_FAILED_MARKER.as_bytes() {
+                print_warning_error_msg(&format!(
+                    "The export failed partway; '{}' is incomplete.",
+                    path
+                ));
+            } else {
+                print_msg(&format!("Exported the conversation to '{}'.", path));
+            }
+        } else {
+            print_error_response("failed to export the conversation", response).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn import_conversation(
+        &self,
+        client: &Client,
+        import: ConversationImport,
+    ) -> Result<(), Box<dyn StdError>> {
+        let contents = match fs::read_to_string(&import.path).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                print_warning_error_msg(&format!("Cannot read '{}': {}", import.path, e));
+                return Ok(());
+            }
+        };
+
+        let url = server_url("/chatapp/admin/import"); // endpoint
+        let url = Url::parse_with_params(
+            &url,
+            &[
+                ("create_users", import.create_users.to_string()),
+                (
+                    "create_conversation",
+                    import.create_conversation.to_string(),
+                ),
+            ],
+        )?;
+
+        let session = self.session.as_ref().unwrap();
+        // Send the POST request with headers; the file is already JSON
+        let response = client
+            .post(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(contents)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let report: ImportReport = response.json().await.expect("Failed to parse JSON");
+            if report.created_conversation {
+                print_msg(&format!("Created conversation {}.", report.chat_id));
+            }
+            if !report.created_users.is_empty() {
+                print_msg(&format!(
+                    "Created users: {}",
+                    report.created_users.join(", ")
+                ));
+            }
+            print_msg(&format!(
+                "Imported {} messages into conversation {}.",
+                report.imported, report.chat_id
             ));
+            for conflict in report.conflicts {
+                print_warning_error_msg(&format!(
+                    "Skipped message {} from {}: {}",
+                    conflict.message_id.as_deref().unwrap_or("without id"),
+                    conflict.sender,
+                    conflict.reason
+                ));
+            }
+        } else {
+            print_error_response("failed to import the conversation", response).await;
         }
 
         Ok(())
     }
 
-    fn error_response(&self, message: &str) -> Result<bool, Box<dyn StdError>> {
-        print_warning_error_msg(message);
+    pub async fn list_mentions(&self, client: &Client) -> Result<(), Box<dyn StdError>> {
+        let url = server_url("/chatapp/user/mentions"); // endpoint
+
+        let session = self.session.as_ref().unwrap();
+        // Send the GET request with headers
+        let response = client
+            .get(url)
+            .header("username", &session.username)
+            .header("session_id", &session.session_id)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            let mentions: Vec<MentionResponse> =
+                response.json().await.expect("Failed to parse JSON");
+            if mentions.is_empty() {
+                print_msg("Nobody has mentioned you yet.");
+            } else {
+                for mention in mentions {
+                    let conversation = match &mention.room_name {
+                        Some(room_name) => format!("chat room {}", room_name),
+                        None => format!("chat {}", mention.chat_id),
+                    };
+                    print_msg(&format!(
+                        "{} {} in {}: {}",
+                        mention.created_at, mention.sender, conversation, mention.message
+                    ));
+                }
+            }
+        } else {
+            print_error_response("failed to retrieve mentions", response).await;
+        }
+
+        Ok(())
+    }
+
+    async fn error_response(
+        &self,
+        context: &str,
+        response: Response,
+    ) -> Result<bool, Box<dyn StdError>> {
+        print_error_response(context, response).await;
         Ok(false)
     }
 }
@@ -1,10 +1,30 @@
 CREATE TABLE user (
     username varchar(255) NOT NULL,
-    email varchar(255),
+    email varchar(255) UNIQUE,
     password varchar(255) NOT NULL,
     session_id varchar(255),
+    verified BOOLEAN NOT NULL DEFAULT FALSE,
+    verification_code varchar(255),
+    verification_expires_at TIMESTAMP NULL,
+    verification_attempts INT NOT NULL DEFAULT 0,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
     PRIMARY KEY (username)
 );
+CREATE TABLE login_attempt (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    ip_address VARCHAR(64),
+    reason VARCHAR(64) NOT NULL,
+    attempted_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (username)
+);
+CREATE TABLE password_reset (
+    token VARCHAR(255) NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (token),
+    FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE
+);
 CREATE TABLE private_chat (
     chat_id VARCHAR(255),
     user1 VARCHAR(255) NOT NULL,
@@ -13,24 +33,114 @@ CREATE TABLE private_chat (
     FOREIGN KEY (user2) REFERENCES user(username) ON DELETE CASCADE,
     UNIQUE (user1, user2)
 );
+CREATE TABLE group_chat (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    chat_id VARCHAR(255) NOT NULL UNIQUE,
+    participant_key VARCHAR(512) NOT NULL UNIQUE,
+    created_by VARCHAR(255),
+    FOREIGN KEY (created_by) REFERENCES user(username) ON DELETE SET NULL
+);
+CREATE TABLE group_chat_member (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    group_id BIGINT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    FOREIGN KEY (group_id) REFERENCES group_chat(id) ON DELETE CASCADE,
+    FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE,
+    UNIQUE (group_id, username)
+);
 CREATE TABLE chat_room (
     id BIGINT AUTO_INCREMENT PRIMARY KEY,
     chat_room_id varchar(255),
-    name VARCHAR(100) NOT NULL
+    name VARCHAR(100) NOT NULL UNIQUE,
+    topic VARCHAR(255),
+    description VARCHAR(1000),
+    owner VARCHAR(255),
+    visibility VARCHAR(16) NOT NULL DEFAULT 'public',
+    password VARCHAR(255),
+    FOREIGN KEY (owner) REFERENCES user(username) ON DELETE SET NULL
 );
 CREATE TABLE room_member (
     id BIGINT AUTO_INCREMENT PRIMARY KEY,
     room_id BIGINT NOT NULL,
     username VARCHAR(255)  NOT NULL,
+    role VARCHAR(16) NOT NULL DEFAULT 'member',
     FOREIGN KEY (room_id) REFERENCES chat_room(id) ON DELETE CASCADE,
     FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE,
     UNIQUE (room_id, username)
 );
+CREATE TABLE room_ban (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    room_id BIGINT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    banned_by VARCHAR(255),
+    FOREIGN KEY (room_id) REFERENCES chat_room(id) ON DELETE CASCADE,
+    FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE,
+    UNIQUE (room_id, username)
+);
+CREATE TABLE room_invite (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    room_id BIGINT NOT NULL,
+    invitee VARCHAR(255) NOT NULL,
+    inviter VARCHAR(255) NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    FOREIGN KEY (room_id) REFERENCES chat_room(id) ON DELETE CASCADE,
+    FOREIGN KEY (invitee) REFERENCES user(username) ON DELETE CASCADE,
+    FOREIGN KEY (inviter) REFERENCES user(username) ON DELETE CASCADE,
+    UNIQUE (room_id, invitee)
+);
+CREATE TABLE user_block (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    blocker VARCHAR(255) NOT NULL,
+    blocked VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (blocker) REFERENCES user(username) ON DELETE CASCADE,
+    FOREIGN KEY (blocked) REFERENCES user(username) ON DELETE CASCADE,
+    UNIQUE (blocker, blocked)
+);
+CREATE TABLE room_mute (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    room_id BIGINT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    muted VARCHAR(255) NOT NULL,
+    FOREIGN KEY (room_id) REFERENCES chat_room(id) ON DELETE CASCADE,
+    FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE,
+    FOREIGN KEY (muted) REFERENCES user(username) ON DELETE CASCADE,
+    UNIQUE (room_id, username, muted)
+);
 CREATE TABLE chat_message (
     id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    message_id VARCHAR(36) UNIQUE,
     chat_id VARCHAR(255) NOT NULL,
     username VARCHAR(255) NOT NULL,
-    message VARCHAR(255),
+    message TEXT,
     timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE,
+    FULLTEXT INDEX message_text (message),
+    INDEX chat_time (chat_id, timestamp)
+);
+CREATE TABLE last_read (
+    username VARCHAR(255) NOT NULL,
+    chat_id VARCHAR(255) NOT NULL,
+    last_message_id BIGINT NOT NULL,
+    PRIMARY KEY (username, chat_id),
+    FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE
+);
+CREATE TABLE mention (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    message_id VARCHAR(36) NOT NULL,
+    chat_id VARCHAR(255) NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    sender VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE,
+    UNIQUE (message_id, username)
+);
+CREATE TABLE notification (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    content TEXT NOT NULL,
+    chat_id VARCHAR(255),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     FOREIGN KEY (username) REFERENCES user(username) ON DELETE CASCADE
 );
@@ -1,13 +1,36 @@
-use crate::common::{FetchHistoryMessage, PubSubError, SubscriptionMessage, UserMessage};
-use crate::database::DbManager;
-use std::collections::HashMap;
+use crate::common::{
+    preview, AckMessage, DeliveryReceipt, ErrorMessage, FetchHistoryMessage, Notification,
+    NotificationKind, PubSubError, SubscriptionMessage, SystemMessage, UserMessage,
+};
+use crate::database::{is_unavailable, DbManager};
+use crate::dedup::{Deduplicator, Seen};
+use crate::handshake::{Credentials, Identity};
+use crate::mention::parse_mentions;
+use crate::metrics::Metrics;
+use crate::rate_limit::{RateLimit, RoomRateLimiter, TokenBucket};
+use crate::validation::{validate_message_content, DEFAULT_MAX_MESSAGE_LENGTH};
+use crate::wal::{StoredMessage, WriteAheadQueue};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::broadcast::Sender;
-use tokio_websockets::Message;
+use tokio_websockets::{CloseCode, Message};
+use uuid::Uuid;
+
+const MAX_CLIENT_MSG_ID_LENGTH: usize = 64;
+// Message content is shortened to this many characters in notifications.
+const NOTIFICATION_PREVIEW_LENGTH: usize = 40;
 
 struct Subscriber {
     topic: Option<String>,
     sender: Sender<Message>,
+    // Delivery receipts are only forwarded in private chats.
+    private_chat: bool,
+    // Senders this user blocked or muted in the topic when subscribing. Only
+    // used while the database cannot be asked.
+    silenced_senders: HashSet<String>,
 }
 
 #[derive(Clone)]
@@ -15,15 +38,130 @@ pub struct Broker {
     subscribers: Arc<Mutex<HashMap<String, Subscriber>>>,
     topics: Arc<Mutex<HashMap<String, Vec<String>>>>,
     db_manager: Arc<DbManager>,
+    // Secret the REST server signs system messages with. System messages are
+    // rejected when it is not configured.
+    system_token: Option<String>,
+    max_message_length: usize,
+    connection_limit: RateLimit,
+    room_limiter: Arc<RoomRateLimiter>,
+    metrics: Arc<Metrics>,
+    // Messages accepted while the database was unavailable.
+    queue: Arc<WriteAheadQueue>,
+    dedup: Arc<Deduplicator>,
 }
 
 impl Broker {
     pub async fn new() -> Broker {
-        let db_manager = DbManager::new().await.unwrap();
+        let db_manager = Arc::new(DbManager::new().await.unwrap());
+        let wal_path = env::var("PUBSUB_WAL_PATH").unwrap_or(String::from("pubsub-wal.jsonl"));
+        let queue = Arc::new(
+            WriteAheadQueue::open(PathBuf::from(wal_path))
+                .await
+                .unwrap(),
+        );
+        let dead_letter_path = env::var("PUBSUB_DEAD_LETTER_PATH")
+            .unwrap_or(String::from("pubsub-dead-letters.jsonl"));
+        let dead_letters = Arc::new(
+            WriteAheadQueue::open(PathBuf::from(dead_letter_path))
+                .await
+                .unwrap(),
+        );
+        let retry_secs = env::var("PUBSUB_WAL_RETRY_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(5)
+            .max(1);
+        let dedup_window_secs = env::var("PUBSUB_DEDUP_WINDOW_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(300);
+        spawn_queue_writer(
+            db_manager.clone(),
+            queue.clone(),
+            dead_letters,
+            Duration::from_secs(retry_secs),
+        );
+
         Broker {
             subscribers: Arc::new(Mutex::new(HashMap::new())),
             topics: Arc::new(Mutex::new(HashMap::new())),
-            db_manager: Arc::new(db_manager),
+            db_manager,
+            system_token: env::var("PUBSUB_SYSTEM_TOKEN").ok(),
+            max_message_length: env::var("PUBSUB_MAX_MESSAGE_LENGTH")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_MAX_MESSAGE_LENGTH),
+            connection_limit: RateLimit::from_env("PUBSUB_CONNECTION", 1.0, 5.0),
+            room_limiter: Arc::new(RoomRateLimiter::new(RateLimit::from_env(
+                "PUBSUB_ROOM",
+                10.0,
+                20.0,
+            ))),
+            metrics: Arc::new(Metrics::default()),
+            queue,
+            dedup: Arc::new(Deduplicator::new(Duration::from_secs(dedup_window_secs))),
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// A rate limiter for the messages sent on a single connection.
+    pub fn connection_bucket(&self) -> TokenBucket {
+        TokenBucket::new(self.connection_limit)
+    }
+
+    /// Checks the credentials sent with a websocket upgrade request.
+    pub async fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        if let Some(token) = &credentials.system_token {
+            return match &self.system_token {
+                Some(system_token) if system_token == token => Some(Identity::System),
+                _ => None,
+            };
+        }
+
+        let (Some(username), Some(session_id)) = (&credentials.username, &credentials.session_id)
+        else {
+            return None;
+        };
+        if self
+            .db_manager
+            .is_session_id_valid(username, session_id)
+            .await
+        {
+            Some(Identity::User {
+                username: username.clone(),
+                session_id: session_id.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Whether the session a connection was opened with is still the user's
+    /// current one. While the database cannot be asked, it is assumed to be.
+    pub async fn is_session_current(&self, username: &str, session_id: &str) -> bool {
+        match self.db_manager.get_session_id(username).await {
+            Ok(current) => current.as_deref() == Some(session_id),
+            Err(e) => is_unavailable(&e),
+        }
+    }
+
+    /// Registers a user connection as soon as it is upgraded, so that
+    /// notifications reach users who are not in any conversation. A newer
+    /// connection of the same user replaces the older one.
+    pub fn connect(&self, username: &str, sender: Sender<Message>) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut topics = self.topics.lock().unwrap();
+        let subscriber = Subscriber {
+            topic: None,
+            sender,
+            private_chat: false,
+            silenced_senders: HashSet::new(),
+        };
+        if let Some(previous) = subscribers.insert(username.to_string(), subscriber) {
+            leave_topic(&mut topics, &previous, username);
         }
     }
 
@@ -38,17 +176,54 @@ impl Broker {
             .await
         {
             true => {
+                if self
+                    .db_manager
+                    .is_banned(&sub_msg.topic, &sub_msg.username)
+                    .await
+                    .unwrap_or(false)
+                {
+                    println!(
+                        "Failed to subscribe user \"{}\" to topic \"{}\": User is banned",
+                        sub_msg.username, sub_msg.topic
+                    );
+                    return Err(PubSubError::Banned);
+                }
+                // Keep conversations closed when membership cannot be checked.
+                if !self
+                    .db_manager
+                    .is_participant(&sub_msg.topic, &sub_msg.username)
+                    .await
+                    .unwrap_or(false)
+                {
+                    println!(
+                        "Failed to subscribe user \"{}\" to topic \"{}\": User is not a participant",
+                        sub_msg.username, sub_msg.topic
+                    );
+                    return Err(PubSubError::NotAParticipant);
+                }
+
+                let private_chat = self.db_manager.is_private_chat(&sub_msg.topic).await;
+                let silenced_senders = self
+                    .db_manager
+                    .get_silenced_senders(&sub_msg.topic, &sub_msg.username)
+                    .await
+                    .into_iter()
+                    .collect();
+
                 let mut subscribers = self.subscribers.lock().unwrap();
                 let subscriber: Subscriber = Subscriber {
                     topic: Some(sub_msg.topic.clone()),
                     sender,
+                    private_chat,
+                    silenced_senders,
                 };
-                subscribers.insert(sub_msg.username.clone(), subscriber);
-
                 let mut topics = self.topics.lock().unwrap();
+                if let Some(previous) = subscribers.insert(sub_msg.username.clone(), subscriber) {
+                    leave_topic(&mut topics, &previous, &sub_msg.username);
+                }
                 topics
                     .entry(sub_msg.topic.clone())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(sub_msg.username.clone());
 
                 println!(
@@ -74,17 +249,15 @@ impl Broker {
             .await
         {
             true => {
-                let mut topics = self.topics.lock().unwrap();
-                let topic_subs = topics.get_mut(&sub_msg.topic).unwrap();
-                let idx = topic_subs
-                    .iter()
-                    .position(|x| *x == sub_msg.username)
-                    .unwrap();
-                topic_subs.remove(idx);
-
                 let mut subscribers = self.subscribers.lock().unwrap();
-                let subscriber = subscribers.get_mut(&sub_msg.username).unwrap();
-                subscriber.topic = None;
+                let mut topics = self.topics.lock().unwrap();
+                // The topic may be gone already, e.g. when its room was deleted.
+                if let Some(topic_subs) = topics.get_mut(&sub_msg.topic) {
+                    topic_subs.retain(|username| *username != sub_msg.username);
+                }
+                if let Some(subscriber) = subscribers.get_mut(&sub_msg.username) {
+                    subscriber.topic = None;
+                }
                 println!(
                     "Unsubscribed user {} from topic {}",
                     sub_msg.username, sub_msg.topic
@@ -99,45 +272,495 @@ impl Broker {
         };
     }
 
-    pub fn publish(&self, user_msg: UserMessage) {
-        let subscribers = self.subscribers.lock().unwrap();
+    /// Forgets a connection that has ended. Nothing happens if the user has
+    /// connected again since, from another client.
+    pub fn disconnect(&self, username: &str, sender: &Sender<Message>) {
+        let mut subscribers = self.subscribers.lock().unwrap();
         let mut topics = self.topics.lock().unwrap();
+        let Some(subscriber) = subscribers.get(username) else {
+            return;
+        };
+        if !subscriber.sender.same_channel(sender) {
+            return;
+        }
+        leave_topic(&mut topics, subscriber, username);
+        subscribers.remove(username);
+    }
 
-        // Save the received message to db for chat history purposes.
-        self.save_message(&user_msg);
+    /// Stores the message, then delivers it to the other subscribers of its
+    /// topic. The returned ack is only sent once the message is durable.
+    ///
+    /// A message with a `client_msg_id` the sender already used within the
+    /// dedup window is not published again: it is acked with the id of the
+    /// original, or not at all while the original is still being published.
+    pub async fn publish(
+        &self,
+        user_msg: UserMessage,
+        connection_bucket: &mut TokenBucket,
+    ) -> Result<Option<AckMessage>, ErrorMessage> {
+        let Some(client_msg_id) = user_msg.client_msg_id.clone() else {
+            return self
+                .publish_new(user_msg, connection_bucket)
+                .await
+                .map(Some);
+        };
+        if client_msg_id.is_empty() || client_msg_id.len() > MAX_CLIENT_MSG_ID_LENGTH {
+            return Err(ErrorMessage {
+                error: PubSubError::InvalidMessage,
+                message: format!(
+                    "Client message ids must be between 1 and {} characters.",
+                    MAX_CLIENT_MSG_ID_LENGTH
+                ),
+                client_msg_id: None,
+            });
+        }
 
-        let msg: Message = Message::text(serde_json::to_string(&user_msg).unwrap());
+        let sender = user_msg.sender.clone();
+        match self.dedup.begin(&sender, &client_msg_id) {
+            Seen::New => (),
+            Seen::Pending => return Ok(None),
+            Seen::Stored { message_id, queued } => {
+                println!(
+                    "Acknowledged resent message {} from user {}",
+                    message_id, sender
+                );
+                return Ok(Some(AckMessage {
+                    topic: user_msg.topic,
+                    message_id,
+                    queued,
+                    client_msg_id: Some(client_msg_id),
+                }));
+            }
+        }
+
+        match self.publish_new(user_msg, connection_bucket).await {
+            Ok(ack) => {
+                self.dedup
+                    .complete(&sender, &client_msg_id, &ack.message_id, ack.queued);
+                Ok(Some(ack))
+            }
+            Err(mut err_message) => {
+                self.dedup.abandon(&sender, &client_msg_id);
+                err_message.client_msg_id = Some(client_msg_id);
+                Err(err_message)
+            }
+        }
+    }
+
+    async fn publish_new(
+        &self,
+        mut user_msg: UserMessage,
+        connection_bucket: &mut TokenBucket,
+    ) -> Result<AckMessage, ErrorMessage> {
+        // Throttle before anything touches the database.
+        if let Err(wait) = connection_bucket.try_take() {
+            self.metrics.record_throttled_connection();
+            println!(
+                "Dropped message from user {} in topic {}: Connection rate limit",
+                user_msg.sender, user_msg.topic
+            );
+            return Err(rate_limited("You are sending messages too fast.", wait));
+        }
+
+        if let Err(e) = validate_message_content(&user_msg.content, self.max_message_length) {
+            self.metrics.record_rejected();
+            println!(
+                "Rejected message from user {} in topic {}: {}",
+                user_msg.sender, user_msg.topic, e
+            );
+            return Err(ErrorMessage {
+                error: PubSubError::InvalidMessage,
+                message: e,
+                client_msg_id: None,
+            });
+        }
+
+        if let Err(e) = self.check_sender(&user_msg.topic, &user_msg.sender).await {
+            self.metrics.record_rejected();
+            let (reason, message) = match e {
+                PubSubError::Banned => ("User is banned", "You are banned from this chat room."),
+                _ => (
+                    "User is not a participant",
+                    "You are not a participant of this conversation.",
+                ),
+            };
+            println!(
+                "Rejected message from user {} in topic {}: {}",
+                user_msg.sender, user_msg.topic, reason
+            );
+            return Err(ErrorMessage {
+                error: e,
+                message: String::from(message),
+                client_msg_id: None,
+            });
+        }
+
+        // Only senders allowed in the topic spend its shared budget, so
+        // outsiders can neither drain a room nor create buckets for it.
+        if let Err(wait) = self.room_limiter.try_take(&user_msg.topic) {
+            self.metrics.record_throttled_room();
+            println!(
+                "Dropped message from user {} in topic {}: Room rate limit",
+                user_msg.sender, user_msg.topic
+            );
+            return Err(rate_limited("This conversation is too busy.", wait));
+        }
+
+        // Recipients that blocked or muted the sender do not get the message.
+        // Without the database, only what subscribers are known to have
+        // silenced is left out, and nobody is notified.
+        let silenced = match self
+            .db_manager
+            .get_silenced_recipients(&user_msg.topic, &user_msg.sender)
+            .await
+        {
+            Ok(silenced) => silenced,
+            Err(_) => self.subscribers_silencing(&user_msg.topic, &user_msg.sender),
+        };
+        let partner = self
+            .db_manager
+            .get_private_chat_partner(&user_msg.topic, &user_msg.sender)
+            .await
+            .unwrap_or_default()
+            .filter(|partner| !silenced.contains(partner));
+        let mentioned = parse_mentions(&user_msg.content);
+        let mentions: Vec<String> = match self
+            .db_manager
+            .get_participants_among(&user_msg.topic, &mentioned)
+            .await
+        {
+            Ok(participants) => participants,
+            Err(_) => self.subscribed_among(&user_msg.topic, &mentioned),
+        }
+        .into_iter()
+        .filter(|username| *username != user_msg.sender && !silenced.contains(username))
+        .collect();
+
+        let stored = StoredMessage {
+            message_id: Uuid::new_v4().to_string(),
+            topic: user_msg.topic.clone(),
+            sender: user_msg.sender.clone(),
+            content: user_msg.content.clone(),
+            sent_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            mentions,
+        };
+        let queued = self.save_message(&stored).await?;
+        self.metrics.record_published();
+        user_msg.message_id = Some(stored.message_id.clone());
+        let client_msg_id = user_msg.client_msg_id.take();
+
+        let undelivered = self.deliver(&user_msg, &silenced, partner.as_ref(), &stored.mentions);
+        for notification in &undelivered {
+            self.db_manager.save_notification(notification).await;
+        }
+        Ok(AckMessage {
+            topic: stored.topic,
+            message_id: stored.message_id,
+            queued,
+            client_msg_id,
+        })
+    }
+
+    // Sends a published message to the subscribers of its topic and notifies
+    // the private chat partner and mentioned users. Returns the notifications
+    // for users who are not connected.
+    fn deliver(
+        &self,
+        user_msg: &UserMessage,
+        silenced: &[String],
+        partner: Option<&String>,
+        mentions: &[String],
+    ) -> Vec<Notification> {
+        let subscribers = self.subscribers.lock().unwrap();
+        let mut topics = self.topics.lock().unwrap();
+        let mut undelivered = Vec::new();
+
+        let msg: Message = Message::text(serde_json::to_string(user_msg).unwrap());
 
         if let Some(topic_subs) = topics.get_mut(&user_msg.topic) {
             for subs_username in topic_subs.iter() {
                 // Send to all subscribers except for the sender itself.
-                if subs_username != &user_msg.sender {
-                    let subscriber = subscribers.get(subs_username).unwrap();
-                    let _ = subscriber.sender.send(msg.clone());
+                if subs_username != &user_msg.sender && !silenced.contains(subs_username) {
+                    if let Some(subscriber) = subscribers.get(subs_username) {
+                        let _ = subscriber.sender.send(msg.clone());
+                    }
+                }
+            }
+        }
+
+        // A private chat partner who is in another conversation gets a notice.
+        if let Some(partner) = partner {
+            let notification = Notification {
+                recipient: partner.clone(),
+                kind: NotificationKind::DirectMessage,
+                content: format!(
+                    "New message from {}: {}",
+                    user_msg.sender,
+                    preview(&user_msg.content, NOTIFICATION_PREVIEW_LENGTH)
+                ),
+                topic: Some(user_msg.topic.clone()),
+                token: None,
+            };
+            if !notify(&subscribers, &notification) {
+                undelivered.push(notification);
+            }
+        }
+        // The notice above already covers a mentioned private chat partner.
+        for mentioned in mentions {
+            if partner == Some(mentioned) {
+                continue;
+            }
+            let notification = Notification {
+                recipient: mentioned.clone(),
+                kind: NotificationKind::Mention,
+                content: format!(
+                    "{} mentioned you: {}",
+                    user_msg.sender,
+                    preview(&user_msg.content, NOTIFICATION_PREVIEW_LENGTH)
+                ),
+                topic: Some(user_msg.topic.clone()),
+                token: None,
+            };
+            if !notify(&subscribers, &notification) {
+                undelivered.push(notification);
+            }
+        }
+        undelivered
+    }
+
+    // Checks that the sender may still post in the topic. While the database
+    // is unreachable, being subscribed is enough: membership was checked when
+    // subscribing, and kicks and bans end the subscription.
+    async fn check_sender(&self, topic: &str, sender: &str) -> Result<(), PubSubError> {
+        let banned = self.db_manager.is_banned(topic, sender).await;
+        let participant = self.db_manager.is_participant(topic, sender).await;
+        match (banned, participant) {
+            (Ok(true), _) => Err(PubSubError::Banned),
+            (Ok(false), Ok(true)) => Ok(()),
+            (Err(e), _) | (_, Err(e)) if is_unavailable(&e) => {
+                let topics = self.topics.lock().unwrap();
+                match topics.get(topic) {
+                    Some(topic_subs) if topic_subs.iter().any(|u| u == sender) => Ok(()),
+                    _ => Err(PubSubError::NotAParticipant),
                 }
             }
+            _ => Err(PubSubError::NotAParticipant),
         }
     }
 
-    fn save_message(&self, user_msg: &UserMessage) {
-        let cloned_db_manager = self.db_manager.clone();
-        let cloned_user_msg = user_msg.clone();
-        tokio::spawn(async move {
-            cloned_db_manager.save_message(&cloned_user_msg).await;
-        });
+    // Subscribers of the topic who had blocked or muted the sender when they
+    // subscribed.
+    fn subscribers_silencing(&self, topic: &str, sender: &str) -> Vec<String> {
+        let subscribers = self.subscribers.lock().unwrap();
+        let topics = self.topics.lock().unwrap();
+        topics
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .filter(|username| {
+                subscribers
+                    .get(*username)
+                    .is_some_and(|subscriber| subscriber.silenced_senders.contains(sender))
+            })
+            .cloned()
+            .collect()
     }
 
-    pub async fn fetch_history(&self, hist_msg: &FetchHistoryMessage) {
+    // The subscribers of the topic among `usernames`, compared like MySQL
+    // compares them (ignoring case) and spelled as subscribed.
+    fn subscribed_among(&self, topic: &str, usernames: &[String]) -> Vec<String> {
+        let topics = self.topics.lock().unwrap();
+        topics
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .filter(|subscribed| usernames.iter().any(|u| u.eq_ignore_ascii_case(subscribed)))
+            .cloned()
+            .collect()
+    }
+
+    /// Records that the user has seen the conversation up to its latest
+    /// message, so it is not reported as unread at their next login.
+    pub async fn mark_read(&self, topic: &str, username: &str) {
+        self.db_manager.mark_read(topic, username).await;
+    }
+
+    /// Forwards a delivery receipt to the other subscribers of a private chat.
+    /// Receipts from other kinds of conversations are dropped.
+    pub fn forward_receipt(&self, receipt: &DeliveryReceipt) {
         let subscribers = self.subscribers.lock().unwrap();
-        let subscriber = subscribers.get(&hist_msg.username).unwrap();
+        let topics = self.topics.lock().unwrap();
+
+        match subscribers.get(&receipt.delivered_to) {
+            Some(subscriber)
+                if subscriber.private_chat && subscriber.topic.as_ref() == Some(&receipt.topic) => {
+            }
+            _ => return,
+        }
+
+        let msg: Message = Message::text(serde_json::to_string(receipt).unwrap());
+        if let Some(topic_subs) = topics.get(&receipt.topic) {
+            for subs_username in topic_subs.iter() {
+                if subs_username != &receipt.delivered_to {
+                    if let Some(subscriber) = subscribers.get(subs_username) {
+                        let _ = subscriber.sender.send(msg.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Delivers a notification from the REST server to its recipient, if
+    /// they are connected to a conversation.
+    pub async fn publish_notification(
+        &self,
+        mut notification: Notification,
+    ) -> Result<(), PubSubError> {
+        if self.system_token.is_none() || notification.token != self.system_token {
+            println!(
+                "Rejected notification for user {}: Invalid token",
+                notification.recipient
+            );
+            return Err(PubSubError::SubscriptionError);
+        }
+        notification.token = None;
+
+        let delivered = notify(&self.subscribers.lock().unwrap(), &notification);
+        if !delivered {
+            self.db_manager.save_notification(&notification).await;
+        }
+        Ok(())
+    }
+
+    pub fn publish_system(&self, mut sys_msg: SystemMessage) -> Result<(), PubSubError> {
+        if self.system_token.is_none() || sys_msg.token != self.system_token {
+            println!(
+                "Rejected system message for topic {}: Invalid token",
+                sys_msg.topic
+            );
+            return Err(PubSubError::SubscriptionError);
+        }
+        sys_msg.token = None;
 
-        let cloned_sender = subscriber.sender.clone();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut topics = self.topics.lock().unwrap();
+
+        let msg: Message = Message::text(serde_json::to_string(&sys_msg).unwrap());
+        if let Some(topic_subs) = topics.get(&sys_msg.topic) {
+            for subs_username in topic_subs.iter() {
+                if let Some(subscriber) = subscribers.get(subs_username) {
+                    let _ = subscriber.sender.send(msg.clone());
+                }
+            }
+        }
+
+        // The topic is gone, so nobody is subscribed to it any more and
+        // their connections are closed.
+        if sys_msg.close {
+            let close = Message::close(
+                Some(CloseCode::NORMAL_CLOSURE),
+                "The conversation was closed.",
+            );
+            for subs_username in topics.remove(&sys_msg.topic).unwrap_or_default() {
+                if let Some(subscriber) = subscribers.get_mut(&subs_username) {
+                    subscriber.topic = None;
+                    let _ = subscriber.sender.send(close.clone());
+                }
+            }
+        } else if let Some(removed) = &sys_msg.remove {
+            // Usernames compare like they do in the database.
+            let mut removed_subs = Vec::new();
+            if let Some(topic_subs) = topics.get_mut(&sys_msg.topic) {
+                topic_subs.retain(|username| {
+                    let keep = !username.eq_ignore_ascii_case(removed);
+                    if !keep {
+                        removed_subs.push(username.clone());
+                    }
+                    keep
+                });
+            }
+            let close = Message::close(
+                Some(CloseCode::NORMAL_CLOSURE),
+                "You were removed from the conversation.",
+            );
+            for subs_username in removed_subs {
+                if let Some(subscriber) = subscribers.get_mut(&subs_username) {
+                    subscriber.topic = None;
+                    let _ = subscriber.sender.send(close.clone());
+                }
+            }
+        }
+        println!("Published system message to topic {}", sys_msg.topic);
+        Ok(())
+    }
+
+    // Writes the message to the database, or to the local queue when the
+    // database cannot be reached. Returns whether it was queued. While older
+    // messages are still queued, new ones go after them to keep the order.
+    async fn save_message(&self, stored: &StoredMessage) -> Result<bool, ErrorMessage> {
+        if self.queue.is_empty().await {
+            match self.db_manager.save_message(stored).await {
+                Ok(()) => return Ok(false),
+                Err(e) if is_unavailable(&e) => (),
+                Err(_) => {
+                    self.metrics.record_rejected();
+                    return Err(ErrorMessage {
+                        error: PubSubError::InvalidMessage,
+                        message: String::from("Your message could not be saved."),
+                        client_msg_id: None,
+                    });
+                }
+            }
+        }
+
+        match self.queue.push(stored).await {
+            Ok(()) => {
+                println!(
+                    "Queued message {} from user {} in topic {}",
+                    stored.message_id, stored.sender, stored.topic
+                );
+                Ok(true)
+            }
+            Err(e) => {
+                println!(
+                    "Failed to queue message from user {} in topic {}: {}",
+                    stored.sender, stored.topic, e
+                );
+                self.metrics.record_rejected();
+                Err(ErrorMessage {
+                    error: PubSubError::Unavailable,
+                    message: String::from(
+                        "Your message could not be saved. Please try again later.",
+                    ),
+                    client_msg_id: None,
+                })
+            }
+        }
+    }
+
+    pub async fn fetch_history(&self, hist_msg: &FetchHistoryMessage) {
+        let cloned_sender = {
+            let subscribers = self.subscribers.lock().unwrap();
+            // The connection may have closed since the request was read.
+            let Some(subscriber) = subscribers.get(&hist_msg.username) else {
+                println!("No connection to send history to: {}", hist_msg.username);
+                return;
+            };
+            subscriber.sender.clone()
+        };
         let cloned_db_manager = self.db_manager.clone();
         let cloned_hist_msg = hist_msg.clone();
 
         tokio::spawn(async move {
             match cloned_db_manager
-                .get_message_history(cloned_hist_msg.topic, cloned_hist_msg.num_messages)
+                .get_message_history(
+                    cloned_hist_msg.topic,
+                    &cloned_hist_msg.username,
+                    cloned_hist_msg.num_messages,
+                )
                 .await
             {
                 Some(messages) => {
@@ -146,6 +769,8 @@ impl Broker {
                             topic: hist_msg.chat_id.clone(),
                             sender: hist_msg.username.clone(),
                             content: hist_msg.message.clone(),
+                            message_id: hist_msg.message_id.clone(),
+                            client_msg_id: None,
                         };
                         let msg: Message = Message::text(serde_json::to_string(&user_msg).unwrap());
                         let _ = cloned_sender.send(msg.clone());
@@ -158,3 +783,98 @@ impl Broker {
         });
     }
 }
+
+/// Sends the notification to its recipient unless they are in the
+/// conversation it is about, and returns false if they are not connected.
+fn notify(subscribers: &HashMap<String, Subscriber>, notification: &Notification) -> bool {
+    let Some(subscriber) = subscribers.get(&notification.recipient) else {
+        return false;
+    };
+    if notification.topic.is_some() && subscriber.topic == notification.topic {
+        return true;
+    }
+    let msg: Message = Message::text(serde_json::to_string(notification).unwrap());
+    subscriber.sender.send(msg).is_ok()
+}
+
+// Removes the user from the topic the subscriber was in, if any.
+fn leave_topic(topics: &mut HashMap<String, Vec<String>>, subscriber: &Subscriber, username: &str) {
+    if let Some(topic_subs) = subscriber.topic.as_ref().and_then(|t| topics.get_mut(t)) {
+        topic_subs.retain(|subscribed| subscribed != username);
+    }
+}
+
+fn rate_limited(reason: &str, wait: Duration) -> ErrorMessage {
+    ErrorMessage {
+        error: PubSubError::RateLimited,
+        message: format!(
+            "{} Try again in {:.1} seconds.",
+            reason,
+            wait.as_secs_f64().max(0.1)
+        ),
+        client_msg_id: None,
+    }
+}
+
+// Periodically writes queued messages to the database, oldest first, until
+// the queue is empty or the database is unreachable again. Their senders were
+// told they were sent, so messages the database rejects are moved to the
+// dead-letter file instead of being dropped.
+fn spawn_queue_writer(
+    db_manager: Arc<DbManager>,
+    queue: Arc<WriteAheadQueue>,
+    dead_letters: Arc<WriteAheadQueue>,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if queue.is_empty().await {
+                continue;
+            }
+            let messages = match queue.peek().await {
+                Ok(messages) => messages,
+                Err(e) => {
+                    println!("Failed to read the message queue: {}", e);
+                    continue;
+                }
+            };
+
+            let mut written = 0;
+            for message in &messages {
+                // Retrying will not help these, so they are set aside.
+                let dead_letter = match message {
+                    Ok(message) => match db_manager.save_message(message).await {
+                        Ok(()) => None,
+                        Err(e) if is_unavailable(&e) => break,
+                        Err(e) => {
+                            println!(
+                                "Failed to write queued message {}, moving it to the dead-letter file: {}",
+                                message.message_id, e
+                            );
+                            dead_letters.push(message).await.err()
+                        }
+                    },
+                    Err(line) => {
+                        println!(
+                            "Moving unreadable entry in the message queue to the dead-letter file"
+                        );
+                        dead_letters.push_line(line).await.err()
+                    }
+                };
+                if let Some(e) = dead_letter {
+                    // Left in the queue, to be tried again.
+                    println!("Failed to write to the dead-letter file: {}", e);
+                    break;
+                }
+                written += 1;
+            }
+            if written > 0 {
+                match queue.remove_first(written).await {
+                    Ok(()) => println!("Wrote {} queued messages to the database", written),
+                    Err(e) => println!("Failed to update the message queue: {}", e),
+                }
+            }
+        }
+    });
+}
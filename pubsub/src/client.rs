@@ -1,31 +1,66 @@
 use crate::common::{
-    ErrorMessage, FetchHistoryMessage, SubscriptionAction, SubscriptionMessage, UserMessage,
-    PUBSUB_SERVER_ADDRESS,
+    preview, AckMessage, DeliveryReceipt, ErrorMessage, FetchHistoryMessage, Notification,
+    NotificationKind, PubSubError, SubscriptionAction, SubscriptionMessage, SystemMessage,
+    UserMessage, PUBSUB_SERVER_ADDRESS,
 };
+use crate::mention::highlight_mentions;
+use crate::tls::client_connector;
+use crate::validation::{validate_message_content, MAX_MESSAGE_LENGTH_LIMIT};
 use futures_util::stream::StreamExt;
+use futures_util::FutureExt;
 use futures_util::SinkExt;
-use http::Uri;
+use http::header::AUTHORIZATION;
+use http::{HeaderName, HeaderValue, Uri};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::io;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::TcpStream;
 use tokio_websockets::tls::MaybeTlsStream;
 use tokio_websockets::{ClientBuilder, Error, Message, WebSocketStream};
+use uuid::Uuid;
+
+// A message that has not been acked after this long is sent again. The broker
+// recognizes the copy by its client message id and does not publish it twice.
+const RESEND_AFTER: Duration = Duration::from_secs(3);
+const MAX_SEND_ATTEMPTS: u32 = 3;
+// Number of sent messages remembered to show delivery receipts for.
+const MAX_SENT_MESSAGES: usize = 100;
+// Outgoing messages are shortened to this many characters in status lines.
+const STATUS_PREVIEW_LENGTH: usize = 40;
+
+struct PendingMessage {
+    message: UserMessage,
+    sent_at: Instant,
+    attempts: u32,
+}
 
 pub struct PubSubClient {
     username: String,
     session_id: String,
     topic: Option<String>,
     stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    // Users blocked or muted in the current conversation. The broker already
+    // holds back their messages; this also hides any that slip through.
+    ignored_users: HashSet<String>,
+    // Messages sent but not yet acked, by client message id.
+    pending: HashMap<String, PendingMessage>,
+    // Acked messages that may still get a delivery receipt: (message id, content).
+    sent: VecDeque<(String, String)>,
 }
 
 impl PubSubClient {
     pub async fn new(username: String, session_id: String) -> Result<PubSubClient, Error> {
-        let client_builder = ClientBuilder::from_uri(Uri::from_static(PUBSUB_SERVER_ADDRESS));
-        match client_builder.connect().await {
-            Ok((stream, _)) => Ok(PubSubClient {
+        match connect(&username, &session_id).await {
+            Ok(stream) => Ok(PubSubClient {
                 username,
                 session_id,
                 topic: None,
                 stream,
+                ignored_users: HashSet::new(),
+                pending: HashMap::new(),
+                sent: VecDeque::new(),
             }),
             Err(e) => {
                 println!("Failed to connect to the pub-sub messaging server. {e}");
@@ -34,15 +69,22 @@ impl PubSubClient {
         }
     }
 
+    pub fn set_session_id(&mut self, session_id: String) {
+        self.session_id = session_id;
+    }
+
+    pub fn set_ignored_users(&mut self, users: Vec<String>) {
+        self.ignored_users = users.into_iter().collect();
+    }
+
     pub async fn reconnect(&mut self) -> Result<(), Error> {
-        let client_builder = ClientBuilder::from_uri(Uri::from_static(PUBSUB_SERVER_ADDRESS));
         // Make sure existing stream is closed first.
         let _ = self.stream.close().await;
 
-        match client_builder.connect().await {
-            Ok((stream, _)) => {
+        match connect(&self.username, &self.session_id).await {
+            Ok(stream) => {
                 self.stream = stream;
-                return Ok(());
+                Ok(())
             }
             Err(e) => {
                 println!("Failed to connect to the pub-sub messaging server. {e}");
@@ -51,6 +93,19 @@ impl PubSubClient {
         }
     }
 
+    /// Prints the notifications that arrived while no conversation was open,
+    /// without waiting for more.
+    pub fn show_notifications(&mut self) {
+        while let Some(Some(Ok(msg))) = self.stream.next().now_or_never() {
+            if let Some(notification) = msg
+                .as_text()
+                .and_then(|text| serde_json::from_str::<Notification>(text).ok())
+            {
+                println!(">>> {} <<<", notification.content);
+            }
+        }
+    }
+
     pub async fn subscribe(&mut self, topic: String) -> Result<(), Error> {
         let subscription_message: SubscriptionMessage = SubscriptionMessage {
             topic: topic.clone(),
@@ -86,10 +141,14 @@ impl PubSubClient {
     }
 
     pub async fn fetch_history(&mut self) -> Result<(), Error> {
+        self.fetch_recent_messages(10).await
+    }
+
+    pub async fn fetch_recent_messages(&mut self, num_messages: usize) -> Result<(), Error> {
         let fetch_history_message: FetchHistoryMessage = FetchHistoryMessage {
             topic: self.topic.clone().unwrap(),
             username: self.username.clone(),
-            num_messages: 10,
+            num_messages,
         };
         let message = Message::text(serde_json::to_string(&fetch_history_message).unwrap());
         match self.stream.send(message).await {
@@ -101,6 +160,7 @@ impl PubSubClient {
     pub async fn start(&mut self) -> Result<(), Error> {
         let stdin = tokio::io::stdin();
         let mut stdin = BufReader::new(stdin).lines();
+        let mut resend_ticker = tokio::time::interval(Duration::from_secs(1));
 
         // Consider using tokio::spawn instead of loop + tokio::select!
         loop {
@@ -108,22 +168,58 @@ impl PubSubClient {
                 incoming = self.stream.next() => {
                     match incoming {
                         Some(Ok(msg)) => {
-                            match msg.as_text() {
-                                Some(text) => {
-                                    if let Ok(err_msg) = serde_json::from_str::<ErrorMessage>(&text) {
-                                        println!("Error: {} -> {}", err_msg.error, err_msg.message);
+                            if let Some(text) = msg.as_text() {
+                                if let Ok(err_msg) = serde_json::from_str::<ErrorMessage>(text) {
+                                    if let Some(client_msg_id) = &err_msg.client_msg_id {
+                                        self.pending.remove(client_msg_id);
+                                    }
+                                    println!("Error: {} -> {}", err_msg.error, err_msg.message);
+                                    if let PubSubError::SubscriptionError | PubSubError::Banned | PubSubError::NotAParticipant = err_msg.error {
+                                        println!("Press enter key to exit.");
+                                        // Nothing more can be sent on this connection.
+                                        self.pending.clear();
+                                        self.stream.close().await?;
+                                    }
+                                } else if let Ok(notification) = serde_json::from_str::<Notification>(text) {
+                                    // Shown on its own line, apart from the conversation.
+                                    println!(">>> {} <<<", notification.content);
+                                } else if let Ok(user_msg) = serde_json::from_str::<UserMessage>(text) {
+                                    if !self.ignored_users.contains(&user_msg.sender) {
+                                        println!("{}: {}", user_msg.sender, highlight_mentions(&user_msg.content, &self.username));
+                                        self.send_receipt(&user_msg).await?;
+                                    }
+                                } else if let Ok(sys_msg) = serde_json::from_str::<SystemMessage>(text) {
+                                    println!("*** {} ***", sys_msg.content);
+                                    let removed = sys_msg.remove.as_ref().is_some_and(|username| username.eq_ignore_ascii_case(&self.username));
+                                    if sys_msg.close || removed {
                                         println!("Press enter key to exit.");
+                                        self.pending.clear();
                                         self.stream.close().await?;
-                                    } else if let Ok(user_msg) = serde_json::from_str::<UserMessage>(&text) {
-                                        println!("{}: {}", user_msg.sender, user_msg.content);
-                                    } else {
-                                        println!("Unable to parse received message: {text}");
                                     }
+                                } else if let Ok(receipt) = serde_json::from_str::<DeliveryReceipt>(text) {
+                                    if let Some(idx) = self.sent.iter().position(|(message_id, _)| *message_id == receipt.message_id) {
+                                        let (_, content) = self.sent.remove(idx).unwrap();
+                                        print_status(&format!("delivered to {}", receipt.delivered_to), &content);
+                                    }
+                                } else if let Ok(ack_msg) = serde_json::from_str::<AckMessage>(text) {
+                                    // A resent message can be acked twice; only the first ack counts.
+                                    let pending = ack_msg.client_msg_id.as_ref().and_then(|client_msg_id| self.pending.remove(client_msg_id));
+                                    if let Some(pending) = pending {
+                                        print_status("sent", &pending.message.content);
+                                        if ack_msg.queued {
+                                            println!("(Message sent. It will show up in the history shortly.)");
+                                        }
+                                        if self.sent.len() >= MAX_SENT_MESSAGES {
+                                            self.sent.pop_front();
+                                        }
+                                        self.sent.push_back((ack_msg.message_id, pending.message.content));
+                                    }
+                                } else {
+                                    println!("Unable to parse received message: {text}");
                                 }
-                                None => (),
                             }
                         },
-                        Some(Err(err)) => return Err(err.into()),
+                        Some(Err(err)) => return Err(err),
                         None => return Ok(()),
                     }
                 }
@@ -138,23 +234,38 @@ impl PubSubClient {
                                 println!(":help --> Show chat command options");
                                 println!(":exit --> Leave the chat");
                                 println!(":history --> Show the last 10 messages in the chat");
-                                ()
+
                             } else if line == ":exit" {
                                 println!("Leaving the chat...");
                                 self.unsubscribe().await?;
+                                self.pending.clear();
                                 self.stream.close().await?
                             } else if line == ":history" {
                                 println!("Fetching chat history...");
                                 self.fetch_history().await?
+                            } else if let Err(e) = validate_message_content(&line, MAX_MESSAGE_LENGTH_LIMIT) {
+                                // The broker enforces its own, possibly lower, length limit.
+                                println!("Error: {}", e);
                             } else {
                                 let user_message = self.create_user_message(line.to_string());
                                 let message = Message::text(serde_json::to_string(&user_message).unwrap());
-                                self.stream.send(message).await?
+                                self.stream.send(message).await?;
+                                print_status("pending", &user_message.content);
+                                if let Some(client_msg_id) = user_message.client_msg_id.clone() {
+                                    self.pending.insert(client_msg_id, PendingMessage {
+                                        message: user_message,
+                                        sent_at: Instant::now(),
+                                        attempts: 1,
+                                    });
+                                }
                             }
                         },
                         Err(err) => return Err(err.into()),
                     }
                 }
+                _ = resend_ticker.tick() => {
+                    self.resend_pending().await?
+                }
             }
         }
     }
@@ -164,6 +275,170 @@ impl PubSubClient {
             topic: self.topic.clone().unwrap(),
             sender: self.username.clone(),
             content,
+            message_id: None,
+            client_msg_id: Some(Uuid::new_v4().to_string()),
         }
     }
+
+    // Tells the sender of a message that it was received. The broker only
+    // passes this on in private chats.
+    async fn send_receipt(&mut self, user_msg: &UserMessage) -> Result<(), Error> {
+        let Some(message_id) = &user_msg.message_id else {
+            return Ok(());
+        };
+        if user_msg.sender == self.username {
+            return Ok(());
+        }
+        let receipt = DeliveryReceipt {
+            topic: user_msg.topic.clone(),
+            message_id: message_id.clone(),
+            delivered_to: self.username.clone(),
+        };
+        let message = Message::text(serde_json::to_string(&receipt).unwrap());
+        self.stream.send(message).await
+    }
+
+    async fn resend_pending(&mut self) -> Result<(), Error> {
+        let overdue: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.sent_at.elapsed() >= RESEND_AFTER)
+            .map(|(client_msg_id, _)| client_msg_id.clone())
+            .collect();
+        for client_msg_id in overdue {
+            let Some(pending) = self.pending.get_mut(&client_msg_id) else {
+                continue;
+            };
+            if pending.attempts >= MAX_SEND_ATTEMPTS {
+                println!(
+                    "Error: Message could not be sent -> {}",
+                    pending.message.content
+                );
+                self.pending.remove(&client_msg_id);
+                continue;
+            }
+            pending.attempts += 1;
+            pending.sent_at = Instant::now();
+            let message = Message::text(serde_json::to_string(&pending.message).unwrap());
+            self.stream.send(message).await?;
+        }
+        Ok(())
+    }
+}
+
+// Prints the status of an outgoing message, e.g. "[sent] hello".
+fn print_status(status: &str, content: &str) {
+    println!("[{}] {}", status, preview(content, STATUS_PREVIEW_LENGTH));
+}
+
+// The broker authenticates the websocket upgrade itself, so the credentials
+// go along with the upgrade request. The server address and the CA bundle
+// used for wss:// come from PUBSUB_URL and PUBSUB_CA_PATH.
+async fn connect(
+    username: &str,
+    session_id: &str,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Error> {
+    let server_address =
+        env::var("PUBSUB_URL").unwrap_or_else(|_| PUBSUB_SERVER_ADDRESS.to_string());
+    let uri: Uri = server_address
+        .parse()
+        .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+    let client_builder = ClientBuilder::from_uri(uri)
+        .add_header(HeaderName::from_static("username"), header_value(username)?)
+        .add_header(
+            HeaderName::from_static("session_id"),
+            header_value(session_id)?,
+        );
+    let ca_path = env::var("PUBSUB_CA_PATH").ok();
+    connect_with_ca(client_builder, ca_path.as_deref()).await
+}
+
+// Without a CA bundle, wss:// connections are verified against the system's
+// root certificates.
+async fn connect_with_ca(
+    client_builder: ClientBuilder<'_>,
+    ca_path: Option<&str>,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Error> {
+    let (stream, _) = match ca_path {
+        Some(ca_path) => {
+            let connector = client_connector(ca_path)?;
+            client_builder.connector(&connector).connect().await?
+        }
+        None => client_builder.connect().await?,
+    };
+    Ok(stream)
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value)
+        .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))
+}
+
+/// Sends a system message to every subscriber of `topic`. Used by the REST
+/// server, which has to know the broker's `PUBSUB_SYSTEM_TOKEN`.
+pub async fn publish_system_message(
+    server_address: &str,
+    ca_path: Option<&str>,
+    token: &str,
+    topic: &str,
+    content: &str,
+    close: bool,
+    remove: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let system_message = SystemMessage {
+        topic: topic.to_string(),
+        content: content.to_string(),
+        close,
+        remove: remove.map(String::from),
+        token: Some(token.to_string()),
+    };
+    send_as_system(
+        server_address,
+        ca_path,
+        token,
+        serde_json::to_string(&system_message).unwrap(),
+    )
+    .await
+}
+
+/// Sends a notification to a single user, wherever they are. Used by the REST
+/// server, like `publish_system_message`.
+pub async fn publish_notification(
+    server_address: &str,
+    ca_path: Option<&str>,
+    token: &str,
+    recipient: &str,
+    kind: NotificationKind,
+    content: &str,
+    topic: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let notification = Notification {
+        recipient: recipient.to_string(),
+        kind,
+        content: content.to_string(),
+        topic: topic.map(String::from),
+        token: Some(token.to_string()),
+    };
+    send_as_system(
+        server_address,
+        ca_path,
+        token,
+        serde_json::to_string(&notification).unwrap(),
+    )
+    .await
+}
+
+async fn send_as_system(
+    server_address: &str,
+    ca_path: Option<&str>,
+    token: &str,
+    text: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let uri: Uri = server_address.parse()?;
+    let client_builder = ClientBuilder::from_uri(uri)
+        .add_header(AUTHORIZATION, header_value(&format!("Bearer {}", token))?);
+    let mut stream = connect_with_ca(client_builder, ca_path).await?;
+    stream.send(Message::text(text)).await?;
+    stream.close().await?;
+    Ok(())
 }
@@ -4,9 +4,23 @@ use std::fmt;
 pub const PUBSUB_HOST_PORT: &str = "0.0.0.0:8080";
 pub const PUBSUB_SERVER_ADDRESS: &str = "ws://127.0.0.1:8080";
 
+/// Shortens `content` to `max_chars` characters for one-line displays.
+pub(crate) fn preview(content: &str, max_chars: usize) -> String {
+    let mut preview: String = content.chars().take(max_chars).collect();
+    if content.chars().count() > max_chars {
+        preview.push_str("...");
+    }
+    preview
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum PubSubError {
     SubscriptionError,
+    InvalidMessage,
+    Banned,
+    NotAParticipant,
+    RateLimited,
+    Unavailable,
 }
 
 impl fmt::Display for PubSubError {
@@ -19,6 +33,9 @@ impl fmt::Display for PubSubError {
 pub struct ErrorMessage {
     pub error: PubSubError,
     pub message: String,
+    /// The client message id of the message the error is about, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_msg_id: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -47,4 +64,77 @@ pub struct UserMessage {
     pub topic: String,
     pub sender: String,
     pub content: String,
+    /// Assigned by the broker once the message is stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Chosen by the sending client so that a resent copy of the message is
+    /// recognized. Not forwarded to other subscribers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_msg_id: Option<String>,
+}
+
+/// Sent back to the sender of a message once it has been stored.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AckMessage {
+    pub topic: String,
+    pub message_id: String,
+    /// Set when the database was unavailable and the message was queued
+    /// locally; it shows up in the history once the queue is written.
+    #[serde(default)]
+    pub queued: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_msg_id: Option<String>,
+}
+
+/// Sent by a client in a private chat once it has received a message, and
+/// forwarded by the broker to the other user, who sent the message.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DeliveryReceipt {
+    pub topic: String,
+    pub message_id: String,
+    /// Set by the broker to the user whose connection sent the receipt.
+    pub delivered_to: String,
+}
+
+/// Announcement about a topic (e.g. a chat room being edited or deleted) sent
+/// by the REST server rather than by a user.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SystemMessage {
+    pub topic: String,
+    pub content: String,
+    /// Set when the topic no longer exists and subscribers have to leave it.
+    #[serde(default)]
+    pub close: bool,
+    /// A user who was removed from the conversation (kicked, banned or taken
+    /// out of a group) and is unsubscribed from it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remove: Option<String>,
+    /// Shared secret proving the message comes from the REST server. It is
+    /// stripped before the message is forwarded to subscribers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub enum NotificationKind {
+    PrivateChat,
+    DirectMessage,
+    Mention,
+    Invite,
+}
+
+/// Event for a single user about a conversation other than the one they are
+/// in, delivered whatever topic they are subscribed to. Sent by the broker
+/// itself or, with the system token, by the REST server.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Notification {
+    pub recipient: String,
+    pub kind: NotificationKind,
+    pub content: String,
+    /// The conversation the event is about, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    /// Same as `SystemMessage::token`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
 }
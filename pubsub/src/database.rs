@@ -1,5 +1,6 @@
-use crate::common::UserMessage;
-use sqlx::{mysql::MySqlPool, Error, FromRow};
+use crate::common::Notification;
+use crate::wal::StoredMessage;
+use sqlx::{mysql::MySqlPool, Error, FromRow, Row};
 use std::env;
 
 #[derive(FromRow)]
@@ -12,6 +13,7 @@ pub struct User {
 
 #[derive(FromRow)]
 pub struct ChatMessage {
+    pub message_id: Option<String>,
     pub chat_id: String,
     pub username: String,
     pub message: String,
@@ -40,8 +42,9 @@ impl DbManager {
     }
 
     pub async fn get_user(&self, username: &String) -> Option<User> {
-        let query = format!("SELECT * FROM user WHERE username = \"{}\";", username);
-        let result = sqlx::query_as::<_, User>(&query)
+        let query = "SELECT * FROM user WHERE username = ?";
+        let result = sqlx::query_as::<_, User>(query)
+            .bind(username)
             .fetch_one(&self.conn_pool)
             .await;
         match result {
@@ -75,39 +78,369 @@ impl DbManager {
         is_valid
     }
 
-    pub async fn save_message(&self, user_msg: &UserMessage) {
+    /// The user's current session id, or None when they are logged out or do
+    /// not exist.
+    pub async fn get_session_id(&self, username: &str) -> Result<Option<String>, Error> {
+        let query = "SELECT session_id FROM user WHERE username = ?";
+        let result = sqlx::query(query)
+            .bind(username)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => Ok(row.and_then(|row| row.get("session_id"))),
+            Err(e) => {
+                println!("Error querying session of user {} : {}", username, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns true if the user is banned from the chat room with the given topic.
+    pub async fn is_banned(&self, topic: &str, username: &str) -> Result<bool, Error> {
+        let query = r#"
+            SELECT rb.id FROM room_ban rb
+            JOIN chat_room cr ON rb.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rb.username = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(topic)
+            .bind(username)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => Ok(row.is_some()),
+            Err(e) => {
+                println!("Error querying room_ban table for {} : {}", username, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns true if the user takes part in the conversation with the given
+    /// topic: one of the two users of a private chat, a participant of a group
+    /// chat or a member of a chat room.
+    pub async fn is_participant(&self, topic: &str, username: &str) -> Result<bool, Error> {
+        let query = r#"
+            SELECT CAST((
+                EXISTS (
+                    SELECT 1 FROM private_chat
+                    WHERE chat_id = ? AND (user1 = ? OR user2 = ?)
+                )
+                OR EXISTS (
+                    SELECT 1 FROM group_chat gc
+                    JOIN group_chat_member gcm ON gcm.group_id = gc.id
+                    WHERE gc.chat_id = ? AND gcm.username = ?
+                )
+                OR EXISTS (
+                    SELECT 1 FROM chat_room cr
+                    JOIN room_member rm ON rm.room_id = cr.id
+                    WHERE cr.chat_room_id = ? AND rm.username = ?
+                )
+            ) AS SIGNED) AS allowed
+            "#;
+        let result = sqlx::query(query)
+            .bind(topic)
+            .bind(username)
+            .bind(username)
+            .bind(topic)
+            .bind(username)
+            .bind(topic)
+            .bind(username)
+            .fetch_one(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => Ok(row.get::<i64, _>("allowed") != 0),
+            Err(e) => {
+                println!(
+                    "Error checking if {} takes part in {} : {}",
+                    username, topic, e
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns true if the topic is a private chat between two users.
+    pub async fn is_private_chat(&self, topic: &str) -> bool {
+        let result = sqlx::query("SELECT 1 FROM private_chat WHERE chat_id = ?")
+            .bind(topic)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => row.is_some(),
+            Err(e) => {
+                println!("Error querying private_chat table for {} : {}", topic, e);
+                false
+            }
+        }
+    }
+
+    /// The other user of the private chat with the given topic, or `None` if
+    /// the topic is not a private chat of `username`.
+    pub async fn get_private_chat_partner(
+        &self,
+        topic: &str,
+        username: &str,
+    ) -> Result<Option<String>, Error> {
+        let query = r#"
+            SELECT IF(user1 = ?, user2, user1) AS partner FROM private_chat
+            WHERE chat_id = ? AND (user1 = ? OR user2 = ?)
+            "#;
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(topic)
+            .bind(username)
+            .bind(username)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => Ok(row.map(|row| row.get("partner"))),
+            Err(e) => {
+                println!("Error querying private_chat table for {} : {}", topic, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// The users among `usernames` that take part in the conversation with the
+    /// given topic, spelled as in the database.
+    pub async fn get_participants_among(
+        &self,
+        topic: &str,
+        usernames: &[String],
+    ) -> Result<Vec<String>, Error> {
+        if usernames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; usernames.len()].join(", ");
         let query = format!(
-            "INSERT INTO chat_message (chat_id, username, message) VALUES (\"{}\", \"{}\", \"{}\");",
-            user_msg.topic, user_msg.sender, user_msg.content
+            r#"
+            SELECT p.username FROM (
+                SELECT user1 AS username FROM private_chat WHERE chat_id = ?
+                UNION
+                SELECT user2 FROM private_chat WHERE chat_id = ?
+                UNION
+                SELECT gcm.username FROM group_chat gc
+                JOIN group_chat_member gcm ON gcm.group_id = gc.id
+                WHERE gc.chat_id = ?
+                UNION
+                SELECT rm.username FROM chat_room cr
+                JOIN room_member rm ON rm.room_id = cr.id
+                WHERE cr.chat_room_id = ?
+            ) AS p
+            WHERE p.username IN ({})
+            "#,
+            placeholders
         );
+        let mut query = sqlx::query(&query)
+            .bind(topic)
+            .bind(topic)
+            .bind(topic)
+            .bind(topic);
+        for username in usernames {
+            query = query.bind(username);
+        }
+        match query.fetch_all(&self.conn_pool).await {
+            Ok(rows) => Ok(rows.iter().map(|row| row.get("username")).collect()),
+            Err(e) => {
+                println!("Error querying participants of {} : {}", topic, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Users who do not want messages from `sender` in the conversation with
+    /// the given topic: everyone who blocked the sender, and, in a chat room,
+    /// everyone who muted the sender there.
+    pub async fn get_silenced_recipients(
+        &self,
+        topic: &str,
+        sender: &str,
+    ) -> Result<Vec<String>, Error> {
+        let query = r#"
+            SELECT blocker AS username FROM user_block WHERE blocked = ?
+            UNION
+            SELECT rmu.username FROM room_mute rmu
+            JOIN chat_room cr ON rmu.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rmu.muted = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(sender)
+            .bind(topic)
+            .bind(sender)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(rows) => Ok(rows.iter().map(|row| row.get("username")).collect()),
+            Err(e) => {
+                println!(
+                    "Error querying blocks and mutes of {} in {} : {}",
+                    sender, topic, e
+                );
+                Err(e)
+            }
+        }
+    }
 
-        let result = sqlx::query(&query).execute(&self.conn_pool).await;
+    /// The reverse of `get_silenced_recipients`: the senders whose messages
+    /// `username` does not want in the conversation with the given topic.
+    pub async fn get_silenced_senders(&self, topic: &str, username: &str) -> Vec<String> {
+        let query = r#"
+            SELECT blocked AS username FROM user_block WHERE blocker = ?
+            UNION
+            SELECT rmu.muted FROM room_mute rmu
+            JOIN chat_room cr ON rmu.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rmu.username = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(topic)
+            .bind(username)
+            .fetch_all(&self.conn_pool)
+            .await;
         match result {
-            Ok(_) => (),
+            Ok(rows) => rows.iter().map(|row| row.get("username")).collect(),
             Err(e) => {
                 println!(
-                    "Error inserting message from user {} in chat {} : {}",
-                    user_msg.sender,
-                    user_msg.topic,
-                    e.to_string()
+                    "Error querying blocks and mutes by {} in {} : {}",
+                    username, topic, e
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Marks everything in the conversation's history as read by the user.
+    pub async fn mark_read(&self, topic: &str, username: &str) {
+        let query = r#"
+            INSERT INTO last_read (username, chat_id, last_message_id)
+            SELECT ?, ?, COALESCE(MAX(id), 0) FROM chat_message WHERE chat_id = ?
+            ON DUPLICATE KEY UPDATE
+                last_message_id = GREATEST(last_message_id, VALUES(last_message_id))
+            "#;
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(topic)
+            .bind(topic)
+            .execute(&self.conn_pool)
+            .await;
+        if let Err(e) = result {
+            println!(
+                "Error updating last_read table for {} in {} : {}",
+                username, topic, e
+            );
+        }
+    }
+
+    /// Keeps a notification for a user who is not connected, so that it can
+    /// be shown at their next login.
+    pub async fn save_notification(&self, notification: &Notification) {
+        let query =
+            "INSERT INTO notification (username, kind, content, chat_id) VALUES (?, ?, ?, ?)";
+        let result = sqlx::query(query)
+            .bind(&notification.recipient)
+            .bind(format!("{:?}", notification.kind))
+            .bind(&notification.content)
+            .bind(&notification.topic)
+            .execute(&self.conn_pool)
+            .await;
+        if let Err(e) = result {
+            println!(
+                "Error inserting notification for {} : {}",
+                notification.recipient, e
+            );
+        }
+    }
+
+    /// Writes the message and its mentions to the chat history. Writing the
+    /// same message again is a no-op, so queued messages can be retried safely.
+    pub async fn save_message(&self, message: &StoredMessage) -> Result<(), Error> {
+        // The message and its mentions are stored together or not at all;
+        // dropping the transaction on an error rolls it back.
+        let mut tx = self.conn_pool.begin().await?;
+
+        let query = r#"
+            INSERT INTO chat_message (message_id, chat_id, username, message, timestamp)
+            VALUES (?, ?, ?, ?, FROM_UNIXTIME(?))
+            ON DUPLICATE KEY UPDATE message_id = message_id
+            "#;
+        let result = sqlx::query(query)
+            .bind(&message.message_id)
+            .bind(&message.topic)
+            .bind(&message.sender)
+            .bind(&message.content)
+            .bind(message.sent_at)
+            .execute(&mut *tx)
+            .await;
+        if let Err(e) = result {
+            println!(
+                "Error inserting message from user {} in chat {} : {}",
+                message.sender, message.topic, e
+            );
+            return Err(e);
+        }
+
+        let query = r#"
+            INSERT IGNORE INTO mention (message_id, chat_id, username, sender, created_at)
+            VALUES (?, ?, ?, ?, FROM_UNIXTIME(?))
+            "#;
+        for username in &message.mentions {
+            let result = sqlx::query(query)
+                .bind(&message.message_id)
+                .bind(&message.topic)
+                .bind(username)
+                .bind(&message.sender)
+                .bind(message.sent_at)
+                .execute(&mut *tx)
+                .await;
+            if let Err(e) = result {
+                println!(
+                    "Error inserting mention of user {} in chat {} : {}",
+                    username, message.topic, e
                 );
+                return Err(e);
             }
         }
+        if let Err(e) = tx.commit().await {
+            println!(
+                "Error saving message from user {} in chat {} : {}",
+                message.sender, message.topic, e
+            );
+            return Err(e);
+        }
+        Ok(())
     }
 
+    /// The last `num_messages` messages of the conversation, leaving out those
+    /// from users `username` has blocked or muted there.
     pub async fn get_message_history(
         &self,
         topic: String,
+        username: &str,
         num_messages: usize,
     ) -> Option<Vec<ChatMessage>> {
-        // "SELECT * FROM chat_message WHERE chat_id = \"{}\" LIMIT {};",
-        // "SELECT * FROM (SELECT * FROM chat_message WHERE chat_id = \"{}\" ORDER BY id DESC LIMIT {}) AS sub ORDER BY id ASC;"
-        let query = format!(
-            "SELECT * FROM (SELECT * FROM chat_message WHERE chat_id = \"{}\" ORDER BY id DESC LIMIT {}) AS sub ORDER BY id ASC;",
-            &topic, num_messages
-        );
+        let query = r#"
+            SELECT * FROM (
+                SELECT cm.* FROM chat_message cm
+                WHERE cm.chat_id = ?
+                AND cm.username NOT IN (
+                    SELECT blocked FROM user_block WHERE blocker = ?
+                )
+                AND cm.username NOT IN (
+                    SELECT rmu.muted FROM room_mute rmu
+                    JOIN chat_room cr ON rmu.room_id = cr.id
+                    WHERE cr.chat_room_id = ? AND rmu.username = ?
+                )
+                ORDER BY cm.timestamp DESC, cm.id DESC LIMIT ?
+            ) AS sub ORDER BY timestamp, id
+            "#;
 
-        let result = sqlx::query_as::<_, ChatMessage>(&query)
+        let result = sqlx::query_as::<_, ChatMessage>(query)
+            .bind(&topic)
+            .bind(username)
+            .bind(&topic)
+            .bind(username)
+            .bind(num_messages as u64)
             .fetch_all(&self.conn_pool)
             .await;
         match result {
@@ -122,3 +455,16 @@ impl DbManager {
         }
     }
 }
+
+/// True for errors caused by the database being unreachable rather than by
+/// the query, i.e. the ones worth retrying later.
+pub fn is_unavailable(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Io(_)
+            | Error::Tls(_)
+            | Error::PoolTimedOut
+            | Error::PoolClosed
+            | Error::WorkerCrashed
+    )
+}
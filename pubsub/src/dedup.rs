@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What is known about a client message id.
+pub enum Seen {
+    /// First time; the caller now owns it and must `complete` or `abandon` it.
+    New,
+    /// Another copy is still being published.
+    Pending,
+    /// Already published as `message_id`.
+    Stored { message_id: String, queued: bool },
+}
+
+struct Entry {
+    stored: Option<(String, bool)>,
+    seen_at: Instant,
+}
+
+/// Remembers the client message ids of recently published messages, per
+/// sender, so that resent copies are acknowledged instead of published again.
+pub struct Deduplicator {
+    window: Duration,
+    entries: Mutex<HashMap<(String, String), Entry>>,
+    last_pruned: Mutex<Instant>,
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration) -> Deduplicator {
+        Deduplicator {
+            window,
+            entries: Mutex::new(HashMap::new()),
+            last_pruned: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn begin(&self, sender: &str, client_msg_id: &str) -> Seen {
+        self.begin_at(sender, client_msg_id, Instant::now())
+    }
+
+    fn begin_at(&self, sender: &str, client_msg_id: &str, now: Instant) -> Seen {
+        self.prune(now);
+        let mut entries = self.entries.lock().unwrap();
+        let key = (sender.to_string(), client_msg_id.to_string());
+        match entries.get(&key) {
+            Some(entry) if self.is_live(entry, now) => match &entry.stored {
+                Some((message_id, queued)) => Seen::Stored {
+                    message_id: message_id.clone(),
+                    queued: *queued,
+                },
+                None => Seen::Pending,
+            },
+            _ => {
+                entries.insert(
+                    key,
+                    Entry {
+                        stored: None,
+                        seen_at: now,
+                    },
+                );
+                Seen::New
+            }
+        }
+    }
+
+    pub fn complete(&self, sender: &str, client_msg_id: &str, message_id: &str, queued: bool) {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (sender.to_string(), client_msg_id.to_string());
+        if let Some(entry) = entries.get_mut(&key) {
+            entry.stored = Some((message_id.to_string(), queued));
+        }
+    }
+
+    /// Forgets a message that was not published, so it can be sent again.
+    pub fn abandon(&self, sender: &str, client_msg_id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&(sender.to_string(), client_msg_id.to_string()));
+    }
+
+    fn is_live(&self, entry: &Entry, now: Instant) -> bool {
+        now.saturating_duration_since(entry.seen_at) < self.window
+    }
+
+    // Drops expired entries, at most a few times per window.
+    fn prune(&self, now: Instant) {
+        let mut last_pruned = self.last_pruned.lock().unwrap();
+        if now.saturating_duration_since(*last_pruned) < self.window / 10 {
+            return;
+        }
+        *last_pruned = now;
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| self.is_live(entry, now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn resent_copies_are_recognized() {
+        let dedup = Deduplicator::new(WINDOW);
+        let now = Instant::now();
+        assert!(matches!(dedup.begin_at("alice", "m1", now), Seen::New));
+        assert!(matches!(dedup.begin_at("alice", "m1", now), Seen::Pending));
+
+        dedup.complete("alice", "m1", "stored-1", true);
+        match dedup.begin_at("alice", "m1", now) {
+            Seen::Stored { message_id, queued } => {
+                assert_eq!(message_id, "stored-1");
+                assert!(queued);
+            }
+            _ => panic!("expected the stored message"),
+        }
+    }
+
+    #[test]
+    fn ids_are_per_sender() {
+        let dedup = Deduplicator::new(WINDOW);
+        let now = Instant::now();
+        assert!(matches!(dedup.begin_at("alice", "m1", now), Seen::New));
+        assert!(matches!(dedup.begin_at("bob", "m1", now), Seen::New));
+        assert!(matches!(dedup.begin_at("alice", "m2", now), Seen::New));
+    }
+
+    #[test]
+    fn abandoned_messages_can_be_sent_again() {
+        let dedup = Deduplicator::new(WINDOW);
+        let now = Instant::now();
+        assert!(matches!(dedup.begin_at("alice", "m1", now), Seen::New));
+        dedup.abandon("alice", "m1");
+        assert!(matches!(dedup.begin_at("alice", "m1", now), Seen::New));
+    }
+
+    #[test]
+    fn ids_expire_after_the_window() {
+        let dedup = Deduplicator::new(WINDOW);
+        let now = Instant::now();
+        dedup.begin_at("alice", "m1", now);
+        dedup.complete("alice", "m1", "stored-1", false);
+
+        let almost = now + WINDOW - Duration::from_secs(1);
+        assert!(matches!(
+            dedup.begin_at("alice", "m1", almost),
+            Seen::Stored { .. }
+        ));
+        assert!(matches!(
+            dedup.begin_at("alice", "m1", now + WINDOW),
+            Seen::New
+        ));
+    }
+
+    #[test]
+    fn expired_entries_are_evicted() {
+        let dedup = Deduplicator::new(WINDOW);
+        let now = Instant::now();
+        dedup.begin_at("alice", "m1", now);
+        dedup.begin_at("bob", "m1", now + WINDOW / 2);
+        assert_eq!(dedup.entries.lock().unwrap().len(), 2);
+
+        dedup.begin_at("carol", "m1", now + WINDOW);
+        let entries = dedup.entries.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(!entries.contains_key(&(String::from("alice"), String::from("m1"))));
+    }
+}
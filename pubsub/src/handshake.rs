@@ -0,0 +1,262 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::timeout;
+
+const MAX_REQUEST_HEAD_SIZE: usize = 8192;
+const REQUEST_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+const UNAUTHORIZED: &[u8] =
+    b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const BAD_REQUEST: &[u8] =
+    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// Who is on the other end of a connection, established during the upgrade.
+#[derive(Clone)]
+pub enum Identity {
+    User {
+        username: String,
+        session_id: String,
+    },
+    /// The REST server, which only sends system messages.
+    System,
+}
+
+/// Credentials found in the upgrade request. Users send `username` and
+/// `session_id` as headers or query parameters; the REST server sends
+/// `Authorization: Bearer <PUBSUB_SYSTEM_TOKEN>`.
+pub struct Credentials {
+    pub username: Option<String>,
+    pub session_id: Option<String>,
+    pub system_token: Option<String>,
+}
+
+/// Reads the HTTP upgrade request from the stream and returns the
+/// credentials in it, along with a stream that replays the request so the
+/// websocket handshake can still be done on it.
+pub async fn read_upgrade_request<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+) -> Option<(Credentials, PrefixedStream<S>)> {
+    let head = match timeout(REQUEST_HEAD_TIMEOUT, read_head(&mut stream)).await {
+        Ok(Ok(Some(head))) => head,
+        Ok(Ok(None)) => {
+            let _ = stream.write_all(BAD_REQUEST).await;
+            return None;
+        }
+        Ok(Err(_)) | Err(_) => return None,
+    };
+
+    let Some(credentials) = parse_credentials(&head) else {
+        let _ = stream.write_all(BAD_REQUEST).await;
+        return None;
+    };
+    Some((credentials, PrefixedStream::new(head, stream)))
+}
+
+/// Answers an upgrade request whose credentials were rejected.
+pub async fn reject_unauthorized<S: AsyncWrite + Unpin>(stream: &mut S) {
+    let _ = stream.write_all(UNAUTHORIZED).await;
+    let _ = stream.shutdown().await;
+}
+
+// Reads until the end of the request head. Returns None when the head is too
+// large or the connection closes first.
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<Vec<u8>>> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        head.extend_from_slice(&chunk[..n]);
+        if head.windows(4).any(|window| window == b"\r\n\r\n") {
+            return Ok(Some(head));
+        }
+        if head.len() > MAX_REQUEST_HEAD_SIZE {
+            return Ok(None);
+        }
+    }
+}
+
+fn parse_credentials(head: &[u8]) -> Option<Credentials> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut lines = head.split("\r\n");
+
+    // e.g. "GET /?username=alice&session_id=... HTTP/1.1"
+    let target = lines.next()?.split(' ').nth(1)?;
+    let mut credentials = Credentials {
+        username: None,
+        session_id: None,
+        system_token: None,
+    };
+    if let Some((_, query)) = target.split_once('?') {
+        for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match name {
+                "username" => credentials.username = Some(percent_decode(value)?),
+                "session_id" => credentials.session_id = Some(percent_decode(value)?),
+                _ => (),
+            }
+        }
+    }
+
+    // Headers take precedence over query parameters.
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "username" => credentials.username = Some(value.to_string()),
+            "session_id" => credentials.session_id = Some(value.to_string()),
+            "authorization" => {
+                credentials.system_token = value.strip_prefix("Bearer ").map(String::from)
+            }
+            _ => (),
+        }
+    }
+    Some(credentials)
+}
+
+// Decodes the %XX escapes of a query parameter value. Returns None for a
+// malformed escape or when the result is not UTF-8.
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// A stream that first yields bytes that were already read from `inner`.
+pub struct PrefixedStream<S> {
+    prefix: Vec<u8>,
+    pos: usize,
+    inner: S,
+}
+
+impl<S> PrefixedStream<S> {
+    pub fn new(prefix: Vec<u8>, inner: S) -> PrefixedStream<S> {
+        PrefixedStream {
+            prefix,
+            pos: 0,
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PrefixedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.prefix.len() {
+            let n = buf.remaining().min(this.prefix.len() - this.pos);
+            buf.put_slice(&this.prefix[this.pos..this.pos + n]);
+            this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(head: &str) -> Option<Credentials> {
+        parse_credentials(head.as_bytes())
+    }
+
+    #[test]
+    fn credentials_from_headers() {
+        let credentials = parse(
+            "GET / HTTP/1.1\r\nHost: chat\r\nUsername: alice\r\nSESSION_ID:  abc-123 \r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(credentials.username.as_deref(), Some("alice"));
+        assert_eq!(credentials.session_id.as_deref(), Some("abc-123"));
+        assert_eq!(credentials.system_token, None);
+    }
+
+    #[test]
+    fn credentials_from_query_string() {
+        let credentials =
+            parse("GET /?foo=bar&username=alice&session_id=abc-123 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(credentials.username.as_deref(), Some("alice"));
+        assert_eq!(credentials.session_id.as_deref(), Some("abc-123"));
+    }
+
+    #[test]
+    fn headers_take_precedence_over_query_string() {
+        let credentials =
+            parse("GET /?username=mallory HTTP/1.1\r\nusername: alice\r\n\r\n").unwrap();
+        assert_eq!(credentials.username.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn system_token_from_bearer_authorization() {
+        let credentials = parse("GET / HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").unwrap();
+        assert_eq!(credentials.system_token.as_deref(), Some("s3cret"));
+
+        let credentials = parse("GET / HTTP/1.1\r\nAuthorization: Basic s3cret\r\n\r\n").unwrap();
+        assert_eq!(credentials.system_token, None);
+    }
+
+    #[test]
+    fn query_values_are_percent_decoded() {
+        let credentials =
+            parse("GET /?username=al%69ce&session_id=a%2Fb%3D HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(credentials.username.as_deref(), Some("alice"));
+        assert_eq!(credentials.session_id.as_deref(), Some("a/b="));
+    }
+
+    #[test]
+    fn malformed_requests_are_rejected() {
+        assert!(parse("").is_none());
+        assert!(parse("GET\r\n\r\n").is_none());
+        assert!(parse_credentials(b"GET /?username=\xff HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse("GET /?username=alice%2 HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse("GET /?username=alice%zz HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse("GET /?username=alice%+1 HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse("GET /?username=%ff HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn missing_credentials_are_left_empty() {
+        let credentials = parse("GET /?username HTTP/1.1\r\nX-Other: 1\r\n\r\n").unwrap();
+        assert_eq!(credentials.username, None);
+        assert_eq!(credentials.session_id, None);
+        assert_eq!(credentials.system_token, None);
+    }
+}
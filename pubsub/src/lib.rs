@@ -2,4 +2,12 @@ mod broker;
 pub mod client;
 pub mod common;
 pub mod database;
+mod dedup;
+mod handshake;
+mod mention;
+mod metrics;
+mod rate_limit;
 pub mod server;
+mod tls;
+pub mod validation;
+mod wal;
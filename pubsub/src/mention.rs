@@ -0,0 +1,102 @@
+use crate::validation::validate_username;
+use colored::Colorize;
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+/// Most users a single message can mention.
+pub const MAX_MENTIONS: usize = 10;
+
+// An `@` that does not follow a word character, then something shaped like a
+// username. Trailing dots and underscores are left out, as in "Hi @alice.".
+static MENTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(^|[^a-zA-Z0-9._@])@([a-zA-Z][a-zA-Z0-9._]*[a-zA-Z0-9])").unwrap()
+});
+
+/// The distinct valid usernames mentioned in `content`, in order, at most
+/// `MAX_MENTIONS` of them. Usernames differing only in case are the same
+/// user, as in the database, and only the first spelling is kept.
+pub fn parse_mentions(content: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    for caps in MENTION.captures_iter(content) {
+        let username = &caps[2];
+        if validate_username(username).is_ok()
+            && !mentions.iter().any(|m| m.eq_ignore_ascii_case(username))
+        {
+            mentions.push(username.to_string());
+            if mentions.len() == MAX_MENTIONS {
+                break;
+            }
+        }
+    }
+    mentions
+}
+
+/// Highlights the mentions of `username` in `content` for the terminal.
+pub fn highlight_mentions(content: &str, username: &str) -> String {
+    MENTION
+        .replace_all(content, |caps: &Captures| {
+            if caps[2].eq_ignore_ascii_case(username) {
+                format!("{}{}", &caps[1], format!("@{}", &caps[2]).yellow().bold())
+            } else {
+                caps[0].to_string()
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_are_found_around_punctuation() {
+        assert_eq!(
+            parse_mentions("Hi @alice, (@bobby) and @carol.smith! Thanks @dave_7."),
+            ["alice", "bobby", "carol.smith", "dave_7"]
+        );
+        assert_eq!(parse_mentions("@alice's turn"), ["alice"]);
+    }
+
+    #[test]
+    fn email_addresses_are_not_mentions() {
+        assert!(parse_mentions("write to a@b.com or alice@example.org").is_empty());
+        assert!(parse_mentions("@@alice").is_empty());
+    }
+
+    #[test]
+    fn invalid_usernames_are_not_mentions() {
+        assert!(parse_mentions("@abc @1alice @").is_empty());
+        assert!(parse_mentions(&format!("@a{}", "b".repeat(20))).is_empty());
+    }
+
+    #[test]
+    fn duplicates_are_dropped_ignoring_case() {
+        assert_eq!(
+            parse_mentions("@alice @bobby @Alice @ALICE @bobby"),
+            ["alice", "bobby"]
+        );
+    }
+
+    #[test]
+    fn mentions_are_capped() {
+        let content: Vec<String> = (0..MAX_MENTIONS + 5)
+            .map(|i| format!("@user{:02}", i))
+            .collect();
+        let mentions = parse_mentions(&content.join(" "));
+        assert_eq!(mentions.len(), MAX_MENTIONS);
+        assert_eq!(mentions[0], "user00");
+    }
+
+    #[test]
+    fn only_own_mentions_are_highlighted() {
+        let highlighted = highlight_mentions("Hi @Alice and @bobby, mail a@alice.com", "alice");
+        assert_eq!(
+            highlighted,
+            format!(
+                "Hi {} and @bobby, mail a@alice.com",
+                "@Alice".yellow().bold()
+            )
+        );
+        assert_eq!(highlight_mentions("no mentions", "alice"), "no mentions");
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Counters for messages handled by the broker, logged periodically.
+#[derive(Default)]
+pub struct Metrics {
+    published: AtomicU64,
+    rejected: AtomicU64,
+    throttled_connection: AtomicU64,
+    throttled_room: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_published(&self) {
+        self.published.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Messages refused for being invalid or sent where the user may not post.
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Messages dropped by the sender's connection limit.
+    pub fn record_throttled_connection(&self) {
+        self.throttled_connection.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Messages dropped by the room's limit.
+    pub fn record_throttled_room(&self) {
+        self.throttled_room.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Logs the counters every `interval` for as long as the server runs.
+    pub fn spawn_logger(metrics: Arc<Metrics>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately.
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                println!(
+                    "Metrics: published={} rejected={} dropped_connection_limit={} dropped_room_limit={}",
+                    metrics.published.load(Ordering::Relaxed),
+                    metrics.rejected.load(Ordering::Relaxed),
+                    metrics.throttled_connection.load(Ordering::Relaxed),
+                    metrics.throttled_room.load(Ordering::Relaxed),
+                );
+            }
+        });
+    }
+}
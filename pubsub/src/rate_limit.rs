@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Room buckets are only pruned once there are this many of them.
+const MAX_IDLE_ROOM_BUCKETS: usize = 1024;
+
+/// Sustained message rate and burst allowed by a token bucket.
+#[derive(Clone, Copy)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// Reads `<prefix>_RATE` (messages per second) and `<prefix>_BURST` from
+    /// the environment, falling back to the given defaults.
+    pub fn from_env(prefix: &str, per_second: f64, burst: f64) -> RateLimit {
+        RateLimit {
+            per_second: env_or(&format!("{}_RATE", prefix), per_second),
+            burst: env_or(&format!("{}_BURST", prefix), burst),
+        }
+    }
+}
+
+/// Starts full with `burst` tokens and refills at `per_second`. Each message
+/// takes one token.
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket::new_at(limit, Instant::now())
+    }
+
+    fn new_at(limit: RateLimit, now: Instant) -> TokenBucket {
+        TokenBucket {
+            limit,
+            tokens: limit.burst,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token, or returns how long to wait until one is available.
+    pub fn try_take(&mut self) -> Result<(), Duration> {
+        self.try_take_at(Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        if self.limit.per_second <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.limit.per_second,
+        ))
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst);
+        self.last_refill = self.last_refill.max(now);
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.limit.burst
+    }
+}
+
+/// One token bucket per room, shared by everyone sending to it.
+pub struct RoomRateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RoomRateLimiter {
+    pub fn new(limit: RateLimit) -> RoomRateLimiter {
+        RoomRateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn try_take(&self, topic: &str) -> Result<(), Duration> {
+        self.try_take_at(topic, Instant::now())
+    }
+
+    fn try_take_at(&self, topic: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(topic) && buckets.len() >= MAX_IDLE_ROOM_BUCKETS {
+            // A full bucket behaves exactly like a new one, so it can go.
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        buckets
+            .entry(topic.to_string())
+            .or_insert_with(|| TokenBucket::new_at(self.limit, now))
+            .try_take_at(now)
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        per_second: 2.0,
+        burst: 3.0,
+    };
+
+    #[test]
+    fn bucket_allows_a_burst_then_limits() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new_at(LIMIT, start);
+        for _ in 0..3 {
+            assert!(bucket.try_take_at(start).is_ok());
+        }
+        // One token comes back every half second.
+        assert_eq!(bucket.try_take_at(start), Err(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new_at(LIMIT, start);
+        for _ in 0..3 {
+            bucket.try_take_at(start).unwrap();
+        }
+
+        let later = start + Duration::from_millis(250);
+        assert_eq!(bucket.try_take_at(later), Err(Duration::from_millis(250)));
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.try_take_at(later).is_ok());
+        assert!(bucket.try_take_at(later).is_err());
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_the_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new_at(LIMIT, start);
+        bucket.try_take_at(start).unwrap();
+
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.is_full(later));
+        for _ in 0..3 {
+            assert!(bucket.try_take_at(later).is_ok());
+        }
+        assert!(bucket.try_take_at(later).is_err());
+    }
+
+    #[test]
+    fn bucket_without_refill_waits_forever() {
+        let start = Instant::now();
+        let limit = RateLimit {
+            per_second: 0.0,
+            burst: 1.0,
+        };
+        let mut bucket = TokenBucket::new_at(limit, start);
+        assert!(bucket.try_take_at(start).is_ok());
+        assert_eq!(
+            bucket.try_take_at(start + Duration::from_secs(3600)),
+            Err(Duration::MAX)
+        );
+    }
+
+    #[test]
+    fn rooms_have_separate_buckets() {
+        let start = Instant::now();
+        let limiter = RoomRateLimiter::new(LIMIT);
+        for _ in 0..3 {
+            assert!(limiter.try_take_at("room-a", start).is_ok());
+        }
+        assert!(limiter.try_take_at("room-a", start).is_err());
+        assert!(limiter.try_take_at("room-b", start).is_ok());
+    }
+}
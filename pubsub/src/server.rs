@@ -1,26 +1,54 @@
 use crate::broker::Broker;
 use crate::common::{
-    ErrorMessage, FetchHistoryMessage, SubscriptionMessage, UserMessage, PUBSUB_HOST_PORT,
+    AckMessage, DeliveryReceipt, ErrorMessage, FetchHistoryMessage, Notification, PubSubError,
+    SubscriptionMessage, SystemMessage, UserMessage, PUBSUB_HOST_PORT,
 };
+use crate::handshake::{read_upgrade_request, reject_unauthorized, Identity, PrefixedStream};
+use crate::metrics::Metrics;
+use crate::tls::{server_acceptor, ServerStream};
 use futures_util::sink::SinkExt;
 use futures_util::stream::{SplitSink, SplitStream, StreamExt};
-use tokio::net::{TcpListener, TcpStream};
+use std::env;
+use std::time::Duration;
+use tokio::net::TcpListener;
 use tokio::sync::broadcast::{channel, Receiver, Sender};
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
 use tokio_websockets::{Message, ServerBuilder, WebSocketStream};
 
 pub struct PubSubServer {
     broker: Broker,
     listener: TcpListener,
+    tls_acceptor: Option<TlsAcceptor>,
 }
 
 impl PubSubServer {
     pub async fn new() -> Result<PubSubServer, std::io::Error> {
+        // Connections are served as wss:// when a certificate and key are set.
+        let tls_acceptor = match (
+            env::var("PUBSUB_TLS_CERT_PATH"),
+            env::var("PUBSUB_TLS_KEY_PATH"),
+        ) {
+            (Ok(cert_path), Ok(key_path)) => match server_acceptor(&cert_path, &key_path) {
+                Ok(acceptor) => Some(acceptor),
+                Err(e) => {
+                    println!("Failed to load TLS certificate and key. {e}");
+                    return Err(e);
+                }
+            },
+            _ => None,
+        };
         match TcpListener::bind(PUBSUB_HOST_PORT).await {
             Ok(listener) => {
-                println!("Listening on port 8080");
+                if tls_acceptor.is_some() {
+                    println!("Listening on port 8080 (TLS)");
+                } else {
+                    println!("Listening on port 8080");
+                }
                 Ok(PubSubServer {
                     broker: Broker::new().await,
                     listener,
+                    tls_acceptor,
                 })
             }
             Err(e) => {
@@ -31,81 +59,222 @@ impl PubSubServer {
     }
 
     pub async fn start(&self) -> Result<(), std::io::Error> {
+        let metrics_interval = env::var("PUBSUB_METRICS_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(60);
+        // 0 turns the periodic metrics log off.
+        if metrics_interval > 0 {
+            Metrics::spawn_logger(self.broker.metrics(), Duration::from_secs(metrics_interval));
+        }
+
         loop {
             let (socket, _) = self.listener.accept().await?;
-            match ServerBuilder::new().accept(socket).await {
-                Ok(ws_stream) => {
-                    let cloned_broker = self.broker.clone();
-                    tokio::spawn(handle_connection(cloned_broker, ws_stream));
+            let cloned_broker = self.broker.clone();
+            let tls_acceptor = self.tls_acceptor.clone();
+            tokio::spawn(async move {
+                let socket = match tls_acceptor {
+                    Some(acceptor) => match acceptor.accept(socket).await {
+                        Ok(tls_stream) => ServerStream::Tls(Box::new(tls_stream)),
+                        Err(e) => {
+                            println!("Failed to handle new connection: TLS handshake failed. {e}");
+                            return;
+                        }
+                    },
+                    None => ServerStream::Plain(socket),
+                };
+                let Some((credentials, mut stream)) = read_upgrade_request(socket).await else {
+                    println!("Failed to handle new connection: Malformed upgrade request");
+                    return;
+                };
+                // Only authenticated upgrades get a websocket.
+                let Some(identity) = cloned_broker.authenticate(&credentials).await else {
+                    println!("Rejected new connection: Invalid credentials");
+                    reject_unauthorized(&mut stream).await;
+                    return;
+                };
+                match ServerBuilder::new().accept(stream).await {
+                    Ok(ws_stream) => handle_connection(cloned_broker, ws_stream, identity).await,
+                    Err(e) => println!("Failed to handle new connection: {e}"),
                 }
-                Err(e) => println!("Failed to handle new connection: {e}"),
-            };
+            });
         }
     }
 }
 
-async fn handle_connection(mut broker: Broker, ws_stream: WebSocketStream<TcpStream>) {
+// How long a closing connection may take to send its last messages.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+const SESSION_ENDED: &str = "Your session has ended. Please log in again.";
+
+type ConnectionStream = WebSocketStream<PrefixedStream<ServerStream>>;
+
+async fn handle_connection(mut broker: Broker, ws_stream: ConnectionStream, identity: Identity) {
     let (mut ws_sender, mut ws_receiver): (
-        SplitSink<WebSocketStream<TcpStream>, Message>,
-        SplitStream<WebSocketStream<TcpStream>>,
+        SplitSink<ConnectionStream, Message>,
+        SplitStream<ConnectionStream>,
     ) = ws_stream.split();
+
+    let (username, session_id) = match identity {
+        Identity::User {
+            username,
+            session_id,
+        } => (username, session_id),
+        // The REST server only ever sends system messages and notifications.
+        Identity::System => {
+            while let Some(Ok(msg)) = ws_receiver.next().await {
+                if let Some(text) = msg.as_text() {
+                    if let Ok(notification) = serde_json::from_str::<Notification>(text) {
+                        let _ = broker.publish_notification(notification).await;
+                    } else if let Ok(sys_msg) = serde_json::from_str::<SystemMessage>(text) {
+                        let _ = broker.publish_system(sys_msg);
+                    }
+                }
+            }
+            return;
+        }
+    };
     let (bcast_tx, mut bcast_rx): (Sender<Message>, Receiver<Message>) = channel(16);
+    let sender_task = tokio::spawn(async move {
+        while let Ok(message) = bcast_rx.recv().await {
+            let _ = ws_sender.send(message).await;
+        }
+    });
+    // Registered before subscribing, so that users who are not in any
+    // conversation still get notifications.
+    broker.connect(&username, bcast_tx.clone());
 
+    // The topic this connection is bound to once it has subscribed.
+    let mut subscribed_topic: Option<String> = None;
     while let Some(Ok(msg)) = ws_receiver.next().await {
-        match msg.as_text() {
-            Some(text) => {
-                if let Ok(sub_msg) = serde_json::from_str::<SubscriptionMessage>(&text) {
-                    match broker.subscribe(&sub_msg, bcast_tx.clone()).await {
-                        Ok(_) => (),
-                        Err(e) => {
-                            let err_message = ErrorMessage {
-                                error: e,
-                                message: format!(
-                                    "Failed to subscribe to topic \"{}\".",
-                                    &sub_msg.topic
-                                ),
-                            };
-                            let msg: Message =
-                                Message::text(serde_json::to_string(&err_message).unwrap());
-                            let _ = ws_sender.send(msg).await;
-                        }
+        if let Some(text) = msg.as_text() {
+            if let Ok(mut sub_msg) = serde_json::from_str::<SubscriptionMessage>(text) {
+                // Subscriptions are always made as the authenticated user.
+                sub_msg.username = username.clone();
+                sub_msg.session_id = session_id.clone();
+                match broker.subscribe(&sub_msg, bcast_tx.clone()).await {
+                    Ok(_) => subscribed_topic = Some(sub_msg.topic),
+                    Err(e) => {
+                        let reason = match e {
+                            PubSubError::Banned => " You are banned from this chat room.",
+                            PubSubError::NotAParticipant => {
+                                " You are not a participant of this conversation."
+                            }
+                            _ => "",
+                        };
+                        let err_message = ErrorMessage {
+                            error: e,
+                            message: format!(
+                                "Failed to subscribe to topic \"{}\".{}",
+                                &sub_msg.topic, reason
+                            ),
+                            client_msg_id: None,
+                        };
+                        let msg: Message =
+                            Message::text(serde_json::to_string(&err_message).unwrap());
+                        let _ = bcast_tx.send(msg);
                     }
-                    break;
                 }
+                break;
             }
-            None => (),
         }
     }
+    let Some(topic) = subscribed_topic else {
+        // Once the broker has let go of the connection, the sender task ends
+        // after flushing what is left, e.g. the subscription error.
+        broker.disconnect(&username, &bcast_tx);
+        drop(bcast_tx);
+        let _ = sender_task.await;
+        return;
+    };
+
+    // Kept to clean up once the connection is done.
+    let (end_broker, end_topic, end_username, end_sender) = (
+        broker.clone(),
+        topic.clone(),
+        username.clone(),
+        bcast_tx.clone(),
+    );
 
+    // Errors and acks go back to this connection only.
+    let reply_tx = bcast_tx.clone();
+    let mut connection_bucket = broker.connection_bucket();
     let receiver_task = tokio::spawn(async move {
+        let send_error_message = |err_message: ErrorMessage| {
+            let msg: Message = Message::text(serde_json::to_string(&err_message).unwrap());
+            let _ = reply_tx.send(msg);
+        };
+        let send_error = |error: PubSubError, message: &str| {
+            send_error_message(ErrorMessage {
+                error,
+                message: String::from(message),
+                client_msg_id: None,
+            });
+        };
+        let send_ack = |ack: AckMessage| {
+            let msg: Message = Message::text(serde_json::to_string(&ack).unwrap());
+            let _ = reply_tx.send(msg);
+        };
+
         while let Some(Ok(msg)) = ws_receiver.next().await {
-            match msg.as_text() {
-                Some(text) => {
-                    if let Ok(sub_msg) = serde_json::from_str::<SubscriptionMessage>(&text) {
-                        broker.unsubscribe(&sub_msg).await;
+            if let Some(text) = msg.as_text() {
+                if let Ok(mut sub_msg) = serde_json::from_str::<SubscriptionMessage>(text) {
+                    sub_msg.username = username.clone();
+                    sub_msg.session_id = session_id.clone();
+                    sub_msg.topic = topic.clone();
+                    broker.unsubscribe(&sub_msg).await;
+                    break;
+                } else if let Ok(mut hist_msg) = serde_json::from_str::<FetchHistoryMessage>(text) {
+                    if hist_msg.topic != topic {
+                        send_error(
+                            PubSubError::InvalidMessage,
+                            "You can only fetch the history of the conversation you are in.",
+                        );
+                        continue;
+                    }
+                    // A password change or reset ends the user's other sessions.
+                    if !broker.is_session_current(&username, &session_id).await {
+                        send_error(PubSubError::SubscriptionError, SESSION_ENDED);
                         break;
-                    } else if let Ok(hist_msg) = serde_json::from_str::<FetchHistoryMessage>(&text)
-                    {
-                        broker.fetch_history(&hist_msg).await;
-                    } else {
-                        match serde_json::from_str::<UserMessage>(text) {
-                            Ok(user_msg) => {
-                                broker.publish(user_msg);
+                    }
+                    hist_msg.username = username.clone();
+                    broker.fetch_history(&hist_msg).await;
+                } else if let Ok(mut receipt) = serde_json::from_str::<DeliveryReceipt>(text) {
+                    receipt.delivered_to = username.clone();
+                    receipt.topic = topic.clone();
+                    broker.forward_receipt(&receipt);
+                } else {
+                    match serde_json::from_str::<UserMessage>(text) {
+                        Ok(mut user_msg) => {
+                            if !broker.is_session_current(&username, &session_id).await {
+                                send_error(PubSubError::SubscriptionError, SESSION_ENDED);
+                                break;
+                            }
+                            // Never trust the sender named by the client.
+                            user_msg.sender = username.clone();
+                            match broker.publish(user_msg, &mut connection_bucket).await {
+                                Ok(Some(ack)) => send_ack(ack),
+                                Ok(None) => (),
+                                Err(err_message) => send_error_message(err_message),
                             }
-                            Err(e) => println!("Oops: {}, message: {}", e, text),
                         }
+                        Err(e) => println!("Oops: {}, message: {}", e, text),
                     }
                 }
-                None => (),
             }
         }
     });
 
-    let sender_task = tokio::spawn(async move {
-        while let Ok(message) = bcast_rx.recv().await {
-            let _ = ws_sender.send(message).await;
-        }
-    });
     let _ = receiver_task.await;
-    sender_task.abort();
+    end_broker.disconnect(&end_username, &end_sender);
+    // Give the sender task a moment to flush what is left, e.g. the error
+    // telling the user their session has ended.
+    drop(end_sender);
+    drop(bcast_tx);
+    let mut sender_task = sender_task;
+    if timeout(FLUSH_TIMEOUT, &mut sender_task).await.is_err() {
+        sender_task.abort();
+    }
+
+    // Everything delivered while subscribed has been seen.
+    end_broker.mark_read(&end_topic, &end_username).await;
 }
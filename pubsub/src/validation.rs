@@ -0,0 +1,285 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use validator::ValidateEmail;
+
+pub const MAX_ROOM_NAME_LENGTH: usize = 100;
+pub const MAX_ROOM_PASSWORD_LENGTH: usize = 64;
+pub const MAX_ROOM_TOPIC_LENGTH: usize = 255;
+pub const MAX_ROOM_DESCRIPTION_LENGTH: usize = 1000;
+/// Default for the broker's PUBSUB_MAX_MESSAGE_LENGTH setting.
+pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = 2000;
+/// Upper bound for PUBSUB_MAX_MESSAGE_LENGTH, so that any message fits the
+/// 64 KB `TEXT` column even with 4-byte characters.
+pub const MAX_MESSAGE_LENGTH_LIMIT: usize = 16000;
+
+/// A validation failure for a single field of a request.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: String) -> FieldError {
+        FieldError {
+            field: String::from(field),
+            message,
+        }
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<(), String> {
+    let re = Regex::new(r"^[a-zA-Z][a-zA-Z0-9._]{3,18}[a-zA-Z0-9]$").unwrap();
+    if !re.is_match(username) {
+        return Err(String::from(
+            "Username must be 5 to 20 characters, start with a letter, contain only letters, digits, '_' or '.', and not end with '_' or '.'.",
+        ));
+    }
+
+    if username.contains("__") || username.contains("..") {
+        return Err(String::from(
+            "Username must not contain consecutive underscores or dots.",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_password(password: &str) -> Result<(), String> {
+    // 6 - 16
+    if password.len() < 6 || password.len() > 16 {
+        return Err(String::from(
+            "Password must be between 6 and 16 characters.",
+        ));
+    }
+
+    // at least one digit
+    let has_digit = Regex::new(r"[0-9]").unwrap();
+    if !has_digit.is_match(password) {
+        return Err(String::from("Password must contain at least one digit."));
+    }
+
+    // at least one special character
+    let has_special = Regex::new(r"[!@#$%^&*]").unwrap();
+    if !has_special.is_match(password) {
+        return Err(String::from(
+            "Password must contain at least one special character (!@#$%^&*).",
+        ));
+    }
+
+    // all characters are valid
+    let valid_chars = Regex::new(r"^[a-zA-Z0-9!@#$%^&*]+$").unwrap();
+    if !valid_chars.is_match(password) {
+        return Err(String::from(
+            "Password can only contain letters, digits and special characters (!@#$%^&*).",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_email_addr(email: &str) -> Result<(), String> {
+    if email.validate_email() {
+        Ok(())
+    } else {
+        Err(String::from("Invalid email address."))
+    }
+}
+
+pub fn validate_room_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err(String::from("Room name must not be empty."));
+    }
+
+    if name.chars().count() > MAX_ROOM_NAME_LENGTH {
+        return Err(format!(
+            "Room name must be at most {} characters.",
+            MAX_ROOM_NAME_LENGTH
+        ));
+    }
+
+    if name.chars().any(char::is_control) {
+        return Err(String::from(
+            "Room name must not contain control characters.",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_room_password(password: &str) -> Result<(), String> {
+    if password.is_empty() || password.len() > MAX_ROOM_PASSWORD_LENGTH {
+        return Err(format!(
+            "Room password must be between 1 and {} characters.",
+            MAX_ROOM_PASSWORD_LENGTH
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_room_topic(topic: &str) -> Result<(), String> {
+    if topic.chars().count() > MAX_ROOM_TOPIC_LENGTH {
+        return Err(format!(
+            "Room topic must be at most {} characters.",
+            MAX_ROOM_TOPIC_LENGTH
+        ));
+    }
+
+    if topic.chars().any(char::is_control) {
+        return Err(String::from(
+            "Room topic must not contain control characters.",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_room_description(description: &str) -> Result<(), String> {
+    if description.chars().count() > MAX_ROOM_DESCRIPTION_LENGTH {
+        return Err(format!(
+            "Room description must be at most {} characters.",
+            MAX_ROOM_DESCRIPTION_LENGTH
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_message_content(content: &str, max_length: usize) -> Result<(), String> {
+    if content.trim().is_empty() {
+        return Err(String::from("Message must not be empty."));
+    }
+
+    let max_length = max_length.min(MAX_MESSAGE_LENGTH_LIMIT);
+    if content.chars().count() > max_length {
+        return Err(format!(
+            "Message must be at most {} characters.",
+            max_length
+        ));
+    }
+
+    if content.chars().any(char::is_control) {
+        return Err(String::from("Message must not contain control characters."));
+    }
+
+    Ok(())
+}
+
+/// Checks that `date` is a calendar date written as YYYY-MM-DD.
+pub fn validate_date(date: &str) -> Result<(), String> {
+    let re = Regex::new(r"^(\d{4})-(\d{2})-(\d{2})$").unwrap();
+    let error = || format!("'{}' is not a date in the form YYYY-MM-DD.", date);
+    let Some(caps) = re.captures(date) else {
+        return Err(error());
+    };
+    let year: u32 = caps[1].parse().unwrap();
+    let month: u32 = caps[2].parse().unwrap();
+    let day: u32 = caps[3].parse().unwrap();
+    // u32::is_multiple_of needs Rust 1.87, newer than the Docker images.
+    #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return Err(error()),
+    };
+    if day == 0 || day > days_in_month {
+        return Err(error());
+    }
+
+    Ok(())
+}
+
+pub fn validate_signup(username: &str, email: &str, password: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if let Err(e) = validate_username(username) {
+        errors.push(FieldError::new("username", e));
+    }
+    if let Err(e) = validate_email_addr(email) {
+        errors.push(FieldError::new("email", e));
+    }
+    if let Err(e) = validate_password(password) {
+        errors.push(FieldError::new("password", e));
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn username_length_is_5_to_20_characters() {
+        assert!(validate_username("abcd").is_err());
+        assert!(validate_username("abcde").is_ok());
+        assert!(validate_username(&format!("a{}", "b".repeat(19))).is_ok());
+        assert!(validate_username(&format!("a{}", "b".repeat(20))).is_err());
+    }
+
+    #[test]
+    fn username_characters() {
+        assert!(validate_username("alice.smith_2").is_ok());
+        assert!(validate_username("1alice").is_err());
+        assert!(validate_username("_alice").is_err());
+        assert!(validate_username("alice_").is_err());
+        assert!(validate_username("alice.").is_err());
+        assert!(validate_username("alice-smith").is_err());
+        assert!(validate_username("alice smith").is_err());
+        assert!(validate_username("alice__smith").is_err());
+        assert!(validate_username("alice..smith").is_err());
+    }
+
+    #[test]
+    fn password_rules() {
+        assert!(validate_password("abc12!").is_ok());
+        assert!(validate_password("Abcdefghij1234!@").is_ok());
+        assert!(validate_password("ab12!").is_err());
+        assert!(validate_password("Abcdefghij1234!@x").is_err());
+        assert!(validate_password("abcdef!").is_err());
+        assert!(validate_password("abcdef1").is_err());
+        assert!(validate_password("abc 12!").is_err());
+        assert!(validate_password("abc12!?").is_err());
+    }
+
+    #[test]
+    fn room_name_rules() {
+        assert!(validate_room_name("General").is_ok());
+        assert!(validate_room_name("Café ☕").is_ok());
+        assert!(validate_room_name("").is_err());
+        assert!(validate_room_name("   ").is_err());
+        assert!(validate_room_name("bad\nname").is_err());
+        // The limit counts characters, not bytes.
+        assert!(validate_room_name(&"é".repeat(MAX_ROOM_NAME_LENGTH)).is_ok());
+        assert!(validate_room_name(&"a".repeat(MAX_ROOM_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn date_must_exist_in_the_calendar() {
+        assert!(validate_date("2024-01-31").is_ok());
+        assert!(validate_date("2024-04-30").is_ok());
+        assert!(validate_date("2024-04-31").is_err());
+        assert!(validate_date("2024-00-10").is_err());
+        assert!(validate_date("2024-13-10").is_err());
+        assert!(validate_date("2024-05-00").is_err());
+    }
+
+    #[test]
+    fn date_handles_leap_years() {
+        assert!(validate_date("2024-02-29").is_ok());
+        assert!(validate_date("2023-02-29").is_err());
+        assert!(validate_date("1900-02-29").is_err());
+        assert!(validate_date("2000-02-29").is_ok());
+    }
+
+    #[test]
+    fn date_must_be_yyyy_mm_dd() {
+        assert!(validate_date("2024-1-05").is_err());
+        assert!(validate_date("24-01-05").is_err());
+        assert!(validate_date("2024/01/05").is_err());
+        assert!(validate_date("2024-01-05T00:00").is_err());
+        assert!(validate_date("").is_err());
+    }
+}
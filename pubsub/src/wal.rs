@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// A message as it is written to the database, or to the queue while the
+/// database cannot be reached.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StoredMessage {
+    pub message_id: String,
+    pub topic: String,
+    pub sender: String,
+    pub content: String,
+    /// Unix time the broker accepted the message at.
+    pub sent_at: u64,
+    /// Participants of the conversation mentioned in the message.
+    #[serde(default)]
+    pub mentions: Vec<String>,
+}
+
+/// Messages accepted while the database was down, kept in a local file (one
+/// JSON object per line) until they can be written to the database.
+pub struct WriteAheadQueue {
+    path: PathBuf,
+    // Number of entries in the file. The lock also serializes file access.
+    len: Mutex<usize>,
+}
+
+impl WriteAheadQueue {
+    pub async fn open(path: PathBuf) -> io::Result<WriteAheadQueue> {
+        let len = match fs::read_to_string(&path).await {
+            Ok(contents) => contents.lines().count(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        if len > 0 {
+            println!(
+                "Found {} queued messages in {}",
+                len,
+                path.to_string_lossy()
+            );
+        }
+        Ok(WriteAheadQueue {
+            path,
+            len: Mutex::new(len),
+        })
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        *self.len.lock().await == 0
+    }
+
+    /// Appends the message and waits until it is on disk.
+    pub async fn push(&self, message: &StoredMessage) -> io::Result<()> {
+        self.push_line(&serde_json::to_string(message)?).await
+    }
+
+    /// Appends a line as it is, e.g. one that `peek` could not parse.
+    pub async fn push_line(&self, line: &str) -> io::Result<()> {
+        let mut len = self.len.lock().await;
+        let line = format!("{}\n", line);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+        *len += 1;
+        Ok(())
+    }
+
+    /// Returns the queued messages, oldest first. Lines that cannot be parsed
+    /// are returned as they are, as errors.
+    pub async fn peek(&self) -> io::Result<Vec<Result<StoredMessage, String>>> {
+        let _len = self.len.lock().await;
+        let contents = match fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(contents
+            .lines()
+            .map(|line| serde_json::from_str(line).map_err(|_| line.to_string()))
+            .collect())
+    }
+
+    /// Removes the `count` oldest messages. Messages pushed since `peek` are kept.
+    pub async fn remove_first(&self, count: usize) -> io::Result<()> {
+        let mut len = self.len.lock().await;
+        let contents = fs::read_to_string(&self.path).await?;
+        let remaining: Vec<&str> = contents.lines().skip(count).collect();
+
+        // Write a new file and swap it in, so a crash never loses the queue.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path).await?;
+        for line in &remaining {
+            file.write_all(line.as_bytes()).await?;
+            file.write_all(b"\n").await?;
+        }
+        file.sync_data().await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        *len = remaining.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("pubsub-wal-test-{}.jsonl", Uuid::new_v4()))
+    }
+
+    fn message(id: &str) -> StoredMessage {
+        StoredMessage {
+            message_id: String::from(id),
+            topic: String::from("room"),
+            sender: String::from("alice"),
+            content: format!("message {}", id),
+            sent_at: 1_700_000_000,
+            mentions: vec![String::from("bob")],
+        }
+    }
+
+    fn ids(entries: &[Result<StoredMessage, String>]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| entry.as_ref().unwrap().message_id.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn append_replay_and_truncate() {
+        let path = temp_path();
+        let queue = WriteAheadQueue::open(path.clone()).await.unwrap();
+        assert!(queue.is_empty().await);
+        assert!(queue.peek().await.unwrap().is_empty());
+
+        for id in ["1", "2", "3"] {
+            queue.push(&message(id)).await.unwrap();
+        }
+        assert!(!queue.is_empty().await);
+        let entries = queue.peek().await.unwrap();
+        assert_eq!(ids(&entries), ["1", "2", "3"]);
+        let first = entries[0].as_ref().unwrap();
+        assert_eq!(first.content, "message 1");
+        assert_eq!(first.mentions, ["bob"]);
+
+        // A restart finds the queue where it was left.
+        drop(queue);
+        let queue = WriteAheadQueue::open(path.clone()).await.unwrap();
+        assert_eq!(ids(&queue.peek().await.unwrap()), ["1", "2", "3"]);
+
+        queue.remove_first(2).await.unwrap();
+        assert_eq!(ids(&queue.peek().await.unwrap()), ["3"]);
+        queue.push(&message("4")).await.unwrap();
+        assert_eq!(ids(&queue.peek().await.unwrap()), ["3", "4"]);
+
+        queue.remove_first(2).await.unwrap();
+        assert!(queue.is_empty().await);
+        let queue = WriteAheadQueue::open(path.clone()).await.unwrap();
+        assert!(queue.is_empty().await);
+
+        let _ = fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn unreadable_lines_are_returned_as_they_are() {
+        let path = temp_path();
+        let queue = WriteAheadQueue::open(path.clone()).await.unwrap();
+        queue.push(&message("1")).await.unwrap();
+        queue.push_line("not json").await.unwrap();
+
+        let entries = queue.peek().await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_ok());
+        assert_eq!(
+            entries[1].as_ref().err().map(String::as_str),
+            Some("not json")
+        );
+
+        let _ = fs::remove_file(&path).await;
+    }
+}
@@ -0,0 +1,59 @@
+use pubsub::common::PUBSUB_SERVER_ADDRESS;
+use std::env;
+use std::str::FromStr;
+
+/// Server settings read from environment variables at startup.
+pub struct ServerConfig {
+    /// When set, new accounts must confirm their email address before logging in.
+    pub require_email_verification: bool,
+    /// Failed logins allowed per username or IP address before it is locked out.
+    pub login_max_failures: u32,
+    /// Lockout applied at `login_max_failures`; it doubles with every further failure.
+    pub login_lockout_secs: u64,
+    pub login_max_lockout_secs: u64,
+    /// Address of the pub-sub broker that room announcements are sent to.
+    pub pubsub_url: String,
+    /// Secret shared with the broker to sign system messages. Announcements
+    /// are skipped when it is not set.
+    pub pubsub_system_token: Option<String>,
+    /// PEM bundle of CA certificates trusted when the broker uses wss://.
+    /// The system roots are used when it is not set.
+    pub pubsub_ca_path: Option<String>,
+    /// Certificate chain and private key (PEM) to serve HTTPS with. TLS is
+    /// only enabled when both are set.
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// Largest conversation export the admin import accepts.
+    pub import_limit_mib: u64,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> ServerConfig {
+        ServerConfig {
+            require_email_verification: env_flag("REQUIRE_EMAIL_VERIFICATION"),
+            login_max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+            login_lockout_secs: env_or("LOGIN_LOCKOUT_SECS", 30),
+            login_max_lockout_secs: env_or("LOGIN_MAX_LOCKOUT_SECS", 900),
+            pubsub_url: env_or("PUBSUB_URL", String::from(PUBSUB_SERVER_ADDRESS)),
+            pubsub_system_token: env::var("PUBSUB_SYSTEM_TOKEN").ok(),
+            pubsub_ca_path: env::var("PUBSUB_CA_PATH").ok(),
+            tls_cert_path: env::var("TLS_CERT_PATH").ok(),
+            tls_key_path: env::var("TLS_KEY_PATH").ok(),
+            import_limit_mib: env_or("IMPORT_LIMIT_MIB", 64),
+        }
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    matches!(
+        env::var(name).as_deref(),
+        Ok("1") | Ok("true") | Ok("TRUE") | Ok("yes")
+    )
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -1,3 +1,4 @@
+use rocket::futures::stream::BoxStream;
 use sqlx::{mysql::MySqlPool, Error, FromRow, Row};
 use uuid::Uuid;
 
@@ -7,12 +8,189 @@ pub struct User {
     pub password: String,
     pub email: Option<String>,
     pub session_id: Option<String>,
+    pub verified: bool,
+    pub verification_code: Option<String>,
+    pub is_admin: bool,
+}
+
+#[derive(FromRow)]
+pub struct LoginAttempt {
+    pub username: String,
+    pub ip_address: Option<String>,
+    pub reason: String,
+    pub attempted_at: String,
 }
 
 #[derive(Debug, FromRow)]
 pub struct ChatRoom {
-    chat_room_id: String,
-    name: String,
+    pub chat_room_id: String,
+    pub name: String,
+    pub topic: Option<String>,
+    pub description: Option<String>,
+    pub visibility: String,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoomVisibility {
+    Public,
+    Private,
+    Password,
+}
+
+impl RoomVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomVisibility::Public => "public",
+            RoomVisibility::Private => "private",
+            RoomVisibility::Password => "password",
+        }
+    }
+
+    pub fn parse(visibility: &str) -> Option<RoomVisibility> {
+        match visibility {
+            "public" => Some(RoomVisibility::Public),
+            "private" => Some(RoomVisibility::Private),
+            "password" => Some(RoomVisibility::Password),
+            _ => None,
+        }
+    }
+}
+
+#[derive(FromRow)]
+pub struct GroupChat {
+    pub chat_id: String,
+    pub participant_key: String,
+}
+
+impl GroupChat {
+    pub fn participants(&self) -> Vec<String> {
+        self.participant_key.split(',').map(String::from).collect()
+    }
+}
+
+/// A group chat is identified by its set of participants: their usernames,
+/// sorted and joined with commas.
+fn group_participant_key(participants: &[String]) -> String {
+    let mut participants = participants.to_vec();
+    participants.sort();
+    participants.dedup();
+    participants.join(",")
+}
+
+#[derive(FromRow)]
+pub struct PendingInvite {
+    pub chat_room_id: String,
+    pub name: String,
+    pub inviter: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum RoomRole {
+    Member,
+    Moderator,
+    Owner,
+}
+
+impl RoomRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomRole::Member => "member",
+            RoomRole::Moderator => "moderator",
+            RoomRole::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<RoomRole> {
+        match role {
+            "member" => Some(RoomRole::Member),
+            "moderator" => Some(RoomRole::Moderator),
+            "owner" => Some(RoomRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(FromRow)]
+pub struct RoomMember {
+    pub username: String,
+    pub role: String,
+}
+
+/// The conversations of a user (`chat_id`, `kind` and `name`, as in
+/// `UnreadConversation`). Binds the username five times.
+const USER_CONVERSATIONS: &str = r#"
+    SELECT chat_id, 'private' AS kind, IF(user1 = ?, user2, user1) AS name
+    FROM private_chat WHERE user1 = ? OR user2 = ?
+    UNION ALL
+    SELECT gc.chat_id, 'group', gc.participant_key FROM group_chat gc
+    JOIN group_chat_member gcm ON gcm.group_id = gc.id
+    WHERE gcm.username = ?
+    UNION ALL
+    SELECT cr.chat_room_id, 'room', cr.name FROM chat_room cr
+    JOIN room_member rm ON rm.room_id = cr.id
+    WHERE rm.username = ?
+"#;
+
+/// A notification kept for a user who was not connected when it was sent.
+#[derive(FromRow)]
+pub struct PendingNotification {
+    pub id: i64,
+    pub kind: String,
+    pub content: String,
+    pub chat_id: Option<String>,
+    pub created_at: String,
+}
+
+/// A conversation with messages the user has not read yet. `kind` is
+/// `private`, `group` or `room`; `name` is the other user of a private chat,
+/// the participant key of a group chat or the name of a chat room.
+#[derive(FromRow)]
+pub struct UnreadConversation {
+    pub chat_id: String,
+    pub kind: String,
+    pub name: String,
+    pub unread: i64,
+    pub senders: String,
+}
+
+#[derive(FromRow)]
+pub struct SearchHit {
+    pub chat_id: String,
+    pub kind: String,
+    pub name: String,
+    pub sender: String,
+    pub message: String,
+    pub sent_at: String,
+}
+
+/// What to look for with `DbManager::search_messages`. Dates are YYYY-MM-DD
+/// and both ends are inclusive.
+pub struct MessageSearch<'a> {
+    /// Boolean-mode full-text query.
+    pub text: Option<&'a str>,
+    pub sender: Option<&'a str>,
+    pub since: Option<&'a str>,
+    pub until: Option<&'a str>,
+    pub limit: u32,
+}
+
+#[derive(FromRow)]
+pub struct Mention {
+    pub chat_id: String,
+    pub room_name: Option<String>,
+    pub sender: String,
+    pub message: String,
+    pub created_at: String,
+}
+
+/// A message as written to a conversation export.
+#[derive(FromRow)]
+pub struct ExportedMessage {
+    pub message_id: Option<String>,
+    pub sender: String,
+    pub message: String,
+    pub sent_at: String,
 }
 
 pub struct DbManager {
@@ -40,12 +218,12 @@ impl DbManager {
         } else {
             (user2, user1)
         };
-        let query = format!(
-            "INSERT INTO private_chat (user1, user2) VALUES (\"{}\", \"{}\");",
-            user1, user2
-        );
-
-        let result = sqlx::query(&query).execute(&self.conn_pool).await;
+        let query = "INSERT INTO private_chat (user1, user2) VALUES (?, ?)";
+        let result = sqlx::query(query)
+            .bind(user1)
+            .bind(user2)
+            .execute(&self.conn_pool)
+            .await;
         match result {
             Ok(_) => {
                 // update chat_id
@@ -60,9 +238,7 @@ impl DbManager {
             Err(e) => {
                 println!(
                     "Error inserting private_chat between users '{}' and '{}' : {}",
-                    user1,
-                    user2,
-                    e.to_string()
+                    user1, user2, e
                 );
                 None
             }
@@ -80,7 +256,7 @@ impl DbManager {
             WHERE user1 = ?;
             "#;
 
-        let result = sqlx::query(&query)
+        let result = sqlx::query(query)
             .bind(username)
             .bind(username)
             .fetch_all(&self.conn_pool)
@@ -112,7 +288,7 @@ impl DbManager {
         };
 
         let query = "SELECT chat_id FROM private_chat WHERE user1 = ? AND user2 = ?";
-        let result = sqlx::query(&query)
+        let result = sqlx::query(query)
             .bind(user1)
             .bind(user2)
             .fetch_optional(&self.conn_pool)
@@ -120,168 +296,1381 @@ impl DbManager {
         match result {
             Ok(row) => row.map(|r| r.get::<String, _>("chat_id")),
             Err(e) => {
-                println!("Failed to retrieve the chat_id: {}", e.to_string());
+                println!("Failed to retrieve the chat_id: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn get_group_chat_by_participants(&self, participants: &[String]) -> Option<String> {
+        let query = "SELECT chat_id FROM group_chat WHERE participant_key = ?";
+        let result = sqlx::query(query)
+            .bind(group_participant_key(participants))
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => row.map(|r| r.get::<String, _>("chat_id")),
+            Err(e) => {
+                println!("Failed to retrieve the group chat id: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn insert_group_chat(
+        &self,
+        participants: &[String],
+        created_by: &str,
+    ) -> Result<String, Error> {
+        let chat_id = Uuid::new_v4().to_string();
+        let mut tx = self.conn_pool.begin().await?;
+
+        let query =
+            "INSERT INTO group_chat (chat_id, participant_key, created_by) VALUES (?, ?, ?)";
+        let group_id = sqlx::query(query)
+            .bind(&chat_id)
+            .bind(group_participant_key(participants))
+            .bind(created_by)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_id();
+
+        for username in participants {
+            sqlx::query("INSERT IGNORE INTO group_chat_member (group_id, username) VALUES (?, ?)")
+                .bind(group_id)
+                .bind(username)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(chat_id)
+    }
+
+    pub async fn get_group_chat(&self, chat_id: &str) -> Option<GroupChat> {
+        let query = "SELECT chat_id, participant_key FROM group_chat WHERE chat_id = ?";
+        let result = sqlx::query_as::<_, GroupChat>(query)
+            .bind(chat_id)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(group) => group,
+            Err(e) => {
+                println!("Error querying group_chat table for {} : {}", chat_id, e);
+                None
+            }
+        }
+    }
+
+    pub async fn get_group_chats(&self, username: &str) -> Option<Vec<GroupChat>> {
+        let query = r#"
+            SELECT gc.chat_id, gc.participant_key FROM group_chat gc
+            JOIN group_chat_member gcm ON gcm.group_id = gc.id
+            WHERE gcm.username = ?
+            ORDER BY gc.id
+            "#;
+        let result = sqlx::query_as::<_, GroupChat>(query)
+            .bind(username)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(groups) => Some(groups),
+            Err(e) => {
+                println!("Error querying group chats of user '{}': {}", username, e);
                 None
             }
         }
     }
 
-    pub async fn insert_chat_room(&self, name: &str) -> Option<String> {
+    /// Replaces the participants of a group chat. Fails with a unique
+    /// violation when another group already has the new participant set.
+    pub async fn set_group_participants(
+        &self,
+        chat_id: &str,
+        participants: &[String],
+    ) -> Result<(), Error> {
+        let mut tx = self.conn_pool.begin().await?;
+
+        sqlx::query("UPDATE group_chat SET participant_key = ? WHERE chat_id = ?")
+            .bind(group_participant_key(participants))
+            .bind(chat_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let query = r#"
+            DELETE gcm FROM group_chat_member gcm
+            JOIN group_chat gc ON gcm.group_id = gc.id
+            WHERE gc.chat_id = ?
+            "#;
+        sqlx::query(query).bind(chat_id).execute(&mut *tx).await?;
+
+        for username in participants {
+            let query = r#"
+                INSERT IGNORE INTO group_chat_member (group_id, username)
+                SELECT id, ? FROM group_chat WHERE chat_id = ?
+                "#;
+            sqlx::query(query)
+                .bind(username)
+                .bind(chat_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn insert_chat_room(
+        &self,
+        name: &str,
+        owner: &str,
+        visibility: RoomVisibility,
+        password: Option<&String>,
+    ) -> Option<String> {
         // insert the chat room
-        let query = "INSERT INTO chat_room (name) VALUES (?)";
-        let id = match sqlx::query(&query)
+        let query = "INSERT INTO chat_room (name, owner, visibility, password) VALUES (?, ?, ?, ?)";
+        let id = match sqlx::query(query)
             .bind(name)
+            .bind(owner)
+            .bind(visibility.as_str())
+            .bind(password)
             .execute(&self.conn_pool)
             .await
         {
             Ok(result) => result.last_insert_id(),
             Err(e) => {
-                println!("Error inserting chat room '{}' : {}", name, e.to_string());
+                println!("Error inserting chat room '{}' : {}", name, e);
                 return None;
             }
         };
 
         // update chat_room_id
         let chat_room_id = Uuid::new_v4().to_string();
-        let res = self.set_chat_room_id(id, name, &chat_room_id).await;
-        if res {
+        if !self.set_chat_room_id(id, name, &chat_room_id).await {
+            return None;
+        }
+
+        // the creator is the owner of the room
+        if self
+            .insert_room_member(&chat_room_id, owner, RoomRole::Owner)
+            .await
+        {
             Some(chat_room_id)
         } else {
             None
         }
     }
 
-    pub async fn insert_user(&self, username: &String, email: &String, password: &String) -> bool {
-        let query = format!(
-            "INSERT INTO user (username, email, password) VALUES (\"{}\", \"{}\", \"{}\");",
-            username, email, password
-        );
+    pub async fn get_chat_room(&self, chat_room_id: &str) -> Option<ChatRoom> {
+        let query = "SELECT chat_room_id, name, topic, description, visibility, password FROM chat_room WHERE chat_room_id = ?";
+        let result = sqlx::query_as::<_, ChatRoom>(query)
+            .bind(chat_room_id)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(room) => room,
+            Err(e) => {
+                println!(
+                    "Error querying chat_room table for {} : {}",
+                    chat_room_id, e
+                );
+                None
+            }
+        }
+    }
 
-        let result = sqlx::query(&query).execute(&self.conn_pool).await;
+    /// Looks a chat room up by name. Names are compared case-insensitively.
+    pub async fn get_chat_room_by_name(&self, name: &str) -> Option<ChatRoom> {
+        let query = "SELECT chat_room_id, name, topic, description, visibility, password FROM chat_room WHERE name = ?";
+        let result = sqlx::query_as::<_, ChatRoom>(query)
+            .bind(name)
+            .fetch_optional(&self.conn_pool)
+            .await;
         match result {
-            Ok(_) => true,
+            Ok(room) => room,
             Err(e) => {
-                println!("Error inserting user {} : {}", username, e.to_string());
-                false
+                println!("Error querying chat_room table for '{}' : {}", name, e);
+                None
             }
         }
     }
 
-    pub async fn set_user_session_id(
+    pub async fn rename_chat_room(&self, chat_room_id: &str, name: &str) -> Result<(), Error> {
+        let query = "UPDATE chat_room SET name = ? WHERE chat_room_id = ?";
+        let result = sqlx::query(query)
+            .bind(name)
+            .bind(chat_room_id)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                println!("Error renaming chat room '{}' : {}", chat_room_id, e);
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn set_chat_room_details(
         &self,
-        username: &String,
-        session_id: Option<&String>,
+        chat_room_id: &str,
+        topic: Option<&String>,
+        description: Option<&String>,
     ) -> bool {
-        let session_id_value = match session_id {
-            Some(id) => format!("\"{}\"", id),
-            None => String::from("null"),
-        };
-        let query = format!(
-            "UPDATE user SET session_id = {} WHERE username = \"{}\";",
-            session_id_value, username
-        );
-        let result = sqlx::query(&query).execute(&self.conn_pool).await;
+        let query = "UPDATE chat_room SET topic = ?, description = ? WHERE chat_room_id = ?";
+        let result = sqlx::query(query)
+            .bind(topic)
+            .bind(description)
+            .bind(chat_room_id)
+            .execute(&self.conn_pool)
+            .await;
         match result {
             Ok(_) => true,
             Err(e) => {
-                println!("Error inserting user {} : {}", username, e.to_string());
+                println!(
+                    "Error updating details of chat room '{}' : {}",
+                    chat_room_id, e
+                );
                 false
             }
         }
     }
 
-    async fn set_chat_id(&self, user1: &str, user2: &str, chat_id: &str) -> bool {
-        let query = "UPDATE private_chat SET chat_id = ? WHERE user1 = ? AND user2 = ?";
-        let result = sqlx::query(&query)
-            .bind(chat_id)
-            .bind(user1)
-            .bind(user2)
+    /// Deletes a chat room together with its messages. Members, bans and
+    /// invites go with it through their foreign keys.
+    pub async fn delete_chat_room(&self, chat_room_id: &str) -> bool {
+        let result = sqlx::query("DELETE FROM chat_room WHERE chat_room_id = ?")
+            .bind(chat_room_id)
+            .execute(&self.conn_pool)
+            .await;
+        if let Err(e) = result {
+            println!("Error deleting chat room '{}' : {}", chat_room_id, e);
+            return false;
+        }
+
+        let result = sqlx::query("DELETE FROM chat_message WHERE chat_id = ?")
+            .bind(chat_room_id)
             .execute(&self.conn_pool)
             .await;
         match result {
             Ok(_) => true,
             Err(e) => {
                 println!(
-                    "Error inserting chat id for chat between '{}' and '{}' : {}",
-                    user1,
-                    user2,
-                    e.to_string()
+                    "Error deleting messages of chat room '{}' : {}",
+                    chat_room_id, e
                 );
                 false
             }
         }
     }
 
-    async fn set_chat_room_id(&self, id: u64, room_name: &str, chat_id: &str) -> bool {
-        let query = "UPDATE chat_room SET chat_room_id = ? WHERE id = ? AND name = ?";
-        let result = sqlx::query(&query)
-            .bind(chat_id)
-            .bind(id)
-            .bind(room_name)
+    pub async fn insert_room_member(
+        &self,
+        chat_room_id: &str,
+        username: &str,
+        role: RoomRole,
+    ) -> bool {
+        let query = r#"
+            INSERT INTO room_member (room_id, username, role)
+            SELECT id, ?, ? FROM chat_room WHERE chat_room_id = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(role.as_str())
+            .bind(chat_room_id)
             .execute(&self.conn_pool)
             .await;
         match result {
-            Ok(_) => true,
+            Ok(res) => res.rows_affected() == 1,
             Err(e) => {
                 println!(
-                    "Error inserting chat id for chat room '{}': {}",
-                    room_name,
-                    e.to_string()
+                    "Error adding user '{}' to chat room '{}': {}",
+                    username, chat_room_id, e
                 );
                 false
             }
         }
     }
 
-    pub async fn get_user(&self, username: &String) -> Option<User> {
-        let query = format!("SELECT * FROM user WHERE username = \"{}\";", username);
-        let result = sqlx::query_as::<_, User>(&query)
-            .fetch_one(&self.conn_pool)
+    pub async fn get_room_role(&self, chat_room_id: &str, username: &str) -> Option<RoomRole> {
+        let query = r#"
+            SELECT rm.role FROM room_member rm
+            JOIN chat_room cr ON rm.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rm.username = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(chat_room_id)
+            .bind(username)
+            .fetch_optional(&self.conn_pool)
             .await;
         match result {
-            Ok(user) => Some(user),
+            Ok(row) => row.and_then(|r| RoomRole::parse(r.get::<String, _>("role").as_str())),
             Err(e) => {
-                println!("Error querying user table for {} : {}", username, e);
+                println!(
+                    "Error querying room_member table for user '{}' in chat room '{}': {}",
+                    username, chat_room_id, e
+                );
                 None
             }
         }
     }
 
-    pub async fn get_all_users(&self) -> Option<Vec<User>> {
-        let query = "SELECT * FROM user;";
-        let result = sqlx::query_as::<_, User>(&query)
+    pub async fn get_room_members(&self, chat_room_id: &str) -> Option<Vec<RoomMember>> {
+        let query = r#"
+            SELECT rm.username, rm.role FROM room_member rm
+            JOIN chat_room cr ON rm.room_id = cr.id
+            WHERE cr.chat_room_id = ?
+            ORDER BY rm.id
+            "#;
+        let result = sqlx::query_as::<_, RoomMember>(query)
+            .bind(chat_room_id)
             .fetch_all(&self.conn_pool)
             .await;
         match result {
-            Ok(users) => Some(users),
+            Ok(members) => Some(members),
             Err(e) => {
-                println!("Error querying user table: {}", e);
+                println!(
+                    "Error querying room_member table for chat room '{}': {}",
+                    chat_room_id, e
+                );
                 None
             }
         }
     }
 
-    pub async fn get_all_chat_rooms(&self) -> Option<Vec<(String, String)>> {
-        let query = "SELECT chat_room_id, name FROM chat_room";
-        let result = sqlx::query_as::<_, ChatRoom>(&query)
-            .fetch_all(&self.conn_pool)
+    pub async fn set_room_role(&self, chat_room_id: &str, username: &str, role: RoomRole) -> bool {
+        let query = r#"
+            UPDATE room_member rm
+            JOIN chat_room cr ON rm.room_id = cr.id
+            SET rm.role = ?
+            WHERE cr.chat_room_id = ? AND rm.username = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(role.as_str())
+            .bind(chat_room_id)
+            .bind(username)
+            .execute(&self.conn_pool)
             .await;
         match result {
-            Ok(chat_rooms) => {
-                if chat_rooms.is_empty() {
-                    None
-                } else {
-                    let mut names = Vec::new();
-                    for room in chat_rooms {
-                        names.push((room.chat_room_id, room.name));
-                    }
-                    Some(names)
-                }
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error setting role of user '{}' in chat room '{}': {}",
+                    username, chat_room_id, e
+                );
+                false
             }
+        }
+    }
+
+    pub async fn remove_room_member(&self, chat_room_id: &str, username: &str) -> bool {
+        let query = r#"
+            DELETE rm FROM room_member rm
+            JOIN chat_room cr ON rm.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rm.username = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(chat_room_id)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
             Err(e) => {
-                println!("Error querying chat_room table: {}", e);
-                None
+                println!(
+                    "Error removing user '{}' from chat room '{}': {}",
+                    username, chat_room_id, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn insert_room_ban(
+        &self,
+        chat_room_id: &str,
+        username: &str,
+        banned_by: &str,
+    ) -> bool {
+        let query = r#"
+            INSERT IGNORE INTO room_ban (room_id, username, banned_by)
+            SELECT id, ?, ? FROM chat_room WHERE chat_room_id = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(banned_by)
+            .bind(chat_room_id)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error banning user '{}' from chat room '{}': {}",
+                    username, chat_room_id, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn delete_room_ban(&self, chat_room_id: &str, username: &str) -> bool {
+        let query = r#"
+            DELETE rb FROM room_ban rb
+            JOIN chat_room cr ON rb.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rb.username = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(chat_room_id)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error unbanning user '{}' from chat room '{}': {}",
+                    username, chat_room_id, e
+                );
+                false
             }
         }
     }
+
+    pub async fn is_banned(&self, chat_room_id: &str, username: &str) -> bool {
+        let query = r#"
+            SELECT rb.id FROM room_ban rb
+            JOIN chat_room cr ON rb.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rb.username = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(chat_room_id)
+            .bind(username)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => row.is_some(),
+            Err(e) => {
+                println!(
+                    "Error querying room_ban table for user '{}': {}",
+                    username, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn insert_user(
+        &self,
+        username: &String,
+        email: &String,
+        password: &String,
+        verification_code: Option<&String>,
+        verification_ttl_minutes: u32,
+    ) -> Result<(), Error> {
+        let query = "INSERT INTO user (username, email, password, verified, verification_code, verification_expires_at) VALUES (?, ?, ?, ?, ?, IF(? IS NULL, NULL, NOW() + INTERVAL ? MINUTE))";
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(email)
+            .bind(password)
+            .bind(verification_code.is_none())
+            .bind(verification_code)
+            .bind(verification_code)
+            .bind(verification_ttl_minutes)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                println!("Error inserting user {} : {}", username, e);
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn get_user_by_email(&self, email: &String) -> Option<User> {
+        let query = "SELECT * FROM user WHERE email = ?";
+        let result = sqlx::query_as::<_, User>(query)
+            .bind(email)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(user) => user,
+            Err(e) => {
+                println!("Error querying user table for email {} : {}", email, e);
+                None
+            }
+        }
+    }
+
+    pub async fn set_user_verified(&self, username: &String) -> bool {
+        let query = "UPDATE user SET verified = TRUE, verification_code = NULL, verification_expires_at = NULL, verification_attempts = 0 WHERE username = ?";
+        let result = sqlx::query(query)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error verifying user {} : {}", username, e);
+                false
+            }
+        }
+    }
+
+    pub async fn set_verification_code(
+        &self,
+        username: &String,
+        code: &String,
+        ttl_minutes: u32,
+    ) -> bool {
+        let query = "UPDATE user SET verification_code = ?, verification_expires_at = NOW() + INTERVAL ? MINUTE, verification_attempts = 0 WHERE username = ? AND NOT verified";
+        let result = sqlx::query(query)
+            .bind(code)
+            .bind(ttl_minutes)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error setting verification code for user {} : {}",
+                    username, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn is_verification_code_valid(&self, username: &String, code: &String) -> bool {
+        let query = "SELECT username FROM user WHERE username = ? AND verification_code = ? AND verification_expires_at > NOW()";
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(code)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => row.is_some(),
+            Err(e) => {
+                println!(
+                    "Error checking verification code for user {} : {}",
+                    username, e
+                );
+                false
+            }
+        }
+    }
+
+    /// Counts a wrong verification code and drops the pending code once
+    /// `max_attempts` have failed. Returns whether the code is still pending.
+    pub async fn record_verification_failure(&self, username: &String, max_attempts: u32) -> bool {
+        // MySQL applies SET assignments left to right, so the second one sees
+        // the incremented attempt count.
+        let query = "UPDATE user SET verification_attempts = verification_attempts + 1, verification_code = IF(verification_attempts >= ?, NULL, verification_code) WHERE username = ? AND NOT verified";
+        let result = sqlx::query(query)
+            .bind(max_attempts)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        if let Err(e) = result {
+            println!(
+                "Error recording verification failure for user {} : {}",
+                username, e
+            );
+        }
+        self.get_user(username)
+            .await
+            .is_some_and(|user| user.verification_code.is_some())
+    }
+
+    pub async fn set_user_password(&self, username: &String, password: &String) -> bool {
+        let query = "UPDATE user SET password = ? WHERE username = ?";
+        let result = sqlx::query(query)
+            .bind(password)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error updating password for user {} : {}", username, e);
+                false
+            }
+        }
+    }
+
+    pub async fn insert_password_reset(
+        &self,
+        username: &String,
+        token: &String,
+        ttl_minutes: u32,
+    ) -> bool {
+        let query = "INSERT INTO password_reset (token, username, expires_at) VALUES (?, ?, NOW() + INTERVAL ? MINUTE)";
+        let result = sqlx::query(query)
+            .bind(token)
+            .bind(username)
+            .bind(ttl_minutes)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error inserting password reset for user {} : {}",
+                    username, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn is_password_reset_valid(&self, username: &String, token: &String) -> bool {
+        let query = "SELECT token FROM password_reset WHERE token = ? AND username = ? AND expires_at > NOW()";
+        let result = sqlx::query(query)
+            .bind(token)
+            .bind(username)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => row.is_some(),
+            Err(e) => {
+                println!(
+                    "Error querying password_reset table for {} : {}",
+                    username, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn delete_password_resets(&self, username: &String) -> bool {
+        let query = "DELETE FROM password_reset WHERE username = ?";
+        let result = sqlx::query(query)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error deleting password resets for {} : {}", username, e);
+                false
+            }
+        }
+    }
+
+    pub async fn set_user_session_id(
+        &self,
+        username: &String,
+        session_id: Option<&String>,
+    ) -> bool {
+        let query = "UPDATE user SET session_id = ? WHERE username = ?";
+        let result = sqlx::query(query)
+            .bind(session_id)
+            .bind(username)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error inserting user {} : {}", username, e);
+                false
+            }
+        }
+    }
+
+    async fn set_chat_id(&self, user1: &str, user2: &str, chat_id: &str) -> bool {
+        let query = "UPDATE private_chat SET chat_id = ? WHERE user1 = ? AND user2 = ?";
+        let result = sqlx::query(query)
+            .bind(chat_id)
+            .bind(user1)
+            .bind(user2)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error inserting chat id for chat between '{}' and '{}' : {}",
+                    user1, user2, e
+                );
+                false
+            }
+        }
+    }
+
+    async fn set_chat_room_id(&self, id: u64, room_name: &str, chat_id: &str) -> bool {
+        let query = "UPDATE chat_room SET chat_room_id = ? WHERE id = ? AND name = ?";
+        let result = sqlx::query(query)
+            .bind(chat_id)
+            .bind(id)
+            .bind(room_name)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error inserting chat id for chat room '{}': {}",
+                    room_name, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn get_user(&self, username: &String) -> Option<User> {
+        let query = "SELECT * FROM user WHERE username = ?";
+        let result = sqlx::query_as::<_, User>(query)
+            .bind(username)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(user) => user,
+            Err(e) => {
+                println!("Error querying user table for {} : {}", username, e);
+                None
+            }
+        }
+    }
+
+    pub async fn get_all_users(&self) -> Option<Vec<User>> {
+        let query = "SELECT * FROM user;";
+        let result = sqlx::query_as::<_, User>(query)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(users) => Some(users),
+            Err(e) => {
+                println!("Error querying user table: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the chat rooms the user may see: every room that is not
+    /// private, plus the private rooms the user belongs to or is invited to.
+    pub async fn get_all_chat_rooms(&self, username: &str) -> Option<Vec<ChatRoom>> {
+        let query = r#"
+            SELECT cr.chat_room_id, cr.name, cr.topic, cr.description, cr.visibility, cr.password
+            FROM chat_room cr
+            WHERE cr.visibility <> 'private'
+                OR EXISTS (SELECT 1 FROM room_member rm WHERE rm.room_id = cr.id AND rm.username = ?)
+                OR EXISTS (
+                    SELECT 1 FROM room_invite ri
+                    WHERE ri.room_id = cr.id AND ri.invitee = ? AND ri.status = 'pending'
+                )
+            "#;
+        let result = sqlx::query_as::<_, ChatRoom>(query)
+            .bind(username)
+            .bind(username)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(chat_rooms) => {
+                if chat_rooms.is_empty() {
+                    None
+                } else {
+                    Some(chat_rooms)
+                }
+            }
+            Err(e) => {
+                println!("Error querying chat_room table: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn upsert_room_invite(
+        &self,
+        chat_room_id: &str,
+        invitee: &str,
+        inviter: &str,
+    ) -> bool {
+        let query = r#"
+            INSERT INTO room_invite (room_id, invitee, inviter, status)
+            SELECT id, ?, ?, 'pending' FROM chat_room WHERE chat_room_id = ?
+            ON DUPLICATE KEY UPDATE inviter = VALUES(inviter), status = 'pending'
+            "#;
+        let result = sqlx::query(query)
+            .bind(invitee)
+            .bind(inviter)
+            .bind(chat_room_id)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error inviting user '{}' to chat room '{}': {}",
+                    invitee, chat_room_id, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn get_room_invite_status(
+        &self,
+        chat_room_id: &str,
+        invitee: &str,
+    ) -> Option<String> {
+        let query = r#"
+            SELECT ri.status FROM room_invite ri
+            JOIN chat_room cr ON ri.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND ri.invitee = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(chat_room_id)
+            .bind(invitee)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => row.map(|r| r.get::<String, _>("status")),
+            Err(e) => {
+                println!("Error querying room_invite table for {} : {}", invitee, e);
+                None
+            }
+        }
+    }
+
+    pub async fn set_room_invite_status(
+        &self,
+        chat_room_id: &str,
+        invitee: &str,
+        status: &str,
+    ) -> bool {
+        let query = r#"
+            UPDATE room_invite ri
+            JOIN chat_room cr ON ri.room_id = cr.id
+            SET ri.status = ?
+            WHERE cr.chat_room_id = ? AND ri.invitee = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(status)
+            .bind(chat_room_id)
+            .bind(invitee)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error updating invite of user '{}' : {}", invitee, e);
+                false
+            }
+        }
+    }
+
+    pub async fn get_pending_invites(&self, username: &str) -> Option<Vec<PendingInvite>> {
+        let query = r#"
+            SELECT cr.chat_room_id, cr.name, ri.inviter FROM room_invite ri
+            JOIN chat_room cr ON ri.room_id = cr.id
+            WHERE ri.invitee = ? AND ri.status = 'pending'
+            ORDER BY ri.id
+            "#;
+        let result = sqlx::query_as::<_, PendingInvite>(query)
+            .bind(username)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(invites) => Some(invites),
+            Err(e) => {
+                println!("Error querying room_invite table for {} : {}", username, e);
+                None
+            }
+        }
+    }
+
+    pub async fn insert_user_block(&self, blocker: &str, blocked: &str) -> bool {
+        let query = "INSERT IGNORE INTO user_block (blocker, blocked) VALUES (?, ?)";
+        let result = sqlx::query(query)
+            .bind(blocker)
+            .bind(blocked)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error blocking user '{}' for '{}': {}", blocked, blocker, e);
+                false
+            }
+        }
+    }
+
+    pub async fn delete_user_block(&self, blocker: &str, blocked: &str) -> bool {
+        let query = "DELETE FROM user_block WHERE blocker = ? AND blocked = ?";
+        let result = sqlx::query(query)
+            .bind(blocker)
+            .bind(blocked)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error unblocking user '{}' for '{}': {}",
+                    blocked, blocker, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn is_blocked(&self, blocker: &str, blocked: &str) -> bool {
+        let query = "SELECT id FROM user_block WHERE blocker = ? AND blocked = ?";
+        let result = sqlx::query(query)
+            .bind(blocker)
+            .bind(blocked)
+            .fetch_optional(&self.conn_pool)
+            .await;
+        match result {
+            Ok(row) => row.is_some(),
+            Err(e) => {
+                println!("Error querying user_block table for {} : {}", blocker, e);
+                false
+            }
+        }
+    }
+
+    pub async fn get_blocked_users(&self, blocker: &str) -> Option<Vec<String>> {
+        let query = "SELECT blocked FROM user_block WHERE blocker = ? ORDER BY blocked";
+        let result = sqlx::query(query)
+            .bind(blocker)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(rows) => Some(rows.iter().map(|row| row.get("blocked")).collect()),
+            Err(e) => {
+                println!("Error querying user_block table for {} : {}", blocker, e);
+                None
+            }
+        }
+    }
+
+    pub async fn insert_room_mute(&self, chat_room_id: &str, username: &str, muted: &str) -> bool {
+        let query = r#"
+            INSERT IGNORE INTO room_mute (room_id, username, muted)
+            SELECT id, ?, ? FROM chat_room WHERE chat_room_id = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(muted)
+            .bind(chat_room_id)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error muting user '{}' in chat room '{}' for '{}': {}",
+                    muted, chat_room_id, username, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn delete_room_mute(&self, chat_room_id: &str, username: &str, muted: &str) -> bool {
+        let query = r#"
+            DELETE rmu FROM room_mute rmu
+            JOIN chat_room cr ON rmu.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rmu.username = ? AND rmu.muted = ?
+            "#;
+        let result = sqlx::query(query)
+            .bind(chat_room_id)
+            .bind(username)
+            .bind(muted)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error unmuting user '{}' in chat room '{}' for '{}': {}",
+                    muted, chat_room_id, username, e
+                );
+                false
+            }
+        }
+    }
+
+    /// Users `username` has muted in the chat room. Empty for any other topic.
+    pub async fn get_room_mutes(&self, chat_room_id: &str, username: &str) -> Option<Vec<String>> {
+        let query = r#"
+            SELECT rmu.muted FROM room_mute rmu
+            JOIN chat_room cr ON rmu.room_id = cr.id
+            WHERE cr.chat_room_id = ? AND rmu.username = ?
+            ORDER BY rmu.muted
+            "#;
+        let result = sqlx::query(query)
+            .bind(chat_room_id)
+            .bind(username)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(rows) => Some(rows.iter().map(|row| row.get("muted")).collect()),
+            Err(e) => {
+                println!("Error querying room_mute table for {} : {}", username, e);
+                None
+            }
+        }
+    }
+
+    /// Returns the notifications kept for the user, oldest first, and
+    /// forgets them.
+    pub async fn take_pending_notifications(
+        &self,
+        username: &str,
+    ) -> Option<Vec<PendingNotification>> {
+        let query = r#"
+            SELECT id, kind, content, chat_id,
+                DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at
+            FROM notification WHERE username = ? ORDER BY id
+            "#;
+        let result = sqlx::query_as::<_, PendingNotification>(query)
+            .bind(username)
+            .fetch_all(&self.conn_pool)
+            .await;
+        let notifications = match result {
+            Ok(notifications) => notifications,
+            Err(e) => {
+                println!("Error querying notification table for {} : {}", username, e);
+                return None;
+            }
+        };
+        // Only what was read is deleted, so notifications saved in between
+        // are kept for the next login.
+        if let Some(last) = notifications.last() {
+            let query = "DELETE FROM notification WHERE username = ? AND id <= ?";
+            let result = sqlx::query(query)
+                .bind(username)
+                .bind(last.id)
+                .execute(&self.conn_pool)
+                .await;
+            if let Err(e) = result {
+                println!("Error deleting notifications for {} : {}", username, e);
+            }
+        }
+        Some(notifications)
+    }
+
+    /// Conversations of the user with messages from others that are newer
+    /// than their last read position, most recent first. Messages from users
+    /// they blocked or muted are not counted.
+    pub async fn get_unread_conversations(
+        &self,
+        username: &str,
+    ) -> Option<Vec<UnreadConversation>> {
+        let query = format!(
+            r#"
+            SELECT c.chat_id, c.kind, c.name, COUNT(*) AS unread,
+                GROUP_CONCAT(DISTINCT cm.username ORDER BY cm.username) AS senders
+            FROM ({}) AS c
+            JOIN chat_message cm ON cm.chat_id = c.chat_id
+            LEFT JOIN last_read lr ON lr.username = ? AND lr.chat_id = c.chat_id
+            WHERE cm.username <> ?
+            AND cm.id > COALESCE(lr.last_message_id, 0)
+            AND cm.username NOT IN (
+                SELECT blocked FROM user_block WHERE blocker = ?
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM room_mute rmu
+                JOIN chat_room mcr ON rmu.room_id = mcr.id
+                WHERE mcr.chat_room_id = c.chat_id AND rmu.username = ?
+                AND rmu.muted = cm.username
+            )
+            GROUP BY c.chat_id, c.kind, c.name
+            ORDER BY MAX(cm.timestamp) DESC
+            "#,
+            USER_CONVERSATIONS
+        );
+        let result = sqlx::query_as::<_, UnreadConversation>(&query)
+            .bind(username)
+            .bind(username)
+            .bind(username)
+            .bind(username)
+            .bind(username)
+            .bind(username)
+            .bind(username)
+            .bind(username)
+            .bind(username)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(conversations) => Some(conversations),
+            Err(e) => {
+                println!("Error querying unread messages for {} : {}", username, e);
+                None
+            }
+        }
+    }
+
+    /// Messages in the user's conversations matching the search, newest first.
+    /// Messages from users they blocked or muted are left out.
+    pub async fn search_messages(
+        &self,
+        username: &str,
+        search: &MessageSearch<'_>,
+    ) -> Option<Vec<SearchHit>> {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if let Some(text) = search.text {
+            conditions.push("AND MATCH(cm.message) AGAINST (? IN BOOLEAN MODE)");
+            params.push(text);
+        }
+        if let Some(sender) = search.sender {
+            conditions.push("AND cm.username = ?");
+            params.push(sender);
+        }
+        if let Some(since) = search.since {
+            conditions.push("AND cm.timestamp >= STR_TO_DATE(?, '%Y-%m-%d')");
+            params.push(since);
+        }
+        if let Some(until) = search.until {
+            conditions.push("AND cm.timestamp < STR_TO_DATE(?, '%Y-%m-%d') + INTERVAL 1 DAY");
+            params.push(until);
+        }
+        let query = format!(
+            r#"
+            SELECT c.chat_id, c.kind, c.name, cm.username AS sender, cm.message,
+                DATE_FORMAT(cm.timestamp, '%Y-%m-%d %H:%i:%s') AS sent_at
+            FROM ({}) AS c
+            JOIN chat_message cm ON cm.chat_id = c.chat_id
+            WHERE cm.username NOT IN (
+                SELECT blocked FROM user_block WHERE blocker = ?
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM room_mute rmu
+                JOIN chat_room mcr ON rmu.room_id = mcr.id
+                WHERE mcr.chat_room_id = c.chat_id AND rmu.username = ?
+                AND rmu.muted = cm.username
+            )
+            {}
+            ORDER BY cm.timestamp DESC, cm.id DESC
+            LIMIT ?
+            "#,
+            USER_CONVERSATIONS,
+            conditions.join("\n            ")
+        );
+
+        let mut query = sqlx::query_as::<_, SearchHit>(&query);
+        for _ in 0..7 {
+            query = query.bind(username);
+        }
+        for param in params {
+            query = query.bind(param);
+        }
+        let result = query.bind(search.limit).fetch_all(&self.conn_pool).await;
+        match result {
+            Ok(hits) => Some(hits),
+            Err(e) => {
+                println!("Error searching messages for {} : {}", username, e);
+                None
+            }
+        }
+    }
+
+    /// The latest messages mentioning the user, newest first. Mentions in
+    /// chat rooms the user has left are not included.
+    pub async fn get_mentions(&self, username: &str, limit: u32) -> Option<Vec<Mention>> {
+        let query = r#"
+            SELECT m.chat_id, cr.name AS room_name, m.sender, cm.message,
+                DATE_FORMAT(m.created_at, '%Y-%m-%d %H:%i:%s') AS created_at
+            FROM mention m
+            JOIN chat_message cm ON cm.message_id = m.message_id
+            LEFT JOIN chat_room cr ON cr.chat_room_id = m.chat_id
+            WHERE m.username = ?
+            AND (cr.id IS NULL OR EXISTS (
+                SELECT 1 FROM room_member rm WHERE rm.room_id = cr.id AND rm.username = ?
+            ))
+            ORDER BY m.id DESC
+            LIMIT ?
+            "#;
+        let result = sqlx::query_as::<_, Mention>(query)
+            .bind(username)
+            .bind(username)
+            .bind(limit)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(mentions) => Some(mentions),
+            Err(e) => {
+                println!("Error querying mention table for {} : {}", username, e);
+                None
+            }
+        }
+    }
+
+    /// Every message of a conversation, oldest first, optionally from a day
+    /// (YYYY-MM-DD) on. Rows are fetched as the stream is polled, so long
+    /// histories are never held in memory at once.
+    pub fn stream_messages(
+        &self,
+        chat_id: &str,
+        since: Option<&str>,
+    ) -> BoxStream<'_, Result<ExportedMessage, Error>> {
+        let query = r#"
+            SELECT message_id, username AS sender, message,
+                DATE_FORMAT(timestamp, '%Y-%m-%d %H:%i:%s') AS sent_at
+            FROM chat_message
+            WHERE chat_id = ?
+            AND (? IS NULL OR timestamp >= STR_TO_DATE(?, '%Y-%m-%d'))
+            ORDER BY timestamp, id
+            "#;
+        sqlx::query_as::<_, ExportedMessage>(query)
+            .bind(chat_id.to_string())
+            .bind(since.map(String::from))
+            .bind(since.map(String::from))
+            .fetch(&self.conn_pool)
+    }
+
+    /// Creates a verified user without an email for imported history. The
+    /// password is random, so the account cannot be logged into.
+    pub async fn insert_imported_user(&self, username: &str) -> Result<(), Error> {
+        let query = "INSERT INTO user (username, password, verified) VALUES (?, ?, TRUE)";
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(Uuid::new_v4().to_string())
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                println!("Error inserting imported user {} : {}", username, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// The id of the conversation's most recently stored message, 0 if none.
+    pub async fn get_last_message_id(&self, chat_id: &str) -> Result<i64, Error> {
+        let query = "SELECT COALESCE(MAX(id), 0) AS last_id FROM chat_message WHERE chat_id = ?";
+        let row = sqlx::query(query)
+            .bind(chat_id)
+            .fetch_one(&self.conn_pool)
+            .await?;
+        Ok(row.get("last_id"))
+    }
+
+    /// Moves read positions past messages imported after `last_message_id`,
+    /// which are older than they look from their ids: users who had read up
+    /// to it, and the `new_members` of a conversation created by the import,
+    /// have read everything.
+    pub async fn skip_imported_messages(
+        &self,
+        chat_id: &str,
+        last_message_id: i64,
+        new_members: &[String],
+    ) -> Result<(), Error> {
+        let query = r#"
+            UPDATE last_read lr
+            JOIN (SELECT MAX(id) AS last_id FROM chat_message WHERE chat_id = ?) cm
+            SET lr.last_message_id = cm.last_id
+            WHERE lr.chat_id = ? AND lr.last_message_id >= ?
+            "#;
+        sqlx::query(query)
+            .bind(chat_id)
+            .bind(chat_id)
+            .bind(last_message_id)
+            .execute(&self.conn_pool)
+            .await?;
+
+        let query = r#"
+            INSERT IGNORE INTO last_read (username, chat_id, last_message_id)
+            SELECT ?, ?, COALESCE(MAX(id), 0) FROM chat_message WHERE chat_id = ?
+            "#;
+        for username in new_members {
+            sqlx::query(query)
+                .bind(username)
+                .bind(chat_id)
+                .bind(chat_id)
+                .execute(&self.conn_pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Stores an imported message with its original id, author and time
+    /// (YYYY-MM-DD HH:MM:SS).
+    pub async fn insert_imported_message(
+        &self,
+        message_id: &str,
+        chat_id: &str,
+        sender: &str,
+        message: &str,
+        sent_at: &str,
+    ) -> Result<(), Error> {
+        let query = r#"
+            INSERT INTO chat_message (message_id, chat_id, username, message, timestamp)
+            VALUES (?, ?, ?, ?, STR_TO_DATE(?, '%Y-%m-%d %H:%i:%s'))
+            "#;
+        sqlx::query(query)
+            .bind(message_id)
+            .bind(chat_id)
+            .bind(sender)
+            .bind(message)
+            .bind(sent_at)
+            .execute(&self.conn_pool)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn insert_login_attempt(
+        &self,
+        username: &String,
+        ip_address: Option<&String>,
+        reason: &str,
+    ) -> bool {
+        let query = "INSERT INTO login_attempt (username, ip_address, reason) VALUES (?, ?, ?)";
+        let result = sqlx::query(query)
+            .bind(username)
+            .bind(ip_address)
+            .bind(reason)
+            .execute(&self.conn_pool)
+            .await;
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                println!(
+                    "Error inserting login attempt for user {} : {}",
+                    username, e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn get_login_attempts(
+        &self,
+        username: Option<&String>,
+        limit: u32,
+    ) -> Option<Vec<LoginAttempt>> {
+        let query = r#"
+            SELECT username, ip_address, reason,
+                DATE_FORMAT(attempted_at, '%Y-%m-%d %H:%i:%s') AS attempted_at
+            FROM login_attempt
+            WHERE ? IS NULL OR username = ?
+            ORDER BY id DESC
+            LIMIT ?
+            "#;
+        let result = sqlx::query_as::<_, LoginAttempt>(query)
+            .bind(username)
+            .bind(username)
+            .bind(limit)
+            .fetch_all(&self.conn_pool)
+            .await;
+        match result {
+            Ok(attempts) => Some(attempts),
+            Err(e) => {
+                println!("Error querying login_attempt table: {}", e);
+                None
+            }
+        }
+    }
+}
+
+pub fn is_unique_violation(e: &Error) -> bool {
+    e.as_database_error()
+        .map(|db_error| db_error.is_unique_violation())
+        .unwrap_or(false)
 }
@@ -0,0 +1,520 @@
+use pubsub::validation::{
+    validate_date, validate_message_content, validate_room_name, validate_username,
+    MAX_MESSAGE_LENGTH_LIMIT,
+};
+use rocket::data::{Data, Limits};
+use rocket::serde::json::{self, Json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{get, post};
+use std::collections::{BTreeSet, HashSet};
+use uuid::Uuid;
+
+use crate::database::{is_unique_violation, DbManager, RoomRole, RoomVisibility};
+
+use super::chat::{ConversationExport, ExportConversation, MAX_GROUP_CHAT_SIZE};
+use super::common::{require_admin, UserReqInfo};
+use super::error::{ApiError, ApiResult};
+
+const DEFAULT_FAILED_LOGINS_LIMIT: u32 = 50;
+const MAX_FAILED_LOGINS_LIMIT: u32 = 500;
+// Imported messages must fit a TIMESTAMP column, which holds 1970 to 2038 UTC.
+// A day is left at each end so the session time zone cannot push them out.
+const MIN_SENT_AT: &str = "1970-01-02 00:00:00";
+const MAX_SENT_AT: &str = "2038-01-18 23:59:59";
+// Ids of imported messages that had none are derived in this namespace.
+const IMPORT_NAMESPACE: Uuid = Uuid::from_u128(0x76d39ef0_4e0a_4e05_a812_9d6dfd7ccae5);
+
+#[derive(Deserialize, Serialize)]
+pub struct FailedLoginResponse {
+    username: String,
+    ip_address: Option<String>,
+    reason: String,
+    attempted_at: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ImportReport {
+    chat_id: String,
+    created_conversation: bool,
+    created_users: Vec<String>,
+    imported: u32,
+    conflicts: Vec<ImportConflict>,
+}
+
+/// A message that was not imported, and why.
+#[derive(Deserialize, Serialize)]
+pub struct ImportConflict {
+    message_id: Option<String>,
+    sender: String,
+    reason: String,
+}
+
+#[get("/failed-logins?<username>&<limit>")]
+pub async fn failed_logins(
+    username: Option<String>,
+    limit: Option<u32>,
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<FailedLoginResponse>>> {
+    require_admin(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    match db_manager
+        .get_login_attempts(
+            username.as_ref(),
+            limit
+                .unwrap_or(DEFAULT_FAILED_LOGINS_LIMIT)
+                .min(MAX_FAILED_LOGINS_LIMIT),
+        )
+        .await
+    {
+        Some(attempts) => Ok(Json(
+            attempts
+                .into_iter()
+                .map(|attempt| FailedLoginResponse {
+                    username: attempt.username,
+                    ip_address: attempt.ip_address,
+                    reason: attempt.reason,
+                    attempted_at: attempt.attempted_at,
+                })
+                .collect(),
+        )),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve login attempts.",
+        ))),
+    }
+}
+
+/// Loads the messages of a JSON export into the conversation it describes,
+/// keeping their ids, senders and times. Users and the conversation itself are
+/// only created when asked to; messages that cannot be stored are reported as
+/// conflicts instead of failing the whole import.
+///
+/// Exports of long histories outgrow Rocket's JSON limit, so the body is read
+/// up to the `import` limit instead (see `ServerConfig::import_limit_mib`).
+#[post(
+    "/import?<create_users>&<create_conversation>",
+    format = "json",
+    data = "<data>"
+)]
+pub async fn import_conversation(
+    create_users: Option<bool>,
+    create_conversation: Option<bool>,
+    data: Data<'_>,
+    limits: &Limits,
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<ImportReport>> {
+    require_admin(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    let limit = limits.get("import").unwrap_or(Limits::JSON);
+    let body = data.open(limit).into_string().await.map_err(|e| {
+        println!("Failed to read an import: {}", e);
+        ApiError::BadRequest(String::from("Failed to read the export."))
+    })?;
+    if !body.is_complete() {
+        return Err(ApiError::Unprocessable(format!(
+            "The export is larger than the import limit of {}.",
+            limit
+        )));
+    }
+    let ConversationExport {
+        conversation,
+        messages,
+    } = json::from_str(&body)
+        .map_err(|e| ApiError::Unprocessable(format!("The export is not valid: {}", e)))?;
+    if !["private", "group", "room"].contains(&conversation.kind.as_str()) {
+        return Err(ApiError::Unprocessable(format!(
+            "Unknown conversation kind '{}'.",
+            conversation.kind
+        )));
+    }
+
+    // Everyone taking part has to exist before the conversation does.
+    let usernames: BTreeSet<&String> = conversation
+        .participants
+        .iter()
+        .map(|p| &p.username)
+        .chain(messages.iter().map(|m| &m.sender))
+        .collect();
+    let mut created_users = Vec::new();
+    let mut missing_users = HashSet::new();
+    for username in usernames {
+        if db_manager.get_user(username).await.is_some() {
+            continue;
+        }
+        if create_users.unwrap_or(false)
+            && validate_username(username).is_ok()
+            && db_manager.insert_imported_user(username).await.is_ok()
+        {
+            created_users.push(username.clone());
+        } else {
+            missing_users.insert(username.clone());
+        }
+    }
+
+    let (chat_id, created_conversation) = resolve_import_target(
+        db_manager.inner(),
+        &conversation,
+        &missing_users,
+        create_conversation.unwrap_or(false),
+        &user_info.username,
+    )
+    .await?;
+
+    let senders = conversation_members(db_manager.inner(), &conversation, &chat_id).await?;
+
+    let last_message_id = db_manager
+        .get_last_message_id(&chat_id)
+        .await
+        .map_err(|e| {
+            println!("Failed to query conversation '{}': {}", chat_id, e);
+            ApiError::Internal(String::from("Failed to import the conversation."))
+        })?;
+    let mut imported = 0;
+    let mut conflicts = Vec::new();
+    for message in messages {
+        // Messages are held to what the broker accepts, at its largest limit.
+        let reason = if missing_users.contains(&message.sender) {
+            Some(String::from("The sender does not exist."))
+        } else if !senders.contains(&message.sender) {
+            Some(String::from("The sender is not in the conversation."))
+        } else if !is_valid_sent_at(&message.sent_at) {
+            Some(String::from(
+                "sent_at must be YYYY-MM-DD HH:MM:SS, between 1970-01-02 and 2038-01-18.",
+            ))
+        } else if let Err(e) = validate_message_content(&message.message, MAX_MESSAGE_LENGTH_LIMIT)
+        {
+            Some(e)
+        } else {
+            let message_id = message.message_id.clone().unwrap_or_else(|| {
+                imported_message_id(
+                    &chat_id,
+                    &message.sender,
+                    &message.sent_at,
+                    &message.message,
+                )
+            });
+            match db_manager
+                .insert_imported_message(
+                    &message_id,
+                    &chat_id,
+                    &message.sender,
+                    &message.message,
+                    &message.sent_at,
+                )
+                .await
+            {
+                Ok(()) => None,
+                Err(e) if is_unique_violation(&e) => {
+                    Some(String::from("A message with this id already exists."))
+                }
+                Err(e) => {
+                    println!("Failed to import message into '{}': {}", chat_id, e);
+                    Some(String::from("Failed to store the message."))
+                }
+            }
+        };
+        match reason {
+            None => imported += 1,
+            Some(reason) => conflicts.push(ImportConflict {
+                message_id: message.message_id,
+                sender: message.sender,
+                reason,
+            }),
+        }
+    }
+    // Imported messages get the newest ids but are history, not unread news.
+    let new_members: Vec<String> = if created_conversation {
+        conversation
+            .participants
+            .iter()
+            .map(|p| p.username.clone())
+            .filter(|username| !missing_users.contains(username))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if let Err(e) = db_manager
+        .skip_imported_messages(&chat_id, last_message_id, &new_members)
+        .await
+    {
+        println!(
+            "Failed to update read positions of conversation '{}': {}",
+            chat_id, e
+        );
+    }
+    println!(
+        "Administrator '{}' imported {} messages into conversation '{}' ({} conflicts)",
+        user_info.username,
+        imported,
+        chat_id,
+        conflicts.len()
+    );
+
+    Ok(Json(ImportReport {
+        chat_id,
+        created_conversation,
+        created_users,
+        imported,
+        conflicts,
+    }))
+}
+
+// Finds the conversation to import into, or creates it when allowed. Chat
+// rooms are matched by id, then by name; a created room is private and owned
+// by its exported owner if they exist, otherwise by the administrator.
+async fn resolve_import_target(
+    db_manager: &DbManager,
+    conversation: &ExportConversation,
+    missing_users: &HashSet<String>,
+    create: bool,
+    admin: &str,
+) -> Result<(String, bool), ApiError> {
+    let participants: Vec<String> = conversation
+        .participants
+        .iter()
+        .map(|p| p.username.clone())
+        .collect();
+    let not_found = || {
+        ApiError::NotFound(String::from(
+            "The conversation does not exist. Allow creating it to import anyway.",
+        ))
+    };
+    let missing = participants.iter().find(|u| missing_users.contains(*u));
+
+    match conversation.kind.as_str() {
+        "private" => {
+            let [user1, user2] = participants.as_slice() else {
+                return Err(ApiError::Unprocessable(String::from(
+                    "A private chat needs exactly two participants.",
+                )));
+            };
+            if let Some(chat_id) = db_manager.get_chat_id(user1, user2).await {
+                return Ok((chat_id, false));
+            }
+            if !create {
+                return Err(not_found());
+            }
+            if let Some(username) = missing {
+                return Err(missing_participant(username));
+            }
+            db_manager
+                .insert_private_chat(user1, user2)
+                .await
+                .map(|chat_id| (chat_id, true))
+                .ok_or_else(|| {
+                    ApiError::Internal(String::from("Failed to create the private chat."))
+                })
+        }
+        "group" => {
+            if let Some(group) = db_manager.get_group_chat(&conversation.chat_id).await {
+                return Ok((group.chat_id, false));
+            }
+            if let Some(chat_id) = db_manager
+                .get_group_chat_by_participants(&participants)
+                .await
+            {
+                return Ok((chat_id, false));
+            }
+            if !create {
+                return Err(not_found());
+            }
+            if let Some(username) = missing {
+                return Err(missing_participant(username));
+            }
+            if participants.len() < 3 || participants.len() > MAX_GROUP_CHAT_SIZE {
+                return Err(ApiError::Unprocessable(format!(
+                    "A group chat needs between 3 and {} participants.",
+                    MAX_GROUP_CHAT_SIZE
+                )));
+            }
+            match db_manager.insert_group_chat(&participants, admin).await {
+                Ok(chat_id) => Ok((chat_id, true)),
+                Err(e) => {
+                    println!("Failed to create imported group chat: {}", e);
+                    Err(ApiError::Internal(String::from(
+                        "Failed to create the group chat.",
+                    )))
+                }
+            }
+        }
+        _ => {
+            if let Some(room) = db_manager.get_chat_room(&conversation.chat_id).await {
+                return Ok((room.chat_room_id, false));
+            }
+            let name = conversation.name.as_deref().unwrap_or_default();
+            if let Some(room) = db_manager.get_chat_room_by_name(name).await {
+                return Ok((room.chat_room_id, false));
+            }
+            if !create {
+                return Err(not_found());
+            }
+            validate_room_name(name).map_err(ApiError::Unprocessable)?;
+
+            let members: Vec<(&String, RoomRole)> = conversation
+                .participants
+                .iter()
+                .filter(|p| !missing_users.contains(&p.username))
+                .map(|p| {
+                    let role = p.role.as_deref().and_then(RoomRole::parse);
+                    (&p.username, role.unwrap_or(RoomRole::Member))
+                })
+                .collect();
+            let owner = members
+                .iter()
+                .find(|(_, role)| *role == RoomRole::Owner)
+                .map(|(username, _)| username.as_str())
+                .unwrap_or(admin);
+            let chat_room_id = db_manager
+                .insert_chat_room(name, owner, RoomVisibility::Private, None)
+                .await
+                .ok_or_else(|| {
+                    ApiError::Internal(String::from("Failed to create the chat room."))
+                })?;
+            for (username, role) in members {
+                if username != owner {
+                    let role = if role == RoomRole::Owner {
+                        RoomRole::Member
+                    } else {
+                        role
+                    };
+                    db_manager
+                        .insert_room_member(&chat_room_id, username, role)
+                        .await;
+                }
+            }
+            Ok((chat_room_id, true))
+        }
+    }
+}
+
+// Who may have written in the conversation imported into: the two users of
+// a private chat, the participants of a group chat or the members of a room.
+async fn conversation_members(
+    db_manager: &DbManager,
+    conversation: &ExportConversation,
+    chat_id: &str,
+) -> Result<HashSet<String>, ApiError> {
+    let members = match conversation.kind.as_str() {
+        "private" => Some(
+            conversation
+                .participants
+                .iter()
+                .map(|p| p.username.clone())
+                .collect(),
+        ),
+        "group" => db_manager
+            .get_group_chat(chat_id)
+            .await
+            .map(|group| group.participants().into_iter().collect()),
+        _ => db_manager
+            .get_room_members(chat_id)
+            .await
+            .map(|members| members.into_iter().map(|m| m.username).collect()),
+    };
+    members.ok_or_else(|| ApiError::Internal(String::from("Failed to import the conversation.")))
+}
+
+fn missing_participant(username: &str) -> ApiError {
+    ApiError::Unprocessable(format!(
+        "Participant '{}' does not exist. Allow creating users to import anyway.",
+        username
+    ))
+}
+
+// The id of an imported message that has none. It only depends on the
+// message, so importing the same export again finds it already stored.
+fn imported_message_id(chat_id: &str, sender: &str, sent_at: &str, content: &str) -> String {
+    let name = [chat_id, sender, sent_at, content].join("\0");
+    Uuid::new_v5(&IMPORT_NAMESPACE, name.as_bytes()).to_string()
+}
+
+// Whether `sent_at` is a YYYY-MM-DD HH:MM:SS time, as written by the export,
+// that fits a TIMESTAMP column.
+fn is_valid_sent_at(sent_at: &str) -> bool {
+    let Some((date, time)) = sent_at.split_once(' ') else {
+        return false;
+    };
+    let parts: Vec<&str> = time.split(':').collect();
+    validate_date(date).is_ok()
+        && (MIN_SENT_AT..=MAX_SENT_AT).contains(&sent_at)
+        && parts.len() == 3
+        && parts.iter().zip([24, 60, 60]).all(|(part, max)| {
+            part.len() == 2
+                && part.bytes().all(|b| b.is_ascii_digit())
+                && part.parse::<u32>().is_ok_and(|value| value < max)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sent_at_accepts_export_times() {
+        assert!(is_valid_sent_at("2024-02-29 23:59:59"));
+        assert!(is_valid_sent_at("2024-06-01 00:00:00"));
+        assert!(is_valid_sent_at(MIN_SENT_AT));
+        assert!(is_valid_sent_at(MAX_SENT_AT));
+    }
+
+    #[test]
+    fn sent_at_rejects_malformed_times() {
+        assert!(!is_valid_sent_at(""));
+        assert!(!is_valid_sent_at("2024-06-01"));
+        assert!(!is_valid_sent_at("2024-06-01T12:00:00"));
+        assert!(!is_valid_sent_at("2024-06-01  12:00:00"));
+        assert!(!is_valid_sent_at("2024-06-01 12:00"));
+        assert!(!is_valid_sent_at("2024-06-01 12:00:00:00"));
+        assert!(!is_valid_sent_at("2024-06-01 1:00:00"));
+        assert!(!is_valid_sent_at("2024-06-01 +1:00:00"));
+        assert!(!is_valid_sent_at("2024-06-01 12:00:00.5"));
+        assert!(!is_valid_sent_at("2024-6-01 12:00:00"));
+    }
+
+    #[test]
+    fn imported_message_id_is_stable() {
+        let id = imported_message_id("chat", "alice", "2024-06-01 12:00:00", "hello");
+        assert_eq!(
+            id,
+            imported_message_id("chat", "alice", "2024-06-01 12:00:00", "hello")
+        );
+        assert_ne!(
+            id,
+            imported_message_id("chat", "alice", "2024-06-01 12:00:00", "hello!")
+        );
+        assert_ne!(
+            id,
+            imported_message_id("other", "alice", "2024-06-01 12:00:00", "hello")
+        );
+        // Fields cannot run into each other.
+        assert_ne!(
+            imported_message_id("chat", "al", "ice", "hello"),
+            imported_message_id("chat", "ali", "ce", "hello")
+        );
+    }
+
+    #[test]
+    fn sent_at_rejects_out_of_range_times() {
+        assert!(!is_valid_sent_at("2024-06-01 24:00:00"));
+        assert!(!is_valid_sent_at("2024-06-01 12:60:00"));
+        assert!(!is_valid_sent_at("2024-06-01 12:00:60"));
+        assert!(!is_valid_sent_at("2023-02-29 12:00:00"));
+        assert!(!is_valid_sent_at("2024-13-01 12:00:00"));
+        // TIMESTAMP columns only hold 1970 to 2038.
+        assert!(!is_valid_sent_at("1969-12-31 23:59:59"));
+        assert!(!is_valid_sent_at("1970-01-01 12:00:00"));
+        assert!(!is_valid_sent_at("2038-01-19 00:00:00"));
+        assert!(!is_valid_sent_at("2100-01-01 00:00:00"));
+    }
+}
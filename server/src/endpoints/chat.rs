@@ -1,23 +1,179 @@
-use rocket::http::Status;
-use rocket::serde::json::Json;
+use pubsub::client::{publish_notification, publish_system_message};
+use pubsub::common::NotificationKind;
+use pubsub::validation::{
+    validate_date, validate_room_description, validate_room_name, validate_room_password,
+    validate_room_topic, FieldError,
+};
+use rocket::futures::stream::{Stream, StreamExt};
+use rocket::http::{ContentType, Status};
+use rocket::response::stream::TextStream;
+use rocket::serde::json::{self, json, Json};
 use rocket::serde::{Deserialize, Serialize};
 use rocket::{get, post};
 
-use crate::database::DbManager;
+use crate::config::ServerConfig;
+use crate::database::{
+    is_unique_violation, ChatRoom, DbManager, ExportedMessage, GroupChat, MessageSearch, RoomRole,
+    RoomVisibility,
+};
 
-use super::common::{is_session_id_valid, UserReqInfo};
+use super::common::{require_session, UserReqInfo};
+use super::error::{ApiError, ApiResult};
+
+/// Fewest participants a group chat can have, including its creator.
+const MIN_GROUP_CHAT_SIZE: usize = 3;
+/// Most participants a group chat can have, including its creator.
+pub const MAX_GROUP_CHAT_SIZE: usize = 20;
+
+const DEFAULT_SEARCH_LIMIT: u32 = 20;
+const MAX_SEARCH_LIMIT: u32 = 100;
+// Shorter words are not in MySQL's full-text index (innodb_ft_min_token_size).
+const MIN_SEARCH_WORD_LENGTH: usize = 3;
+// Ends an export that failed partway, in every format.
+const EXPORT_FAILED_MARKER: &str = "\n*** Export failed: this transcript is incomplete. ***\n";
+
+#[derive(Deserialize, Serialize)]
+pub struct SearchHitResponse {
+    chat_id: String,
+    kind: String,
+    name: String,
+    sender: String,
+    message: String,
+    sent_at: String,
+}
+
+/// The conversation a JSON export is about, written before its messages.
+/// `kind` is `private`, `group` or `room`; `name` is only set for rooms.
+#[derive(Deserialize, Serialize)]
+pub struct ExportConversation {
+    pub chat_id: String,
+    pub kind: String,
+    pub name: Option<String>,
+    pub participants: Vec<ExportParticipant>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ExportParticipant {
+    pub username: String,
+    /// Role in the chat room; not set for private and group chats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+}
+
+/// A message as exported. Messages cannot be edited, so `message` is the text
+/// as it was sent and there is no edit history to export.
+#[derive(Deserialize, Serialize)]
+pub struct ExportMessage {
+    pub message_id: Option<String>,
+    pub sender: String,
+    pub sent_at: String,
+    pub message: String,
+}
+
+/// A whole JSON export, as accepted back by the admin import.
+#[derive(Deserialize, Serialize)]
+pub struct ConversationExport {
+    pub conversation: ExportConversation,
+    pub messages: Vec<ExportMessage>,
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct ChatRoomResponse {
     room_id: String,
     name: String,
+    topic: Option<String>,
+    visibility: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChatRoomInfoResponse {
+    room_id: String,
+    name: String,
+    topic: Option<String>,
+    description: Option<String>,
+    visibility: String,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct ChatRoomRequest {
     username: String,
     session_id: String,
-    room_name: String
+    room_name: String,
+    #[serde(default)]
+    visibility: Option<String>, // public (default), private or password
+    #[serde(default)]
+    password: Option<String>, // only for password-protected rooms
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChatRoomJoinRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomRenameRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomDetailsRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    // Fields left out are unchanged; an empty string clears them.
+    #[serde(default)]
+    topic: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomInviteResponse {
+    room_id: String,
+    name: String,
+    inviter: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomModerationRequest {
+    username: String,
+    session_id: String,
+    room_id: String,
+    target: String, // the member the action applies to
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomMemberResponse {
+    username: String,
+    role: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct GroupChatRequest {
+    username: String,
+    session_id: String,
+    participants: Vec<String>, // everyone else in the group
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct GroupMemberRequest {
+    username: String,
+    session_id: String,
+    chat_id: String,
+    target: String, // the participant to add or remove
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct GroupChatResponse {
+    chat_id: String,
+    participants: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -28,19 +184,63 @@ pub struct PrivateChatRequest {
 }
 
 #[post("/private-chat/create", format = "json", data = "<private_chat_info>")]
-pub async fn create_private_chat<'a>(
+pub async fn create_private_chat(
     private_chat_info: Json<PrivateChatRequest>,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, Json<String>) {
-    let valid_session: bool = is_session_id_valid(
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<(Status, Json<String>)> {
+    require_session(
         &private_chat_info.username,
         &private_chat_info.session_id,
         db_manager.inner(),
     )
-    .await;
+    .await?;
+
+    if private_chat_info.username == private_chat_info.recipient {
+        return Err(ApiError::Unprocessable(String::from(
+            "You cannot create a private chat with yourself.",
+        )));
+    }
+
+    if db_manager
+        .get_user(&private_chat_info.recipient)
+        .await
+        .is_none()
+    {
+        return Err(ApiError::NotFound(format!(
+            "User '{}' does not exist.",
+            &private_chat_info.recipient
+        )));
+    }
+
+    if db_manager
+        .is_blocked(&private_chat_info.username, &private_chat_info.recipient)
+        .await
+    {
+        return Err(ApiError::Forbidden(format!(
+            "You have blocked user '{}'. Unblock them first.",
+            &private_chat_info.recipient
+        )));
+    }
+    if db_manager
+        .is_blocked(&private_chat_info.recipient, &private_chat_info.username)
+        .await
+    {
+        return Err(ApiError::Forbidden(format!(
+            "User '{}' is not accepting private chats from you.",
+            &private_chat_info.recipient
+        )));
+    }
 
-    if !valid_session {
-        return (Status::Unauthorized, Json(String::new()));
+    if db_manager
+        .get_chat_id(&private_chat_info.username, &private_chat_info.recipient)
+        .await
+        .is_some()
+    {
+        return Err(ApiError::Conflict(format!(
+            "A private chat with user '{}' already exists. Use resume-chat instead.",
+            &private_chat_info.recipient
+        )));
     }
 
     let res = db_manager
@@ -52,32 +252,41 @@ pub async fn create_private_chat<'a>(
             "Private chat created between users '{}' and '{}'",
             &private_chat_info.username, &private_chat_info.recipient
         );
-        return (Status::Created, Json(id));
+        notify_user(
+            config.inner(),
+            &private_chat_info.recipient,
+            NotificationKind::PrivateChat,
+            format!(
+                "{} started a private chat with you.",
+                &private_chat_info.username
+            ),
+            Some(&id),
+        )
+        .await;
+        Ok((Status::Created, Json(id)))
     } else {
         println!(
             "Failed to create a private chat between users '{}' and '{}'",
             &private_chat_info.username, &private_chat_info.recipient
         );
-
-        return (Status::Unauthorized, Json(String::new()));
+        Err(ApiError::Internal(format!(
+            "Failed to create a private chat with user '{}'.",
+            &private_chat_info.recipient
+        )))
     }
 }
 
 #[post("/private-chat/resume", format = "json", data = "<private_chat_info>")]
-pub async fn resume_private_chat<'a>(
+pub async fn resume_private_chat(
     private_chat_info: Json<PrivateChatRequest>,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, Json<String>) {
-    let valid_session: bool = is_session_id_valid(
+) -> ApiResult<(Status, Json<String>)> {
+    require_session(
         &private_chat_info.username,
         &private_chat_info.session_id,
         db_manager.inner(),
     )
-    .await;
-
-    if !valid_session {
-        return (Status::Unauthorized, Json(String::new()));
-    }
+    .await?;
 
     let res = db_manager
         .get_chat_id(&private_chat_info.username, &private_chat_info.recipient)
@@ -87,113 +296,1731 @@ pub async fn resume_private_chat<'a>(
             "Private chat resumed between users '{}' and '{}'",
             &private_chat_info.username, &private_chat_info.recipient
         );
-        (Status::Created, Json(chat_id))
+        Ok((Status::Created, Json(chat_id)))
     } else {
         println!(
             "Failed to resume a private chat between users '{}' and '{}'",
             &private_chat_info.username, &private_chat_info.recipient
         );
-        (Status::InternalServerError, Json(String::new()))
+        Err(ApiError::NotFound(format!(
+            "No private chat with user '{}' exists.",
+            &private_chat_info.recipient
+        )))
+    }
+}
+
+/// Returns the group chat between the caller and `participants`, creating it
+/// if it does not exist yet. The same set of people always gets the same chat.
+#[post("/group-chat/create", format = "json", data = "<group_info>")]
+pub async fn create_group_chat(
+    group_info: Json<GroupChatRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<(Status, Json<String>)> {
+    let info = group_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+
+    let mut participants = info.participants.clone();
+    participants.push(info.username.clone());
+    participants.sort();
+    participants.dedup();
+    if participants.len() < MIN_GROUP_CHAT_SIZE {
+        return Err(ApiError::Unprocessable(String::from(
+            "A group chat needs at least two other users. Use a private chat instead.",
+        )));
+    }
+    if participants.len() > MAX_GROUP_CHAT_SIZE {
+        return Err(ApiError::Unprocessable(format!(
+            "A group chat can have at most {} participants.",
+            MAX_GROUP_CHAT_SIZE
+        )));
+    }
+    for participant in &participants {
+        if db_manager.get_user(participant).await.is_none() {
+            return Err(ApiError::NotFound(format!(
+                "User '{}' does not exist.",
+                participant
+            )));
+        }
+    }
+
+    if let Some(chat_id) = db_manager
+        .get_group_chat_by_participants(&participants)
+        .await
+    {
+        return Ok((Status::Ok, Json(chat_id)));
+    }
+
+    match db_manager
+        .insert_group_chat(&participants, &info.username)
+        .await
+    {
+        Ok(chat_id) => {
+            println!("Group chat created between users {:?}", participants);
+            Ok((Status::Created, Json(chat_id)))
+        }
+        // Someone created the same group at the same time.
+        Err(e) if is_unique_violation(&e) => {
+            match db_manager
+                .get_group_chat_by_participants(&participants)
+                .await
+            {
+                Some(chat_id) => Ok((Status::Ok, Json(chat_id))),
+                None => Err(ApiError::Internal(String::from(
+                    "Failed to create the group chat.",
+                ))),
+            }
+        }
+        Err(e) => {
+            println!("Failed to create group chat {:?}: {}", participants, e);
+            Err(ApiError::Internal(String::from(
+                "Failed to create the group chat.",
+            )))
+        }
+    }
+}
+
+#[post("/group-chat/add", format = "json", data = "<member_info>")]
+pub async fn add_group_member(
+    member_info: Json<GroupMemberRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    let info = member_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    let group = require_group_member(db_manager.inner(), &info.chat_id, &info.username).await?;
+
+    let mut participants = group.participants();
+    if participants.contains(&info.target) {
+        return Err(ApiError::Conflict(format!(
+            "User '{}' is already in this group chat.",
+            info.target
+        )));
+    }
+    if participants.len() >= MAX_GROUP_CHAT_SIZE {
+        return Err(ApiError::Unprocessable(format!(
+            "A group chat can have at most {} participants.",
+            MAX_GROUP_CHAT_SIZE
+        )));
+    }
+    if db_manager.get_user(&info.target).await.is_none() {
+        return Err(ApiError::NotFound(format!(
+            "User '{}' does not exist.",
+            info.target
+        )));
+    }
+
+    participants.push(info.target.clone());
+    set_group_participants(db_manager.inner(), &info.chat_id, &participants).await?;
+    announce(
+        config.inner(),
+        &info.chat_id,
+        format!("{} added {} to the group.", info.username, info.target),
+        false,
+    )
+    .await;
+    Ok(Status::Ok)
+}
+
+#[post("/group-chat/remove", format = "json", data = "<member_info>")]
+pub async fn remove_group_member(
+    member_info: Json<GroupMemberRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    let info = member_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    let group = require_group_member(db_manager.inner(), &info.chat_id, &info.username).await?;
+
+    let mut participants = group.participants();
+    let Some(idx) = participants.iter().position(|p| *p == info.target) else {
+        return Err(ApiError::NotFound(format!(
+            "User '{}' is not in this group chat.",
+            info.target
+        )));
+    };
+    // Groups keep the size they need to be created with, so that a group
+    // never turns into a second private chat between the same two users.
+    if participants.len() <= MIN_GROUP_CHAT_SIZE {
+        return Err(ApiError::Unprocessable(format!(
+            "A group chat needs at least {} participants. Use a private chat instead.",
+            MIN_GROUP_CHAT_SIZE
+        )));
+    }
+
+    participants.remove(idx);
+    set_group_participants(db_manager.inner(), &info.chat_id, &participants).await?;
+    announce_removal(
+        config.inner(),
+        &info.chat_id,
+        format!("{} removed {} from the group.", info.username, info.target),
+        &info.target,
+    )
+    .await;
+    Ok(Status::Ok)
+}
+
+#[get("/group-chat/all")]
+pub async fn get_group_chats(
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<GroupChatResponse>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    match db_manager.get_group_chats(&user_info.username).await {
+        Some(groups) => Ok(Json(
+            groups
+                .iter()
+                .map(|group| GroupChatResponse {
+                    chat_id: group.chat_id.clone(),
+                    participants: group.participants(),
+                })
+                .collect(),
+        )),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve the group chats.",
+        ))),
+    }
+}
+
+async fn require_group_member(
+    db_manager: &DbManager,
+    chat_id: &str,
+    username: &String,
+) -> Result<GroupChat, ApiError> {
+    let group = db_manager
+        .get_group_chat(chat_id)
+        .await
+        .ok_or_else(|| ApiError::NotFound(format!("Group chat '{}' does not exist.", chat_id)))?;
+    if group.participants().contains(username) {
+        Ok(group)
+    } else {
+        Err(ApiError::Forbidden(String::from(
+            "You are not in this group chat.",
+        )))
+    }
+}
+
+async fn set_group_participants(
+    db_manager: &DbManager,
+    chat_id: &str,
+    participants: &[String],
+) -> Result<(), ApiError> {
+    match db_manager
+        .set_group_participants(chat_id, participants)
+        .await
+    {
+        Ok(()) => Ok(()),
+        Err(e) if is_unique_violation(&e) => Err(ApiError::Conflict(String::from(
+            "A group chat with these participants already exists.",
+        ))),
+        Err(e) => {
+            println!("Failed to update group chat '{}': {}", chat_id, e);
+            Err(ApiError::Internal(String::from(
+                "Failed to update the group chat.",
+            )))
+        }
     }
 }
 
 #[post("/chat-room/create", format = "json", data = "<chat_room_info>")]
-pub async fn create_chat_room<'a>(
+pub async fn create_chat_room(
     chat_room_info: Json<ChatRoomRequest>,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, Json<String>) {
-    let valid_session: bool = is_session_id_valid(
+) -> ApiResult<(Status, Json<String>)> {
+    require_session(
         &chat_room_info.username,
         &chat_room_info.session_id,
         db_manager.inner(),
     )
-    .await;
+    .await?;
+
+    let mut errors = Vec::new();
+    if let Err(e) = validate_room_name(&chat_room_info.room_name) {
+        errors.push(FieldError::new("room_name", e));
+    }
+    let visibility = match chat_room_info.visibility.as_deref() {
+        None => Some(RoomVisibility::Public),
+        Some(visibility) => RoomVisibility::parse(visibility),
+    };
+    match (visibility, &chat_room_info.password) {
+        (None, _) => errors.push(FieldError::new(
+            "visibility",
+            String::from("Visibility must be one of 'public', 'private' or 'password'."),
+        )),
+        (Some(RoomVisibility::Password), Some(password)) => {
+            if let Err(e) = validate_room_password(password) {
+                errors.push(FieldError::new("password", e));
+            }
+        }
+        (Some(RoomVisibility::Password), None) => errors.push(FieldError::new(
+            "password",
+            String::from("A password-protected room needs a password."),
+        )),
+        (Some(_), Some(_)) => errors.push(FieldError::new(
+            "password",
+            String::from("Only password-protected rooms can have a password."),
+        )),
+        (Some(_), None) => {}
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
 
-    if !valid_session {
-        return (Status::Unauthorized, Json(String::new()));
+    if db_manager
+        .get_chat_room_by_name(&chat_room_info.room_name)
+        .await
+        .is_some()
+    {
+        return Err(ApiError::Conflict(format!(
+            "A chat room named '{}' already exists.",
+            chat_room_info.room_name
+        )));
     }
 
     let res = db_manager
-        .insert_chat_room(&chat_room_info.room_name)
+        .insert_chat_room(
+            &chat_room_info.room_name,
+            &chat_room_info.username,
+            visibility.unwrap_or(RoomVisibility::Public),
+            chat_room_info.password.as_ref(),
+        )
         .await;
     if let Some(chat_room_id) = res {
         println!("Chat room '{}' created", chat_room_info.room_name);
-        (Status::Created, Json(chat_room_id))
+        Ok((Status::Created, Json(chat_room_id)))
     } else {
         println!("Failed to create chat room '{}'.", chat_room_info.room_name);
-        (Status::InternalServerError, Json(String::new()))
+        Err(ApiError::Internal(format!(
+            "Failed to create chat room '{}'.",
+            chat_room_info.room_name
+        )))
     }
 }
 
-#[post("/chat-room/join", format = "json", data = "<chat_room_info>")]
-pub async fn join_chat_room<'a>(
-    chat_room_info: Json<ChatRoomRequest>,
+#[post("/chat-room/join", format = "json", data = "<join_info>")]
+pub async fn join_chat_room(
+    join_info: Json<ChatRoomJoinRequest>,
     db_manager: &rocket::State<DbManager>,
-) -> Status {
-    let valid_session: bool = is_session_id_valid(
-        &chat_room_info.username,
-        &chat_room_info.session_id,
+) -> ApiResult<Status> {
+    require_session(
+        &join_info.username,
+        &join_info.session_id,
         db_manager.inner(),
     )
-    .await;
+    .await?;
+    let room = require_chat_room(db_manager.inner(), &join_info.room_id).await?;
+
+    if db_manager
+        .is_banned(&join_info.room_id, &join_info.username)
+        .await
+    {
+        return Err(ApiError::Forbidden(String::from(
+            "You are banned from this chat room.",
+        )));
+    }
+
+    if db_manager
+        .get_room_role(&join_info.room_id, &join_info.username)
+        .await
+        .is_some()
+    {
+        return Ok(Status::Ok);
+    }
 
-    if !valid_session {
-        return Status::Unauthorized;
+    match RoomVisibility::parse(&room.visibility) {
+        Some(RoomVisibility::Public) => {}
+        Some(RoomVisibility::Password) => {
+            if join_info.password.is_none() || join_info.password != room.password {
+                return Err(ApiError::Forbidden(String::from(
+                    "Incorrect password for this chat room.",
+                )));
+            }
+        }
+        // Private rooms can only be joined with a pending invite, which is
+        // accepted by joining.
+        Some(RoomVisibility::Private) | None => {
+            let status = db_manager
+                .get_room_invite_status(&join_info.room_id, &join_info.username)
+                .await;
+            if status.as_deref() != Some("pending") {
+                return Err(ApiError::Forbidden(String::from(
+                    "This chat room is private. You need an invite to join.",
+                )));
+            }
+            db_manager
+                .set_room_invite_status(&join_info.room_id, &join_info.username, "accepted")
+                .await;
+        }
     }
 
-    Status::Created
+    if db_manager
+        .insert_room_member(&join_info.room_id, &join_info.username, RoomRole::Member)
+        .await
+    {
+        println!(
+            "User '{}' joined chat room '{}'",
+            join_info.username, join_info.room_id
+        );
+        Ok(Status::Created)
+    } else {
+        Err(ApiError::Internal(String::from(
+            "Failed to join the chat room.",
+        )))
+    }
 }
 
-#[get("/private-chat/recipients")]
-pub async fn get_all_recipients(
+#[get("/chat-room/members?<room_id>")]
+pub async fn get_chat_room_members(
+    room_id: String,
     user_info: UserReqInfo,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, Json<Vec<String>>) {
-    let valid_session: bool = is_session_id_valid(
+) -> ApiResult<Json<Vec<RoomMemberResponse>>> {
+    require_session(
         &user_info.username,
         &user_info.session_id,
         db_manager.inner(),
     )
-    .await;
-    if !valid_session {
-        return (Status::Unauthorized, Json(vec![]));
+    .await?;
+    require_room_role(
+        db_manager.inner(),
+        &room_id,
+        &user_info.username,
+        RoomRole::Member,
+    )
+    .await?;
+
+    match db_manager.get_room_members(&room_id).await {
+        Some(members) => Ok(Json(
+            members
+                .into_iter()
+                .map(|member| RoomMemberResponse {
+                    username: member.username,
+                    role: member.role,
+                })
+                .collect(),
+        )),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve the chat room members.",
+        ))),
+    }
+}
+
+#[post("/chat-room/promote", format = "json", data = "<moderation_info>")]
+pub async fn promote_room_member(
+    moderation_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    let info = moderation_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Owner,
+    )
+    .await?;
+
+    match require_target_role(db_manager.inner(), &info.room_id, &info.target).await? {
+        RoomRole::Member => set_room_role(db_manager.inner(), &info, RoomRole::Moderator).await,
+        RoomRole::Moderator => Err(ApiError::Conflict(format!(
+            "User '{}' is already a moderator.",
+            info.target
+        ))),
+        RoomRole::Owner => Err(ApiError::Unprocessable(String::from(
+            "The owner cannot be promoted.",
+        ))),
     }
+}
 
-    match db_manager
-        .get_all_chat_recipients(&user_info.username)
+#[post("/chat-room/demote", format = "json", data = "<moderation_info>")]
+pub async fn demote_room_member(
+    moderation_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    let info = moderation_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Owner,
+    )
+    .await?;
+
+    match require_target_role(db_manager.inner(), &info.room_id, &info.target).await? {
+        RoomRole::Moderator => set_room_role(db_manager.inner(), &info, RoomRole::Member).await,
+        RoomRole::Member => Err(ApiError::Conflict(format!(
+            "User '{}' is not a moderator.",
+            info.target
+        ))),
+        RoomRole::Owner => Err(ApiError::Unprocessable(String::from(
+            "The owner cannot be demoted.",
+        ))),
+    }
+}
+
+#[post("/chat-room/kick", format = "json", data = "<moderation_info>")]
+pub async fn kick_room_member(
+    moderation_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    let info = moderation_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    let role = require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Moderator,
+    )
+    .await?;
+
+    let target_role = require_target_role(db_manager.inner(), &info.room_id, &info.target).await?;
+    if target_role >= role {
+        return Err(ApiError::Forbidden(format!(
+            "You cannot kick user '{}' from this chat room.",
+            info.target
+        )));
+    }
+
+    if db_manager
+        .remove_room_member(&info.room_id, &info.target)
         .await
     {
-        Some(partners) => (Status::Ok, Json(partners)),
-        None => (Status::Ok, Json(vec![])),
+        println!(
+            "User '{}' kicked '{}' from chat room '{}'",
+            info.username, info.target, info.room_id
+        );
+        announce_removal(
+            config.inner(),
+            &info.room_id,
+            format!(
+                "{} kicked {} from the chat room.",
+                info.username, info.target
+            ),
+            &info.target,
+        )
+        .await;
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to kick user '{}'.",
+            info.target
+        )))
     }
 }
 
-#[get("/chat-room/all")]
-pub async fn get_all_chat_rooms(
+#[post("/chat-room/ban", format = "json", data = "<moderation_info>")]
+pub async fn ban_room_member(
+    moderation_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    let info = moderation_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    let role = require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Moderator,
+    )
+    .await?;
+
+    if db_manager.get_user(&info.target).await.is_none() {
+        return Err(ApiError::NotFound(format!(
+            "User '{}' does not exist.",
+            info.target
+        )));
+    }
+    // Users that are not members yet can be banned pre-emptively.
+    if let Some(target_role) = db_manager.get_room_role(&info.room_id, &info.target).await {
+        if target_role >= role {
+            return Err(ApiError::Forbidden(format!(
+                "You cannot ban user '{}' from this chat room.",
+                info.target
+            )));
+        }
+    }
+
+    if db_manager
+        .insert_room_ban(&info.room_id, &info.target, &info.username)
+        .await
+        && db_manager
+            .remove_room_member(&info.room_id, &info.target)
+            .await
+    {
+        println!(
+            "User '{}' banned '{}' from chat room '{}'",
+            info.username, info.target, info.room_id
+        );
+        announce_removal(
+            config.inner(),
+            &info.room_id,
+            format!(
+                "{} banned {} from the chat room.",
+                info.username, info.target
+            ),
+            &info.target,
+        )
+        .await;
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to ban user '{}'.",
+            info.target
+        )))
+    }
+}
+
+#[post("/chat-room/unban", format = "json", data = "<moderation_info>")]
+pub async fn unban_room_member(
+    moderation_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    let info = moderation_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Moderator,
+    )
+    .await?;
+
+    if !db_manager.is_banned(&info.room_id, &info.target).await {
+        return Err(ApiError::Conflict(format!(
+            "User '{}' is not banned from this chat room.",
+            info.target
+        )));
+    }
+
+    if db_manager
+        .delete_room_ban(&info.room_id, &info.target)
+        .await
+    {
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to unban user '{}'.",
+            info.target
+        )))
+    }
+}
+
+#[get("/chat-room/info?<room_id>")]
+pub async fn get_chat_room_info(
+    room_id: String,
     user_info: UserReqInfo,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, Json<Vec<ChatRoomResponse>>) {
-    let valid_session: bool = is_session_id_valid(
+) -> ApiResult<Json<ChatRoomInfoResponse>> {
+    require_session(
         &user_info.username,
         &user_info.session_id,
         db_manager.inner(),
     )
-    .await;
-    if !valid_session {
-        return (Status::Unauthorized, Json(vec![]));
+    .await?;
+    let room = require_chat_room(db_manager.inner(), &room_id).await?;
+
+    // Private rooms are only visible to their members and invited users.
+    if room.visibility == RoomVisibility::Private.as_str()
+        && db_manager
+            .get_room_role(&room_id, &user_info.username)
+            .await
+            .is_none()
+        && db_manager
+            .get_room_invite_status(&room_id, &user_info.username)
+            .await
+            .as_deref()
+            != Some("pending")
+    {
+        return Err(ApiError::NotFound(format!(
+            "Chat room '{}' does not exist.",
+            room_id
+        )));
     }
 
-    match db_manager.get_all_chat_rooms().await {
-        Some(rooms) => {
-            let res: Vec<ChatRoomResponse> = rooms
-                .iter()
+    Ok(Json(ChatRoomInfoResponse {
+        room_id: room.chat_room_id,
+        name: room.name,
+        topic: room.topic,
+        description: room.description,
+        visibility: room.visibility,
+    }))
+}
+
+/// Finds the chat room the user means by `name`: its id, its exact name or an
+/// unambiguous prefix of its name, ignoring case. Only rooms the user can see
+/// are considered, so private rooms stay hidden from outsiders.
+#[get("/chat-room/resolve?<name>")]
+pub async fn resolve_chat_room(
+    name: String,
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<ChatRoomResponse>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    let rooms = db_manager
+        .get_all_chat_rooms(&user_info.username)
+        .await
+        .unwrap_or_default();
+    let wanted = name.to_lowercase();
+    let mut matches: Vec<ChatRoom> = Vec::new();
+    for room in rooms {
+        if room.chat_room_id == name || room.name.to_lowercase() == wanted {
+            matches = vec![room];
+            break;
+        }
+        if room.name.to_lowercase().starts_with(&wanted) {
+            matches.push(room);
+        }
+    }
+
+    match matches.len() {
+        0 => Err(ApiError::NotFound(format!(
+            "No chat room matches '{}'.",
+            name
+        ))),
+        1 => {
+            let room = matches.remove(0);
+            Ok(Json(ChatRoomResponse {
+                room_id: room.chat_room_id,
+                name: room.name,
+                topic: room.topic,
+                visibility: room.visibility,
+            }))
+        }
+        _ => Err(ApiError::Ambiguous {
+            message: format!("More than one chat room matches '{}'.", name),
+            candidates: matches
+                .iter()
+                .map(|room| json!({ "room_id": room.chat_room_id, "name": room.name }))
+                .collect(),
+        }),
+    }
+}
+
+#[post("/chat-room/rename", format = "json", data = "<rename_info>")]
+pub async fn rename_chat_room(
+    rename_info: Json<RoomRenameRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    let info = rename_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Owner,
+    )
+    .await?;
+
+    if let Err(e) = validate_room_name(&info.name) {
+        return Err(ApiError::Validation(vec![FieldError::new("name", e)]));
+    }
+    if let Some(room) = db_manager.get_chat_room_by_name(&info.name).await {
+        // Changing only the case of the room's own name is fine.
+        if room.chat_room_id != info.room_id {
+            return Err(ApiError::Conflict(format!(
+                "A chat room named '{}' already exists.",
+                info.name
+            )));
+        }
+    }
+
+    match db_manager.rename_chat_room(&info.room_id, &info.name).await {
+        Ok(()) => {
+            println!("Chat room '{}' renamed to '{}'", info.room_id, info.name);
+            announce(
+                config.inner(),
+                &info.room_id,
+                format!("The chat room was renamed to '{}'.", info.name),
+                false,
+            )
+            .await;
+            Ok(Status::Ok)
+        }
+        Err(e) if is_unique_violation(&e) => Err(ApiError::Conflict(format!(
+            "A chat room named '{}' already exists.",
+            info.name
+        ))),
+        Err(_) => Err(ApiError::Internal(String::from(
+            "Failed to rename the chat room.",
+        ))),
+    }
+}
+
+#[post("/chat-room/details", format = "json", data = "<details_info>")]
+pub async fn update_chat_room_details(
+    details_info: Json<RoomDetailsRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    let info = details_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Moderator,
+    )
+    .await?;
+    let room = require_chat_room(db_manager.inner(), &info.room_id).await?;
+
+    let mut errors = Vec::new();
+    if info.topic.is_none() && info.description.is_none() {
+        errors.push(FieldError::new(
+            "topic",
+            String::from("Give a topic, a description or both."),
+        ));
+    }
+    if let Some(Err(e)) = info.topic.as_deref().map(validate_room_topic) {
+        errors.push(FieldError::new("topic", e));
+    }
+    if let Some(Err(e)) = info.description.as_deref().map(validate_room_description) {
+        errors.push(FieldError::new("description", e));
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let topic = match &info.topic {
+        Some(topic) if topic.is_empty() => None,
+        Some(topic) => Some(topic.clone()),
+        None => room.topic.clone(),
+    };
+    let description = match &info.description {
+        Some(description) if description.is_empty() => None,
+        Some(description) => Some(description.clone()),
+        None => room.description,
+    };
+
+    if !db_manager
+        .set_chat_room_details(&info.room_id, topic.as_ref(), description.as_ref())
+        .await
+    {
+        return Err(ApiError::Internal(String::from(
+            "Failed to update the chat room.",
+        )));
+    }
+
+    if topic != room.topic {
+        let content = match &topic {
+            Some(topic) => format!("{} changed the topic to: {}", info.username, topic),
+            None => format!("{} cleared the topic.", info.username),
+        };
+        announce(config.inner(), &info.room_id, content, false).await;
+    }
+    Ok(Status::Ok)
+}
+
+#[post("/chat-room/delete", format = "json", data = "<delete_info>")]
+pub async fn delete_chat_room(
+    delete_info: Json<ChatRoomJoinRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    require_session(
+        &delete_info.username,
+        &delete_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+    require_room_role(
+        db_manager.inner(),
+        &delete_info.room_id,
+        &delete_info.username,
+        RoomRole::Owner,
+    )
+    .await?;
+
+    if db_manager.delete_chat_room(&delete_info.room_id).await {
+        println!(
+            "User '{}' deleted chat room '{}'",
+            delete_info.username, delete_info.room_id
+        );
+        announce(
+            config.inner(),
+            &delete_info.room_id,
+            String::from("This chat room has been deleted by its owner."),
+            true,
+        )
+        .await;
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(String::from(
+            "Failed to delete the chat room.",
+        )))
+    }
+}
+
+// Sends a system message to everyone in the room. The change has already been
+// made at this point, so a broker that cannot be reached is only logged.
+async fn announce(config: &ServerConfig, room_id: &str, content: String, close: bool) {
+    send_announcement(config, room_id, content, close, None).await;
+}
+
+// Announces that `removed` left the conversation against their will. The
+// broker also unsubscribes them, so they stop getting its messages.
+async fn announce_removal(config: &ServerConfig, chat_id: &str, content: String, removed: &str) {
+    send_announcement(config, chat_id, content, false, Some(removed)).await;
+}
+
+async fn send_announcement(
+    config: &ServerConfig,
+    room_id: &str,
+    content: String,
+    close: bool,
+    remove: Option<&str>,
+) {
+    let Some(token) = &config.pubsub_system_token else {
+        println!(
+            "PUBSUB_SYSTEM_TOKEN is not set, not announcing change to chat room '{}'",
+            room_id
+        );
+        return;
+    };
+    if let Err(e) = publish_system_message(
+        &config.pubsub_url,
+        config.pubsub_ca_path.as_deref(),
+        token,
+        room_id,
+        &content,
+        close,
+        remove,
+    )
+    .await
+    {
+        println!(
+            "Failed to announce change to chat room '{}': {}",
+            room_id, e
+        );
+    }
+}
+
+// Tells a user about an event wherever they are in the app. Like
+// announcements, notifications are best effort.
+async fn notify_user(
+    config: &ServerConfig,
+    recipient: &str,
+    kind: NotificationKind,
+    content: String,
+    topic: Option<&str>,
+) {
+    let Some(token) = &config.pubsub_system_token else {
+        return;
+    };
+    if let Err(e) = publish_notification(
+        &config.pubsub_url,
+        config.pubsub_ca_path.as_deref(),
+        token,
+        recipient,
+        kind,
+        &content,
+        topic,
+    )
+    .await
+    {
+        println!("Failed to notify user '{}': {}", recipient, e);
+    }
+}
+
+#[post("/chat-room/invite", format = "json", data = "<invite_info>")]
+pub async fn invite_to_chat_room(
+    invite_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<Status> {
+    let info = invite_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Member,
+    )
+    .await?;
+
+    if db_manager.get_user(&info.target).await.is_none() {
+        return Err(ApiError::NotFound(format!(
+            "User '{}' does not exist.",
+            info.target
+        )));
+    }
+    if db_manager
+        .get_room_role(&info.room_id, &info.target)
+        .await
+        .is_some()
+    {
+        return Err(ApiError::Conflict(format!(
+            "User '{}' is already a member of this chat room.",
+            info.target
+        )));
+    }
+    if db_manager.is_banned(&info.room_id, &info.target).await {
+        return Err(ApiError::Forbidden(format!(
+            "User '{}' is banned from this chat room.",
+            info.target
+        )));
+    }
+
+    if db_manager
+        .upsert_room_invite(&info.room_id, &info.target, &info.username)
+        .await
+    {
+        println!(
+            "User '{}' invited '{}' to chat room '{}'",
+            info.username, info.target, info.room_id
+        );
+        if let Some(room) = db_manager.get_chat_room(&info.room_id).await {
+            notify_user(
+                config.inner(),
+                &info.target,
+                NotificationKind::Invite,
+                format!("{} invited you to chat room {}.", info.username, room.name),
+                Some(&info.room_id),
+            )
+            .await;
+        }
+        Ok(Status::Created)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to invite user '{}'.",
+            info.target
+        )))
+    }
+}
+
+/// Hides the target's messages in the room from the caller only.
+#[post("/chat-room/mute", format = "json", data = "<mute_info>")]
+pub async fn mute_room_member(
+    mute_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    let info = mute_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_room_role(
+        db_manager.inner(),
+        &info.room_id,
+        &info.username,
+        RoomRole::Member,
+    )
+    .await?;
+
+    if info.username == info.target {
+        return Err(ApiError::Unprocessable(String::from(
+            "You cannot mute yourself.",
+        )));
+    }
+    if db_manager.get_user(&info.target).await.is_none() {
+        return Err(ApiError::NotFound(format!(
+            "User '{}' does not exist.",
+            info.target
+        )));
+    }
+
+    if db_manager
+        .insert_room_mute(&info.room_id, &info.username, &info.target)
+        .await
+    {
+        println!(
+            "User '{}' muted '{}' in chat room '{}'",
+            info.username, info.target, info.room_id
+        );
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to mute user '{}'.",
+            info.target
+        )))
+    }
+}
+
+#[post("/chat-room/unmute", format = "json", data = "<mute_info>")]
+pub async fn unmute_room_member(
+    mute_info: Json<RoomModerationRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    let info = mute_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+    require_chat_room(db_manager.inner(), &info.room_id).await?;
+
+    let muted = db_manager
+        .get_room_mutes(&info.room_id, &info.username)
+        .await
+        .unwrap_or_default();
+    if !muted.contains(&info.target) {
+        return Err(ApiError::Conflict(format!(
+            "User '{}' is not muted in this chat room.",
+            info.target
+        )));
+    }
+
+    if db_manager
+        .delete_room_mute(&info.room_id, &info.username, &info.target)
+        .await
+    {
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to unmute user '{}'.",
+            info.target
+        )))
+    }
+}
+
+/// The users the caller has muted in the room. Conversations other than chat
+/// rooms have no mutes, so they return an empty list.
+#[get("/chat-room/mutes?<room_id>")]
+pub async fn get_room_mutes(
+    room_id: String,
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<String>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    match db_manager
+        .get_room_mutes(&room_id, &user_info.username)
+        .await
+    {
+        Some(muted) => Ok(Json(muted)),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve muted users.",
+        ))),
+    }
+}
+
+#[get("/chat-room/invites")]
+pub async fn get_room_invites(
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<RoomInviteResponse>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    match db_manager.get_pending_invites(&user_info.username).await {
+        Some(invites) => Ok(Json(
+            invites
+                .into_iter()
+                .map(|invite| RoomInviteResponse {
+                    room_id: invite.chat_room_id,
+                    name: invite.name,
+                    inviter: invite.inviter,
+                })
+                .collect(),
+        )),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve the invites.",
+        ))),
+    }
+}
+
+#[post("/chat-room/invite/accept", format = "json", data = "<join_info>")]
+pub async fn accept_room_invite(
+    join_info: Json<ChatRoomJoinRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    require_session(
+        &join_info.username,
+        &join_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+    require_pending_invite(db_manager.inner(), &join_info).await?;
+
+    // The user may have joined the room on their own since being invited.
+    if db_manager
+        .get_room_role(&join_info.room_id, &join_info.username)
+        .await
+        .is_some()
+    {
+        db_manager
+            .set_room_invite_status(&join_info.room_id, &join_info.username, "accepted")
+            .await;
+        return Ok(Status::Ok);
+    }
+
+    if db_manager
+        .insert_room_member(&join_info.room_id, &join_info.username, RoomRole::Member)
+        .await
+        && db_manager
+            .set_room_invite_status(&join_info.room_id, &join_info.username, "accepted")
+            .await
+    {
+        println!(
+            "User '{}' accepted the invite to chat room '{}'",
+            join_info.username, join_info.room_id
+        );
+        Ok(Status::Created)
+    } else {
+        Err(ApiError::Internal(String::from(
+            "Failed to accept the invite.",
+        )))
+    }
+}
+
+#[post("/chat-room/invite/decline", format = "json", data = "<join_info>")]
+pub async fn decline_room_invite(
+    join_info: Json<ChatRoomJoinRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    require_session(
+        &join_info.username,
+        &join_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+    require_pending_invite(db_manager.inner(), &join_info).await?;
+
+    if db_manager
+        .set_room_invite_status(&join_info.room_id, &join_info.username, "declined")
+        .await
+    {
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(String::from(
+            "Failed to decline the invite.",
+        )))
+    }
+}
+
+async fn require_pending_invite(
+    db_manager: &DbManager,
+    join_info: &ChatRoomJoinRequest,
+) -> Result<(), ApiError> {
+    require_chat_room(db_manager, &join_info.room_id).await?;
+    if db_manager
+        .is_banned(&join_info.room_id, &join_info.username)
+        .await
+    {
+        return Err(ApiError::Forbidden(String::from(
+            "You are banned from this chat room.",
+        )));
+    }
+    match db_manager
+        .get_room_invite_status(&join_info.room_id, &join_info.username)
+        .await
+        .as_deref()
+    {
+        Some("pending") => Ok(()),
+        _ => Err(ApiError::NotFound(format!(
+            "You have no pending invite to chat room '{}'.",
+            join_info.room_id
+        ))),
+    }
+}
+
+async fn require_chat_room(db_manager: &DbManager, room_id: &str) -> Result<ChatRoom, ApiError> {
+    db_manager
+        .get_chat_room(room_id)
+        .await
+        .ok_or_else(|| ApiError::NotFound(format!("Chat room '{}' does not exist.", room_id)))
+}
+
+// Checks that the user has at least `min_role` in the room and returns its actual role.
+async fn require_room_role(
+    db_manager: &DbManager,
+    room_id: &str,
+    username: &str,
+    min_role: RoomRole,
+) -> Result<RoomRole, ApiError> {
+    require_chat_room(db_manager, room_id).await?;
+    match db_manager.get_room_role(room_id, username).await {
+        Some(role) if role >= min_role => Ok(role),
+        Some(_) => Err(ApiError::Forbidden(format!(
+            "Only a room {} or above can do this.",
+            min_role.as_str()
+        ))),
+        None => Err(ApiError::Forbidden(String::from(
+            "You are not a member of this chat room.",
+        ))),
+    }
+}
+
+async fn require_target_role(
+    db_manager: &DbManager,
+    room_id: &str,
+    target: &str,
+) -> Result<RoomRole, ApiError> {
+    db_manager
+        .get_room_role(room_id, target)
+        .await
+        .ok_or_else(|| {
+            ApiError::NotFound(format!(
+                "User '{}' is not a member of this chat room.",
+                target
+            ))
+        })
+}
+
+async fn set_room_role(
+    db_manager: &DbManager,
+    info: &RoomModerationRequest,
+    role: RoomRole,
+) -> ApiResult<Status> {
+    if db_manager
+        .set_room_role(&info.room_id, &info.target, role)
+        .await
+    {
+        println!(
+            "User '{}' is now a {} of chat room '{}'",
+            info.target,
+            role.as_str(),
+            info.room_id
+        );
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to change the role of user '{}'.",
+            info.target
+        )))
+    }
+}
+
+#[get("/private-chat/recipients")]
+pub async fn get_all_recipients(
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<String>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    match db_manager
+        .get_all_chat_recipients(&user_info.username)
+        .await
+    {
+        Some(partners) => Ok(Json(partners)),
+        None => Ok(Json(vec![])),
+    }
+}
+
+#[get("/chat-room/all")]
+pub async fn get_all_chat_rooms(
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<ChatRoomResponse>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    match db_manager.get_all_chat_rooms(&user_info.username).await {
+        Some(rooms) => {
+            let res: Vec<ChatRoomResponse> = rooms
+                .into_iter()
                 .map(|room| ChatRoomResponse {
-                    room_id: room.0.clone(),
-                    name: room.1.clone(),
+                    room_id: room.chat_room_id,
+                    name: room.name,
+                    topic: room.topic,
+                    visibility: room.visibility,
                 })
                 .collect();
-            (Status::Ok, Json(res))
+            Ok(Json(res))
         }
-        None => (Status::Ok, Json(vec![])),
+        None => Ok(Json(vec![])),
+    }
+}
+
+/// Searches the caller's conversations by text, sender and date range.
+#[get("/search?<q>&<sender>&<since>&<until>&<limit>")]
+pub async fn search_messages(
+    q: Option<String>,
+    sender: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<u32>,
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<SearchHitResponse>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    let mut errors = Vec::new();
+    let text = match q.as_deref().map(full_text_query) {
+        Some(None) => {
+            errors.push(FieldError::new(
+                "q",
+                format!(
+                    "Search text needs a word of at least {} letters or digits.",
+                    MIN_SEARCH_WORD_LENGTH
+                ),
+            ));
+            None
+        }
+        Some(Some(text)) => Some(text),
+        None => None,
+    };
+    for (field, date) in [("since", &since), ("until", &until)] {
+        if let Some(Err(e)) = date.as_deref().map(validate_date) {
+            errors.push(FieldError::new(field, e));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+    if text.is_none() && sender.is_none() && since.is_none() && until.is_none() {
+        return Err(ApiError::Unprocessable(String::from(
+            "Give search text, a sender or a date range.",
+        )));
+    }
+
+    let search = MessageSearch {
+        text: text.as_deref(),
+        sender: sender.as_deref(),
+        since: since.as_deref(),
+        until: until.as_deref(),
+        limit: limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT),
+    };
+    match db_manager
+        .search_messages(&user_info.username, &search)
+        .await
+    {
+        Some(hits) => Ok(Json(
+            hits.into_iter()
+                .map(|hit| SearchHitResponse {
+                    chat_id: hit.chat_id,
+                    kind: hit.kind,
+                    name: hit.name,
+                    sender: hit.sender,
+                    message: hit.message,
+                    sent_at: hit.sent_at,
+                })
+                .collect(),
+        )),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to search messages.",
+        ))),
+    }
+}
+
+// Turns free text into a boolean-mode query that requires every word, or a
+// word starting with it. Operators in the text are dropped. Returns None when
+// no word is long enough to be indexed.
+fn full_text_query(text: &str) -> Option<String> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_SEARCH_WORD_LENGTH)
+        .map(|word| format!("+{}*", word))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Json,
+    Markdown,
+    Text,
+}
+
+impl ExportFormat {
+    fn parse(format: &str) -> Option<ExportFormat> {
+        match format {
+            "json" => Some(ExportFormat::Json),
+            "md" => Some(ExportFormat::Markdown),
+            "txt" => Some(ExportFormat::Text),
+            _ => None,
+        }
+    }
+
+    fn content_type(&self) -> ContentType {
+        match self {
+            ExportFormat::Json => ContentType::JSON,
+            ExportFormat::Markdown => ContentType::new("text", "markdown"),
+            ExportFormat::Text => ContentType::Plain,
+        }
+    }
+
+    // A JSON export is a single object: {"conversation": {...}, "messages": [...]}.
+    fn header(&self, conversation: &ExportConversation) -> String {
+        let members = match conversation.kind.as_str() {
+            "room" => Some(
+                conversation
+                    .participants
+                    .iter()
+                    .map(|p| format!("{} ({})", p.username, p.role.as_deref().unwrap_or("member")))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
+            _ => None,
+        };
+        match self {
+            ExportFormat::Json => format!(
+                "{{\"conversation\":{},\"messages\":[",
+                json::to_string(conversation).unwrap_or_default()
+            ),
+            ExportFormat::Markdown => match members {
+                Some(members) => format!("# {}\n\nMembers: {}\n\n", conversation.title(), members),
+                None => format!("# {}\n\n", conversation.title()),
+            },
+            ExportFormat::Text => match members {
+                Some(members) => format!("{}\nMembers: {}\n\n", conversation.title(), members),
+                None => format!("{}\n\n", conversation.title()),
+            },
+        }
+    }
+
+    fn message(&self, message: ExportedMessage, first: bool) -> String {
+        match self {
+            ExportFormat::Json => {
+                let message = ExportMessage {
+                    message_id: message.message_id,
+                    sender: message.sender,
+                    sent_at: message.sent_at,
+                    message: message.message,
+                };
+                format!(
+                    "{}\n{}",
+                    if first { "" } else { "," },
+                    json::to_string(&message).unwrap_or_default()
+                )
+            }
+            ExportFormat::Markdown => format!(
+                "**{}** ({}): {}\n\n",
+                message.sender, message.sent_at, message.message
+            ),
+            ExportFormat::Text => format!(
+                "[{}] {}: {}\n",
+                message.sent_at, message.sender, message.message
+            ),
+        }
+    }
+
+    fn footer(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "\n]}\n",
+            ExportFormat::Markdown | ExportFormat::Text => "",
+        }
+    }
+}
+
+impl ExportConversation {
+    fn title(&self) -> String {
+        let usernames: Vec<&str> = self
+            .participants
+            .iter()
+            .map(|p| p.username.as_str())
+            .collect();
+        match self.kind.as_str() {
+            "private" => format!("Private chat between {}", usernames.join(" and ")),
+            "group" => format!("Group chat with {}", usernames.join(", ")),
+            _ => format!(
+                "Chat room '{}'",
+                self.name.as_deref().unwrap_or(&self.chat_id)
+            ),
+        }
+    }
+}
+
+/// Streams the full history of one of the caller's conversations as JSON,
+/// Markdown or a plain-text transcript (the default). `target` is the other
+/// user of a private chat, the id of a group chat, or the id or name of a chat
+/// room; `since` (YYYY-MM-DD) leaves out older messages.
+#[get("/export?<target>&<format>&<since>")]
+pub async fn export_conversation<'r>(
+    target: String,
+    format: Option<String>,
+    since: Option<String>,
+    user_info: UserReqInfo,
+    db_manager: &'r rocket::State<DbManager>,
+) -> ApiResult<(
+    ContentType,
+    TextStream<impl Stream<Item = String> + Send + 'r>,
+)> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    let mut errors = Vec::new();
+    let format = match format.as_deref().map(ExportFormat::parse) {
+        Some(Some(format)) => format,
+        Some(None) => {
+            errors.push(FieldError::new(
+                "format",
+                String::from("Format must be json, md or txt."),
+            ));
+            ExportFormat::Text
+        }
+        None => ExportFormat::Text,
+    };
+    if let Some(Err(e)) = since.as_deref().map(validate_date) {
+        errors.push(FieldError::new("since", e));
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let conversation =
+        resolve_export_target(db_manager.inner(), &user_info.username, &target).await?;
+    println!(
+        "User '{}' is exporting conversation '{}'",
+        user_info.username, conversation.chat_id
+    );
+
+    let stream = TextStream! {
+        yield format.header(&conversation);
+        let mut messages = db_manager.stream_messages(&conversation.chat_id, since.as_deref());
+        let mut first = true;
+        let mut failed = false;
+        while let Some(result) = messages.next().await {
+            match result {
+                Ok(message) => {
+                    yield format.message(message, first);
+                    first = false;
+                }
+                // The response has started, so the status cannot change. The
+                // marker, and the missing footer, keep the file from passing
+                // for a complete export.
+                Err(e) => {
+                    println!("Failed to export conversation '{}': {}", conversation.chat_id, e);
+                    yield String::from(EXPORT_FAILED_MARKER);
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        if !failed {
+            yield format.footer().to_string();
+        }
+    };
+    Ok((format.content_type(), stream))
+}
+
+// Finds the conversation named by an export target among the user's own.
+// A private chat with a user wins over a chat room of the same name.
+async fn resolve_export_target(
+    db_manager: &DbManager,
+    username: &String,
+    target: &str,
+) -> Result<ExportConversation, ApiError> {
+    let participant = |username: String, role: Option<String>| ExportParticipant { username, role };
+
+    if let Some(chat_id) = db_manager.get_chat_id(username, target).await {
+        let mut usernames = vec![username.clone(), String::from(target)];
+        usernames.sort();
+        return Ok(ExportConversation {
+            chat_id,
+            kind: String::from("private"),
+            name: None,
+            participants: usernames
+                .into_iter()
+                .map(|u| participant(u, None))
+                .collect(),
+        });
+    }
+
+    if db_manager.get_group_chat(target).await.is_some() {
+        let group = require_group_member(db_manager, target, username).await?;
+        return Ok(ExportConversation {
+            chat_id: group.chat_id.clone(),
+            kind: String::from("group"),
+            name: None,
+            participants: group
+                .participants()
+                .into_iter()
+                .map(|u| participant(u, None))
+                .collect(),
+        });
+    }
+
+    let room = match db_manager.get_chat_room(target).await {
+        Some(room) => room,
+        None => db_manager
+            .get_chat_room_by_name(target)
+            .await
+            .ok_or_else(|| ApiError::NotFound(format!("No conversation matches '{}'.", target)))?,
+    };
+    require_room_role(db_manager, &room.chat_room_id, username, RoomRole::Member).await?;
+    let members = db_manager
+        .get_room_members(&room.chat_room_id)
+        .await
+        .ok_or_else(|| {
+            ApiError::Internal(String::from("Failed to retrieve the chat room members."))
+        })?;
+    Ok(ExportConversation {
+        chat_id: room.chat_room_id,
+        kind: String::from("room"),
+        name: Some(room.name),
+        participants: members
+            .into_iter()
+            .map(|member| participant(member.username, Some(member.role)))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_text_query_requires_every_word_as_a_prefix() {
+        assert_eq!(
+            full_text_query("deploy friday").as_deref(),
+            Some("+deploy* +friday*")
+        );
+        assert_eq!(
+            full_text_query("café menü").as_deref(),
+            Some("+café* +menü*")
+        );
+    }
+
+    #[test]
+    fn full_text_query_strips_operators() {
+        for operator in ['+', '-', '<', '>', '(', ')', '~', '*', '"', '@'] {
+            let text = format!("{op}hello{op}world{op}", op = operator);
+            assert_eq!(
+                full_text_query(&text).as_deref(),
+                Some("+hello* +world*"),
+                "operator {:?}",
+                operator
+            );
+        }
+        assert_eq!(
+            full_text_query(r#"-"exact phrase" +(nested <group>) ~noise* @3"#).as_deref(),
+            Some("+exact* +phrase* +nested* +group* +noise*")
+        );
+    }
+
+    #[test]
+    fn full_text_query_needs_an_indexed_word() {
+        assert_eq!(full_text_query(""), None);
+        assert_eq!(full_text_query("+-<>()~*\"@"), None);
+        assert_eq!(full_text_query("a to be"), None);
+        assert_eq!(full_text_query("a to bee").as_deref(), Some("+bee*"));
     }
 }
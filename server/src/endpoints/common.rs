@@ -1,4 +1,5 @@
 use crate::database::DbManager;
+use crate::endpoints::error::ApiError;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::serde::{Deserialize, Serialize};
 
@@ -47,3 +48,29 @@ pub async fn is_session_id_valid(
     }
     is_valid
 }
+
+pub async fn require_session(
+    username: &String,
+    given_session_id: &String,
+    db_manager: &DbManager,
+) -> Result<(), ApiError> {
+    if is_session_id_valid(username, given_session_id, db_manager).await {
+        Ok(())
+    } else {
+        Err(ApiError::invalid_session())
+    }
+}
+
+pub async fn require_admin(
+    username: &String,
+    given_session_id: &String,
+    db_manager: &DbManager,
+) -> Result<(), ApiError> {
+    require_session(username, given_session_id, db_manager).await?;
+    match db_manager.get_user(username).await {
+        Some(user) if user.is_admin => Ok(()),
+        _ => Err(ApiError::Forbidden(String::from(
+            "This action is restricted to administrators.",
+        ))),
+    }
+}
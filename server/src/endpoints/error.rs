@@ -0,0 +1,143 @@
+use pubsub::validation::FieldError;
+use rocket::catch;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+
+/// Body returned by every endpoint when a request fails.
+#[derive(Deserialize, Serialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    pub details: Value,
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// A name matched more than one resource; `candidates` lists them.
+    Ambiguous {
+        message: String,
+        candidates: Value,
+    },
+    Unprocessable(String),
+    Validation(Vec<FieldError>),
+    TooManyRequests {
+        message: String,
+        retry_after_secs: u64,
+    },
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn invalid_session() -> ApiError {
+        ApiError::Unauthorized(String::from("Invalid username or session id."))
+    }
+
+    fn status(&self) -> Status {
+        match self {
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Unauthorized(_) => Status::Unauthorized,
+            ApiError::Forbidden(_) => Status::Forbidden,
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Conflict(_) | ApiError::Ambiguous { .. } => Status::Conflict,
+            ApiError::Unprocessable(_) | ApiError::Validation(_) => Status::UnprocessableEntity,
+            ApiError::TooManyRequests { .. } => Status::TooManyRequests,
+            ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "BAD_REQUEST",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::Forbidden(_) => "FORBIDDEN",
+            ApiError::NotFound(_) => "NOT_FOUND",
+            ApiError::Conflict(_) => "CONFLICT",
+            ApiError::Ambiguous { .. } => "AMBIGUOUS_NAME",
+            ApiError::Unprocessable(_) => "UNPROCESSABLE_ENTITY",
+            ApiError::Validation(_) => "VALIDATION_FAILED",
+            ApiError::TooManyRequests { .. } => "TOO_MANY_REQUESTS",
+            ApiError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::Unprocessable(msg)
+            | ApiError::Internal(msg)
+            | ApiError::Ambiguous { message: msg, .. }
+            | ApiError::TooManyRequests { message: msg, .. } => msg,
+            ApiError::Validation(_) => "One or more fields are invalid.",
+        }
+    }
+
+    fn details(&self) -> Value {
+        match self {
+            ApiError::Validation(errors) => json!({ "fields": errors }),
+            ApiError::Ambiguous { candidates, .. } => json!({ "candidates": candidates }),
+            ApiError::TooManyRequests {
+                retry_after_secs, ..
+            } => json!({ "retry_after_secs": retry_after_secs }),
+            _ => Value::Null,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: String::from(self.code()),
+            message: String::from(self.message()),
+            details: self.details(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = (self.status(), Json(self.body())).respond_to(request)?;
+        if let ApiError::TooManyRequests {
+            retry_after_secs, ..
+        } = self
+        {
+            response.set_raw_header("Retry-After", retry_after_secs.to_string());
+        }
+        Ok(response)
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+// Catchers so that requests rejected by Rocket itself (e.g. a malformed JSON
+// body or an unknown route) still return the same error body.
+#[catch(400)]
+pub fn bad_request() -> ApiError {
+    ApiError::BadRequest(String::from("The request could not be understood."))
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> ApiError {
+    ApiError::NotFound(format!("No route for '{}'.", request.uri()))
+}
+
+#[catch(422)]
+pub fn unprocessable_entity() -> ApiError {
+    ApiError::Unprocessable(String::from(
+        "The request body is missing fields or has fields of the wrong type.",
+    ))
+}
+
+#[catch(500)]
+pub fn internal_error() -> ApiError {
+    ApiError::Internal(String::from("Internal server error."))
+}
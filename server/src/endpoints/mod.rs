@@ -1,3 +1,5 @@
+pub mod admin;
+pub mod chat;
 pub mod common;
+pub mod error;
 pub mod user;
-pub mod chat;
@@ -1,11 +1,24 @@
-use crate::database::DbManager;
-use crate::endpoints::common::{is_session_id_valid, UserReqInfo};
+use crate::config::ServerConfig;
+use crate::database::{is_unique_violation, DbManager};
+use crate::endpoints::common::{require_session, UserReqInfo};
+use crate::endpoints::error::{ApiError, ApiResult};
+use crate::mail::Mailer;
+use crate::throttle::LoginThrottle;
+use pubsub::validation::{validate_password, validate_signup, FieldError};
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use rocket::{get, post};
+use std::net::IpAddr;
+use std::time::Duration;
 use uuid::Uuid;
 
+const PASSWORD_RESET_TTL_MINUTES: u32 = 30;
+const VERIFICATION_CODE_TTL_MINUTES: u32 = 30;
+const MAX_VERIFICATION_ATTEMPTS: u32 = 5;
+const DEFAULT_MENTIONS_LIMIT: u32 = 20;
+const MAX_MENTIONS_LIMIT: u32 = 100;
+
 #[derive(Deserialize, Serialize)]
 pub struct SignupInfo {
     username: String,
@@ -13,12 +26,95 @@ pub struct SignupInfo {
     password: String,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct SignupResponse {
+    verification_required: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyInfo {
+    username: String,
+    code: String,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct UserLogin {
     username: String,
     password: String,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct LoginResponse {
+    message: String,
+    session_id: String,
+    /// Conversations with messages the user has not read yet. Only filled in
+    /// at login.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unread: Vec<UnreadSummary>,
+    /// Notifications sent while the user was not connected, oldest first.
+    /// Only filled in at login.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    notifications: Vec<NotificationSummary>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct NotificationSummary {
+    kind: String,
+    content: String,
+    chat_id: Option<String>,
+    created_at: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UnreadSummary {
+    chat_id: String,
+    kind: String,
+    name: String,
+    unread: i64,
+    senders: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MentionResponse {
+    chat_id: String,
+    room_name: Option<String>,
+    sender: String,
+    message: String,
+    created_at: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChangePasswordInfo {
+    username: String,
+    session_id: String,
+    old_password: String,
+    new_password: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyResendInfo {
+    username: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ResetRequestInfo {
+    username: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ResetPasswordInfo {
+    username: String,
+    token: String,
+    new_password: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BlockRequest {
+    username: String,
+    session_id: String,
+    target: String, // the user to block or unblock
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct UserStatus {
     username: String,
@@ -26,79 +122,450 @@ pub struct UserStatus {
 }
 
 #[post("/signup", format = "json", data = "<signup_info>")]
-pub async fn signup<'a>(
+pub async fn signup(
     signup_info: Json<SignupInfo>,
     db_manager: &rocket::State<DbManager>,
-) -> Status {
-    let success: bool = db_manager
+    mailer: &rocket::State<Mailer>,
+    config: &rocket::State<ServerConfig>,
+) -> ApiResult<(Status, Json<SignupResponse>)> {
+    let errors = validate_signup(
+        &signup_info.username,
+        &signup_info.email,
+        &signup_info.password,
+    );
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    if db_manager.get_user(&signup_info.username).await.is_some() {
+        return Err(ApiError::Conflict(format!(
+            "Username '{}' is already taken.",
+            signup_info.username
+        )));
+    }
+    if db_manager
+        .get_user_by_email(&signup_info.email)
+        .await
+        .is_some()
+    {
+        return Err(ApiError::Conflict(format!(
+            "Email '{}' is already registered.",
+            signup_info.email
+        )));
+    }
+
+    let verification_code = if config.require_email_verification {
+        Some(new_verification_code())
+    } else {
+        None
+    };
+
+    match db_manager
         .insert_user(
             &signup_info.username,
             &signup_info.email,
             &signup_info.password,
+            verification_code.as_ref(),
+            VERIFICATION_CODE_TTL_MINUTES,
         )
-        .await;
-    if success {
-        println!("User created");
-        Status::Created
+        .await
+    {
+        Ok(()) => println!("User created"),
+        Err(e) if is_unique_violation(&e) => {
+            return Err(ApiError::Conflict(String::from(
+                "Username or email is already taken.",
+            )));
+        }
+        Err(_) => {
+            println!("Failed to create user {}", signup_info.username);
+            return Err(ApiError::Internal(format!(
+                "Failed to create user '{}'.",
+                signup_info.username
+            )));
+        }
+    }
+
+    if let Some(code) = &verification_code {
+        mailer.send(
+            &signup_info.email,
+            "Verify your chat app account",
+            format!(
+                "Your verification code for user '{}' is {}. It expires in {} minutes.",
+                signup_info.username, code, VERIFICATION_CODE_TTL_MINUTES
+            ),
+        );
+    }
+
+    Ok((
+        Status::Created,
+        Json(SignupResponse {
+            verification_required: verification_code.is_some(),
+        }),
+    ))
+}
+
+fn new_verification_code() -> String {
+    format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000)
+}
+
+#[post("/verify", format = "json", data = "<verify_info>")]
+pub async fn verify(
+    verify_info: Json<VerifyInfo>,
+    db_manager: &rocket::State<DbManager>,
+    throttle: &rocket::State<LoginThrottle>,
+) -> ApiResult<Status> {
+    let keys = vec![LoginThrottle::verify_key(&verify_info.username)];
+    if let Some(wait) = throttle.remaining_lockout(&keys) {
+        return Err(too_many_attempts(wait));
+    }
+
+    let user = db_manager
+        .get_user(&verify_info.username)
+        .await
+        .ok_or_else(|| {
+            ApiError::NotFound(format!("User '{}' does not exist.", verify_info.username))
+        })?;
+
+    if user.verified {
+        return Err(ApiError::Conflict(format!(
+            "User '{}' is already verified.",
+            verify_info.username
+        )));
+    }
+    if user.verification_code.is_none() {
+        return Err(ApiError::Unprocessable(String::from(
+            "No verification code is pending, request a new one.",
+        )));
+    }
+    if !db_manager
+        .is_verification_code_valid(&verify_info.username, &verify_info.code)
+        .await
+    {
+        throttle.record_failure(&keys);
+        // Too many wrong guesses invalidate the code, so a six digit code
+        // cannot be brute-forced even across lockouts.
+        let pending = db_manager
+            .record_verification_failure(&verify_info.username, MAX_VERIFICATION_ATTEMPTS)
+            .await;
+        return Err(ApiError::Unprocessable(String::from(if pending {
+            "Invalid or expired verification code."
+        } else {
+            "Too many invalid verification codes, request a new one."
+        })));
+    }
+
+    if db_manager.set_user_verified(&verify_info.username).await {
+        throttle.record_success(&keys);
+        println!("User {} verified", verify_info.username);
+        Ok(Status::Ok)
     } else {
-        println!("Failed to create user {}", signup_info.username);
-        Status::InternalServerError
+        Err(ApiError::Internal(format!(
+            "Failed to verify user '{}'.",
+            verify_info.username
+        )))
+    }
+}
+
+#[post("/verify/resend", format = "json", data = "<resend_info>")]
+pub async fn resend_verification_code(
+    resend_info: Json<VerifyResendInfo>,
+    db_manager: &rocket::State<DbManager>,
+    mailer: &rocket::State<Mailer>,
+) -> ApiResult<Status> {
+    // Like password reset requests, answer the same way whether or not the
+    // user exists and is awaiting verification.
+    let user = match db_manager.get_user(&resend_info.username).await {
+        Some(user) if !user.verified => user,
+        _ => return Ok(Status::Accepted),
+    };
+    let email = match &user.email {
+        Some(email) => email,
+        None => return Ok(Status::Accepted),
+    };
+
+    let code = new_verification_code();
+    if !db_manager
+        .set_verification_code(&user.username, &code, VERIFICATION_CODE_TTL_MINUTES)
+        .await
+    {
+        return Err(ApiError::Internal(String::from(
+            "Failed to create a verification code.",
+        )));
     }
+
+    mailer.send(
+        email,
+        "Verify your chat app account",
+        format!(
+            "Your new verification code for user '{}' is {}. It expires in {} minutes.",
+            user.username, code, VERIFICATION_CODE_TTL_MINUTES
+        ),
+    );
+    Ok(Status::Accepted)
 }
 
 #[post("/login", format = "json", data = "<user_login>")]
 pub async fn login(
     user_login: Json<UserLogin>,
+    ip: Option<IpAddr>,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, String) {
-    match db_manager.get_user(&user_login.username).await {
-        Some(user) => {
-            if user.password != user_login.password {
-                (
-                    Status::Unauthorized,
-                    String::from("{\"message\": \"Login Failed\"}"),
+    config: &rocket::State<ServerConfig>,
+    throttle: &rocket::State<LoginThrottle>,
+) -> ApiResult<Json<LoginResponse>> {
+    let ip_address = ip.map(|ip| ip.to_string());
+    let mut keys = vec![LoginThrottle::user_key(&user_login.username)];
+    if let Some(ip_address) = &ip_address {
+        keys.push(LoginThrottle::ip_key(ip_address));
+    }
+
+    if let Some(lockout) = throttle.check_lockout(&keys) {
+        // Logged once per lockout, so that retrying cannot fill the table.
+        if lockout.first_rejection {
+            db_manager
+                .insert_login_attempt(&user_login.username, ip_address.as_ref(), "LOCKED_OUT")
+                .await;
+        }
+        return Err(too_many_attempts(lockout.wait));
+    }
+
+    let user = match db_manager.get_user(&user_login.username).await {
+        Some(user) if user.password == user_login.password => user,
+        _ => {
+            println!("Failed login for user {}", user_login.username);
+            db_manager
+                .insert_login_attempt(
+                    &user_login.username,
+                    ip_address.as_ref(),
+                    "INVALID_CREDENTIALS",
                 )
-            } else {
-                let session_id: String = Uuid::new_v4().to_string();
-                let success = db_manager
-                    .set_user_session_id(&user_login.username, Some(&session_id))
-                    .await;
-                if success {
-                    let response_body = format!(
-                        "{{\"message\": \"Success\", \"session_id\": \"{}\"}}",
-                        session_id
-                    );
-                    (Status::Ok, response_body)
-                } else {
-                    (
-                        Status::InternalServerError,
-                        String::from("{\"message\": \"Login Failed\"}"),
-                    )
-                }
-            }
+                .await;
+            return Err(match throttle.record_failure(&keys) {
+                Some(wait) => too_many_attempts(wait),
+                None => ApiError::Unauthorized(String::from("Invalid username or password.")),
+            });
         }
-        None => (
-            Status::Unauthorized,
-            String::from("{\"message\": \"Login Failed\"}"),
+    };
+    throttle.record_success(&keys);
+
+    if config.require_email_verification && !user.verified {
+        return Err(ApiError::Forbidden(format!(
+            "The email address of user '{}' has not been verified yet.",
+            user_login.username
+        )));
+    }
+
+    let session_id: String = Uuid::new_v4().to_string();
+    let success = db_manager
+        .set_user_session_id(&user_login.username, Some(&session_id))
+        .await;
+    if !success {
+        return Err(ApiError::Internal(String::from(
+            "Failed to create a session.",
+        )));
+    }
+
+    // A failed summary should not keep the user from logging in.
+    let unread = db_manager
+        .get_unread_conversations(&user_login.username)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|conversation| UnreadSummary {
+            chat_id: conversation.chat_id,
+            kind: conversation.kind,
+            name: conversation.name,
+            unread: conversation.unread,
+            senders: conversation.senders.split(',').map(String::from).collect(),
+        })
+        .collect();
+    let notifications = db_manager
+        .take_pending_notifications(&user_login.username)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|notification| NotificationSummary {
+            kind: notification.kind,
+            content: notification.content,
+            chat_id: notification.chat_id,
+            created_at: notification.created_at,
+        })
+        .collect();
+    Ok(Json(LoginResponse {
+        message: String::from("Success"),
+        session_id,
+        unread,
+        notifications,
+    }))
+}
+
+fn too_many_attempts(wait: Duration) -> ApiError {
+    // Round up so that clients never retry before the lockout is over.
+    let retry_after_secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    ApiError::TooManyRequests {
+        message: format!(
+            "Too many failed login attempts. Try again in {} seconds.",
+            retry_after_secs
         ),
+        retry_after_secs,
     }
 }
 
 #[post("/logout", format = "json", data = "<user>")]
-pub async fn logout(user: Json<UserReqInfo>, db_manager: &rocket::State<DbManager>) -> Status {
-    let valid_session: bool =
-        is_session_id_valid(&user.username, &user.session_id, db_manager.inner()).await;
-
-    if valid_session {
-        let success = db_manager.set_user_session_id(&user.username, None).await;
-        if success {
-            Status::Ok
-        } else {
-            Status::InternalServerError
-        }
+pub async fn logout(
+    user: Json<UserReqInfo>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    require_session(&user.username, &user.session_id, db_manager.inner()).await?;
+
+    let success = db_manager.set_user_session_id(&user.username, None).await;
+    if success {
+        Ok(Status::Ok)
     } else {
-        Status::Unauthorized
+        Err(ApiError::Internal(String::from(
+            "Failed to end the session.",
+        )))
+    }
+}
+
+#[post("/password/change", format = "json", data = "<change_info>")]
+pub async fn change_password(
+    change_info: Json<ChangePasswordInfo>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<LoginResponse>> {
+    require_session(
+        &change_info.username,
+        &change_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    let user = db_manager
+        .get_user(&change_info.username)
+        .await
+        .ok_or_else(ApiError::invalid_session)?;
+    if user.password != change_info.old_password {
+        return Err(ApiError::Forbidden(String::from(
+            "The current password is incorrect.",
+        )));
+    }
+    if let Err(e) = validate_password(&change_info.new_password) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "new_password",
+            e,
+        )]));
+    }
+
+    if !db_manager
+        .set_user_password(&change_info.username, &change_info.new_password)
+        .await
+    {
+        return Err(ApiError::Internal(String::from(
+            "Failed to change the password.",
+        )));
     }
+
+    // Issue a new session id so that any other session using the old one is logged out.
+    let session_id: String = Uuid::new_v4().to_string();
+    if !db_manager
+        .set_user_session_id(&change_info.username, Some(&session_id))
+        .await
+    {
+        return Err(ApiError::Internal(String::from(
+            "Password changed but failed to renew the session.",
+        )));
+    }
+
+    println!("Password changed for user {}", change_info.username);
+    Ok(Json(LoginResponse {
+        message: String::from("Success"),
+        session_id,
+        unread: Vec::new(),
+        notifications: Vec::new(),
+    }))
+}
+
+#[post("/password/reset-request", format = "json", data = "<reset_info>")]
+pub async fn request_password_reset(
+    reset_info: Json<ResetRequestInfo>,
+    db_manager: &rocket::State<DbManager>,
+    mailer: &rocket::State<Mailer>,
+) -> ApiResult<Status> {
+    // Always answer the same way so the endpoint cannot be used to find out
+    // which usernames exist.
+    let user = match db_manager.get_user(&reset_info.username).await {
+        Some(user) => user,
+        None => return Ok(Status::Accepted),
+    };
+    let email = match &user.email {
+        Some(email) => email,
+        None => {
+            println!(
+                "No email on record for user {}, password reset skipped",
+                user.username
+            );
+            return Ok(Status::Accepted);
+        }
+    };
+
+    let token: String = Uuid::new_v4().to_string();
+    if !db_manager
+        .insert_password_reset(&user.username, &token, PASSWORD_RESET_TTL_MINUTES)
+        .await
+    {
+        return Err(ApiError::Internal(String::from(
+            "Failed to create a password reset token.",
+        )));
+    }
+
+    mailer.send(
+        email,
+        "Reset your chat app password",
+        format!(
+            "Your password reset token for user '{}' is {}. It expires in {} minutes.",
+            user.username, token, PASSWORD_RESET_TTL_MINUTES
+        ),
+    );
+    Ok(Status::Accepted)
+}
+
+#[post("/password/reset", format = "json", data = "<reset_info>")]
+pub async fn reset_password(
+    reset_info: Json<ResetPasswordInfo>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    if !db_manager
+        .is_password_reset_valid(&reset_info.username, &reset_info.token)
+        .await
+    {
+        return Err(ApiError::Unprocessable(String::from(
+            "Invalid or expired password reset token.",
+        )));
+    }
+    if let Err(e) = validate_password(&reset_info.new_password) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "new_password",
+            e,
+        )]));
+    }
+
+    if !db_manager
+        .set_user_password(&reset_info.username, &reset_info.new_password)
+        .await
+    {
+        return Err(ApiError::Internal(String::from(
+            "Failed to reset the password.",
+        )));
+    }
+    // The token is single use, and any existing session is ended.
+    db_manager
+        .delete_password_resets(&reset_info.username)
+        .await;
+    db_manager
+        .set_user_session_id(&reset_info.username, None)
+        .await;
+
+    println!("Password reset for user {}", reset_info.username);
+    Ok(Status::Ok)
 }
 
 #[get("/status?<username..>")]
@@ -106,24 +573,23 @@ pub async fn user_status(
     username: String,
     user_info: UserReqInfo,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, String) {
-    let valid_session: bool = is_session_id_valid(
+) -> ApiResult<String> {
+    require_session(
         &user_info.username,
         &user_info.session_id,
         db_manager.inner(),
     )
-    .await;
-
-    if valid_session {
-        match db_manager.get_user(&username).await {
-            Some(user) => match user.session_id {
-                Some(_) => (Status::Ok, String::from("ACTIVE")),
-                None => (Status::Ok, String::from("INACTIVE")),
-            },
-            None => (Status::NotFound, String::from("NOT_FOUND")),
-        }
-    } else {
-        (Status::Unauthorized, String::from(""))
+    .await?;
+
+    match db_manager.get_user(&username).await {
+        Some(user) => match user.session_id {
+            Some(_) => Ok(String::from("ACTIVE")),
+            None => Ok(String::from("INACTIVE")),
+        },
+        None => Err(ApiError::NotFound(format!(
+            "User '{}' does not exist.",
+            username
+        ))),
     }
 }
 
@@ -131,37 +597,153 @@ pub async fn user_status(
 pub async fn all_users(
     user_info: UserReqInfo,
     db_manager: &rocket::State<DbManager>,
-) -> (Status, Json<Vec<UserStatus>>) {
-    let valid_session: bool = is_session_id_valid(
+) -> ApiResult<Json<Vec<UserStatus>>> {
+    require_session(
         &user_info.username,
         &user_info.session_id,
         db_manager.inner(),
     )
-    .await;
-
-    if valid_session {
-        match db_manager.get_all_users().await {
-            Some(users) => {
-                let res: Vec<UserStatus> = users
-                    .iter()
-                    .map(|user| {
-                        let status = if user.session_id.is_some() {
-                            "ACTIVE"
-                        } else {
-                            "INACTIVE"
-                        };
-
-                        UserStatus {
-                            username: user.username.clone(),
-                            status: status.to_string(),
-                        }
-                    })
-                    .collect();
-                (Status::Ok, Json(res))
-            }
-            None => (Status::Ok, Json(vec![])),
+    .await?;
+
+    match db_manager.get_all_users().await {
+        Some(users) => {
+            let res: Vec<UserStatus> = users
+                .iter()
+                .map(|user| {
+                    let status = if user.session_id.is_some() {
+                        "ACTIVE"
+                    } else {
+                        "INACTIVE"
+                    };
+
+                    UserStatus {
+                        username: user.username.clone(),
+                        status: status.to_string(),
+                    }
+                })
+                .collect();
+            Ok(Json(res))
         }
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve users.",
+        ))),
+    }
+}
+
+#[post("/block", format = "json", data = "<block_info>")]
+pub async fn block_user(
+    block_info: Json<BlockRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    let info = block_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+
+    if info.username == info.target {
+        return Err(ApiError::Unprocessable(String::from(
+            "You cannot block yourself.",
+        )));
+    }
+    if db_manager.get_user(&info.target).await.is_none() {
+        return Err(ApiError::NotFound(format!(
+            "User '{}' does not exist.",
+            info.target
+        )));
+    }
+
+    if db_manager
+        .insert_user_block(&info.username, &info.target)
+        .await
+    {
+        println!("User '{}' blocked '{}'", info.username, info.target);
+        Ok(Status::Ok)
+    } else {
+        Err(ApiError::Internal(format!(
+            "Failed to block user '{}'.",
+            info.target
+        )))
+    }
+}
+
+#[post("/unblock", format = "json", data = "<block_info>")]
+pub async fn unblock_user(
+    block_info: Json<BlockRequest>,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Status> {
+    let info = block_info.into_inner();
+    require_session(&info.username, &info.session_id, db_manager.inner()).await?;
+
+    if !db_manager.is_blocked(&info.username, &info.target).await {
+        return Err(ApiError::Conflict(format!(
+            "User '{}' is not blocked.",
+            info.target
+        )));
+    }
+
+    if db_manager
+        .delete_user_block(&info.username, &info.target)
+        .await
+    {
+        println!("User '{}' unblocked '{}'", info.username, info.target);
+        Ok(Status::Ok)
     } else {
-        (Status::Unauthorized, Json(vec![]))
+        Err(ApiError::Internal(format!(
+            "Failed to unblock user '{}'.",
+            info.target
+        )))
+    }
+}
+
+#[get("/blocked")]
+pub async fn blocked_users(
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<String>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    match db_manager.get_blocked_users(&user_info.username).await {
+        Some(users) => Ok(Json(users)),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve blocked users.",
+        ))),
+    }
+}
+
+#[get("/mentions?<limit>")]
+pub async fn mentions(
+    limit: Option<u32>,
+    user_info: UserReqInfo,
+    db_manager: &rocket::State<DbManager>,
+) -> ApiResult<Json<Vec<MentionResponse>>> {
+    require_session(
+        &user_info.username,
+        &user_info.session_id,
+        db_manager.inner(),
+    )
+    .await?;
+
+    let limit = limit
+        .unwrap_or(DEFAULT_MENTIONS_LIMIT)
+        .min(MAX_MENTIONS_LIMIT);
+    match db_manager.get_mentions(&user_info.username, limit).await {
+        Some(mentions) => Ok(Json(
+            mentions
+                .into_iter()
+                .map(|mention| MentionResponse {
+                    chat_id: mention.chat_id,
+                    room_name: mention.room_name,
+                    sender: mention.sender,
+                    message: mention.message,
+                    created_at: mention.created_at,
+                })
+                .collect(),
+        )),
+        None => Err(ApiError::Internal(String::from(
+            "Failed to retrieve mentions.",
+        ))),
     }
 }
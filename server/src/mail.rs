@@ -0,0 +1,91 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Destination for the emails sent by the server (e.g. verification codes).
+pub trait MailSink: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), String>;
+}
+
+/// Prints every mail to stdout. Used when no other sink is configured.
+pub struct LogMailSink;
+
+impl MailSink for LogMailSink {
+    fn send(&self, mail: &Mail) -> Result<(), String> {
+        println!(
+            "Mail to '{}' | Subject: {} | {}",
+            mail.to, mail.subject, mail.body
+        );
+        Ok(())
+    }
+}
+
+/// Appends every mail to a local file so it can be inspected while testing.
+pub struct FileMailSink {
+    path: String,
+}
+
+impl FileMailSink {
+    pub fn new(path: String) -> FileMailSink {
+        FileMailSink { path }
+    }
+}
+
+impl MailSink for FileMailSink {
+    fn send(&self, mail: &Mail) -> Result<(), String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open mail file '{}': {}", self.path, e))?;
+        writeln!(
+            file,
+            "To: {}\nSubject: {}\n\n{}\n---",
+            mail.to, mail.subject, mail.body
+        )
+        .map_err(|e| format!("Failed to write mail file '{}': {}", self.path, e))
+    }
+}
+
+pub struct Mailer {
+    sink: Box<dyn MailSink>,
+}
+
+impl Mailer {
+    pub fn new(sink: Box<dyn MailSink>) -> Mailer {
+        Mailer { sink }
+    }
+
+    /// Builds the mailer from `MAIL_SINK` ("log" or "file") and `MAIL_FILE_PATH`.
+    pub fn from_env() -> Mailer {
+        match env::var("MAIL_SINK").as_deref() {
+            Ok("file") => {
+                let path = env::var("MAIL_FILE_PATH").unwrap_or(String::from("mail.log"));
+                println!("Writing outgoing mail to '{}'", path);
+                Mailer::new(Box::new(FileMailSink::new(path)))
+            }
+            _ => Mailer::new(Box::new(LogMailSink)),
+        }
+    }
+
+    pub fn send(&self, to: &str, subject: &str, body: String) -> bool {
+        let mail = Mail {
+            to: String::from(to),
+            subject: String::from(subject),
+            body,
+        };
+        match self.sink.send(&mail) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Failed to send mail to '{}': {}", to, e);
+                false
+            }
+        }
+    }
+}
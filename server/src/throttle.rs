@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Keys come from unauthenticated requests, so their number is capped. Records
+// nobody has failed with for `max_lockout` are pruned at most this often.
+const MAX_RECORDS: usize = 100_000;
+// Records kept when the cap is hit, so that evicting is only needed again
+// after many more new keys.
+const RECORDS_AFTER_EVICTION: usize = MAX_RECORDS * 9 / 10;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+const IP_KEY_PREFIX: &str = "ip:";
+
+struct FailureRecord {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+    // Whether an attempt was already rejected during the current lockout.
+    lockout_rejected: bool,
+}
+
+/// An attempt rejected because one of its keys is locked out.
+pub struct Lockout {
+    pub wait: Duration,
+    /// Whether this is the first attempt rejected during the lockout, so that
+    /// rejections can be logged once per lockout instead of once per attempt.
+    pub first_rejection: bool,
+}
+
+struct Records {
+    by_key: HashMap<String, FailureRecord>,
+    last_pruned: Instant,
+}
+
+/// Tracks failed logins per key (username or IP address) and locks a key out
+/// once it reaches `max_failures`, doubling the lockout on every further failure.
+pub struct LoginThrottle {
+    max_failures: u32,
+    base_lockout: Duration,
+    max_lockout: Duration,
+    records: Mutex<Records>,
+}
+
+impl LoginThrottle {
+    pub fn new(max_failures: u32, base_lockout: Duration, max_lockout: Duration) -> LoginThrottle {
+        LoginThrottle {
+            max_failures,
+            base_lockout,
+            max_lockout,
+            records: Mutex::new(Records {
+                by_key: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn user_key(username: &str) -> String {
+        format!("user:{}", username)
+    }
+
+    pub fn verify_key(username: &str) -> String {
+        format!("verify:{}", username)
+    }
+
+    pub fn ip_key(ip: &str) -> String {
+        format!("{}{}", IP_KEY_PREFIX, ip)
+    }
+
+    /// Returns how long the caller has to wait if any of the keys is locked out.
+    pub fn remaining_lockout(&self, keys: &[String]) -> Option<Duration> {
+        self.remaining_lockout_at(keys, Instant::now())
+    }
+
+    /// Like `remaining_lockout`, for an attempt that is rejected if a key is
+    /// locked out.
+    pub fn check_lockout(&self, keys: &[String]) -> Option<Lockout> {
+        self.check_lockout_at(keys, Instant::now())
+    }
+
+    fn check_lockout_at(&self, keys: &[String], now: Instant) -> Option<Lockout> {
+        let mut records = self.records.lock().unwrap();
+        let mut lockout: Option<Lockout> = None;
+        for key in keys {
+            let Some(record) = records.by_key.get_mut(key) else {
+                continue;
+            };
+            let Some(locked_until) = record.locked_until.filter(|until| *until > now) else {
+                continue;
+            };
+            let first_rejection = !record.lockout_rejected;
+            record.lockout_rejected = true;
+            let lockout = lockout.get_or_insert(Lockout {
+                wait: Duration::ZERO,
+                first_rejection: false,
+            });
+            lockout.wait = lockout.wait.max(locked_until - now);
+            lockout.first_rejection |= first_rejection;
+        }
+        lockout
+    }
+
+    fn remaining_lockout_at(&self, keys: &[String], now: Instant) -> Option<Duration> {
+        let records = self.records.lock().unwrap();
+        keys.iter()
+            .filter_map(|key| records.by_key.get(key))
+            .filter_map(|record| record.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max()
+    }
+
+    /// Records a failed attempt for every key and returns the resulting lockout, if any.
+    pub fn record_failure(&self, keys: &[String]) -> Option<Duration> {
+        self.record_failure_at(keys, Instant::now())
+    }
+
+    fn record_failure_at(&self, keys: &[String], now: Instant) -> Option<Duration> {
+        let mut records = self.records.lock().unwrap();
+        self.prune(&mut records, now);
+        let mut lockout: Option<Duration> = None;
+        for key in keys {
+            if records.by_key.len() >= MAX_RECORDS && !records.by_key.contains_key(key) {
+                self.evict(&mut records, now);
+            }
+            let record = records.by_key.entry(key.clone()).or_insert(FailureRecord {
+                failures: 0,
+                last_failure: now,
+                locked_until: None,
+                lockout_rejected: false,
+            });
+            // Forget old failures once the key has been quiet for longer than the maximum lockout.
+            if now.saturating_duration_since(record.last_failure) > self.max_lockout {
+                record.failures = 0;
+            }
+            record.failures += 1;
+            record.last_failure = now;
+
+            if record.failures >= self.max_failures {
+                let exponent = (record.failures - self.max_failures).min(16);
+                let duration = self
+                    .base_lockout
+                    .saturating_mul(1 << exponent)
+                    .min(self.max_lockout);
+                record.locked_until = Some(now + duration);
+                record.lockout_rejected = false;
+                lockout = lockout.max(Some(duration));
+            }
+        }
+        lockout
+    }
+
+    /// Forgets the failures of a successful login. IP addresses can be shared
+    /// or try other accounts, so their failures are only halved.
+    pub fn record_success(&self, keys: &[String]) {
+        let mut records = self.records.lock().unwrap();
+        for key in keys {
+            if !key.starts_with(IP_KEY_PREFIX) {
+                records.by_key.remove(key);
+            } else if let Some(record) = records.by_key.get_mut(key) {
+                record.failures /= 2;
+                if record.failures == 0 {
+                    records.by_key.remove(key);
+                }
+            }
+        }
+    }
+
+    // Drops records that can no longer lock anyone out: not locked, and quiet
+    // for longer than the maximum lockout, after which failures are forgotten.
+    fn prune(&self, records: &mut Records, now: Instant) {
+        if now.saturating_duration_since(records.last_pruned) < PRUNE_INTERVAL {
+            return;
+        }
+        records
+            .by_key
+            .retain(|_, record| self.is_active(record, now));
+        records.last_pruned = now;
+    }
+
+    // Makes room for new keys once the cap is reached. Inactive records go
+    // first, then those that failed longest ago, down to
+    // `RECORDS_AFTER_EVICTION` so that this only runs once in many failures.
+    fn evict(&self, records: &mut Records, now: Instant) {
+        records
+            .by_key
+            .retain(|_, record| self.is_active(record, now));
+        records.last_pruned = now;
+        let excess = records.by_key.len().saturating_sub(RECORDS_AFTER_EVICTION);
+        if excess == 0 {
+            return;
+        }
+        let mut by_age: Vec<(Instant, String)> = records
+            .by_key
+            .iter()
+            .map(|(key, record)| (record.last_failure, key.clone()))
+            .collect();
+        by_age.select_nth_unstable(excess - 1);
+        for (_, key) in &by_age[..excess] {
+            records.by_key.remove(key);
+        }
+    }
+
+    fn is_active(&self, record: &FailureRecord, now: Instant) -> bool {
+        record
+            .locked_until
+            .is_some_and(|locked_until| locked_until > now)
+            || now.saturating_duration_since(record.last_failure) <= self.max_lockout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_secs(30);
+    const MAX: Duration = Duration::from_secs(300);
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(3, BASE, MAX)
+    }
+
+    fn keys() -> Vec<String> {
+        vec![
+            LoginThrottle::user_key("alice"),
+            LoginThrottle::ip_key("10.0.0.1"),
+        ]
+    }
+
+    fn failures(throttle: &LoginThrottle, key: &str) -> Option<u32> {
+        let records = throttle.records.lock().unwrap();
+        records.by_key.get(key).map(|record| record.failures)
+    }
+
+    #[test]
+    fn locks_out_after_max_failures_and_doubles() {
+        let throttle = throttle();
+        let now = Instant::now();
+        assert_eq!(throttle.record_failure_at(&keys(), now), None);
+        assert_eq!(throttle.record_failure_at(&keys(), now), None);
+        assert_eq!(throttle.record_failure_at(&keys(), now), Some(BASE));
+        assert_eq!(throttle.remaining_lockout_at(&keys(), now), Some(BASE));
+
+        assert_eq!(throttle.record_failure_at(&keys(), now), Some(BASE * 2));
+        assert_eq!(throttle.record_failure_at(&keys(), now), Some(BASE * 4));
+        for _ in 0..20 {
+            throttle.record_failure_at(&keys(), now);
+        }
+        assert_eq!(throttle.remaining_lockout_at(&keys(), now), Some(MAX));
+    }
+
+    #[test]
+    fn lockout_ends() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for _ in 0..3 {
+            throttle.record_failure_at(&keys(), now);
+        }
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            throttle.remaining_lockout_at(&keys(), later),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(throttle.remaining_lockout_at(&keys(), now + BASE), None);
+    }
+
+    #[test]
+    fn failures_are_forgotten_after_a_quiet_period() {
+        let throttle = throttle();
+        let now = Instant::now();
+        throttle.record_failure_at(&keys(), now);
+        throttle.record_failure_at(&keys(), now);
+
+        let later = now + MAX + Duration::from_secs(1);
+        assert_eq!(throttle.record_failure_at(&keys(), later), None);
+        assert_eq!(failures(&throttle, "user:alice"), Some(1));
+    }
+
+    #[test]
+    fn success_clears_the_user_and_halves_the_ip() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for _ in 0..2 {
+            throttle.record_failure_at(&keys(), now);
+        }
+        throttle.record_success(&keys());
+        assert_eq!(failures(&throttle, "user:alice"), None);
+        assert_eq!(failures(&throttle, "ip:10.0.0.1"), Some(1));
+
+        throttle.record_success(&keys());
+        assert_eq!(failures(&throttle, "ip:10.0.0.1"), None);
+    }
+
+    #[test]
+    fn keys_are_throttled_separately() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for _ in 0..3 {
+            throttle.record_failure_at(&keys(), now);
+        }
+        let other_user = vec![
+            LoginThrottle::user_key("bob"),
+            LoginThrottle::ip_key("10.0.0.2"),
+        ];
+        assert_eq!(throttle.remaining_lockout_at(&other_user, now), None);
+        let same_ip = vec![
+            LoginThrottle::user_key("bob"),
+            LoginThrottle::ip_key("10.0.0.1"),
+        ];
+        assert_eq!(throttle.remaining_lockout_at(&same_ip, now), Some(BASE));
+    }
+
+    #[test]
+    fn first_rejection_is_reported_once_per_lockout() {
+        let throttle = throttle();
+        let now = Instant::now();
+        assert!(throttle.check_lockout_at(&keys(), now).is_none());
+        for _ in 0..3 {
+            throttle.record_failure_at(&keys(), now);
+        }
+
+        let lockout = throttle.check_lockout_at(&keys(), now).unwrap();
+        assert_eq!(lockout.wait, BASE);
+        assert!(lockout.first_rejection);
+        assert!(
+            !throttle
+                .check_lockout_at(&keys(), now)
+                .unwrap()
+                .first_rejection
+        );
+
+        let later = now + BASE;
+        assert!(throttle.check_lockout_at(&keys(), later).is_none());
+        throttle.record_failure_at(&keys(), later);
+        assert!(
+            throttle
+                .check_lockout_at(&keys(), later)
+                .unwrap()
+                .first_rejection
+        );
+    }
+
+    #[test]
+    fn inactive_records_are_pruned() {
+        let throttle = throttle();
+        let now = Instant::now();
+        throttle.record_failure_at(&keys(), now);
+
+        let later = now + MAX + PRUNE_INTERVAL;
+        throttle.record_failure_at(&[LoginThrottle::user_key("bob")], later);
+        let records = throttle.records.lock().unwrap();
+        assert_eq!(records.by_key.len(), 1);
+        assert!(records.by_key.contains_key("user:bob"));
+    }
+
+    #[test]
+    fn oldest_records_are_evicted_in_bulk_at_the_cap() {
+        let throttle = throttle();
+        let start = Instant::now();
+        for i in 0..MAX_RECORDS {
+            let now = start + Duration::from_micros(i as u64);
+            throttle.record_failure_at(&[format!("ip:{}", i)], now);
+        }
+        assert_eq!(throttle.records.lock().unwrap().by_key.len(), MAX_RECORDS);
+
+        let now = start + Duration::from_secs(1);
+        throttle.record_failure_at(&[String::from("ip:new")], now);
+        let records = throttle.records.lock().unwrap();
+        assert_eq!(records.by_key.len(), RECORDS_AFTER_EVICTION + 1);
+        let evicted = MAX_RECORDS - RECORDS_AFTER_EVICTION;
+        assert!(!records.by_key.contains_key(&format!("ip:{}", evicted - 1)));
+        assert!(records.by_key.contains_key(&format!("ip:{}", evicted)));
+        assert!(records.by_key.contains_key("ip:new"));
+    }
+}